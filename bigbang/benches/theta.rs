@@ -1,19 +1,23 @@
-use bigbang::{collisions::soft_body, AsEntity, Entity, GravTree, Responsive, SimulationResult};
+use bigbang::{
+    collisions::soft_body, AsEntity, CalculateCollisions, CollisionDetection, Entity, Float,
+    GravTree, GravTree2, LeafKernel, Precision, Responsive, SimulationResult, Solver, TreeKind,
+    TreeUpdate,
+};
 use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
 use std::time;
 #[derive(Clone)]
 struct MyEntity {
-    x: f64,
-    y: f64,
-    z: f64,
-    vx: f64,
-    vy: f64,
-    vz: f64,
-    radius: f64,
+    x: Float,
+    y: Float,
+    z: Float,
+    vx: Float,
+    vy: Float,
+    vz: Float,
+    radius: Float,
 }
 impl AsEntity for MyEntity {
     fn as_entity(&self) -> Entity {
-        return Entity {
+        Entity {
             x: self.x,
             y: self.y,
             z: self.z,
@@ -22,18 +26,28 @@ impl AsEntity for MyEntity {
             vz: self.vz,
             radius: self.radius,
             mass: if self.radius < 1. { 0.5 } else { 105. },
-        };
+            softening_length: 0.,
+        }
+    }
+
+    fn set_entity(&mut self, entity: &Entity) {
+        self.vx = entity.vx;
+        self.vy = entity.vy;
+        self.vz = entity.vz;
+        self.x = entity.x;
+        self.y = entity.y;
+        self.z = entity.z;
     }
 }
 
 impl Responsive for MyEntity {
-    fn respond(&self, simulation_result: SimulationResult<MyEntity>, time_step: f64) -> Self {
+    fn respond(&self, simulation_result: SimulationResult<MyEntity>, time_step: Float) -> Self {
         let (mut ax, mut ay, mut az) = simulation_result.gravitational_acceleration;
         let (x, y, z) = (self.x, self.y, self.z);
         let (mut vx, mut vy, mut vz) = (self.vx, self.vy, self.vz);
         // calculate the collisions
-        for other in &simulation_result.collisions {
-            let (collision_ax, collision_ay, collision_az) = soft_body(self, other, 20f64);
+        for other in simulation_result.colliding_entities() {
+            let (collision_ax, collision_ay, collision_az) = soft_body(self, other, 20.);
             ax += collision_ax;
             ay += collision_ay;
             az += collision_az;
@@ -54,11 +68,11 @@ impl Responsive for MyEntity {
 }
 
 impl MyEntity {
-    pub fn new_entity(x: f64, y: f64, z: f64, radius: f64) -> MyEntity {
+    pub fn new_entity(x: Float, y: Float, z: Float, radius: Float) -> MyEntity {
         MyEntity {
-            vx: 0f64,
-            vy: 0f64,
-            vz: 0f64,
+            vx: 0.,
+            vy: 0.,
+            vz: 0.,
             x,
             y,
             z,
@@ -67,20 +81,88 @@ impl MyEntity {
     }
 }
 
+#[derive(Clone)]
+struct MyEntity2 {
+    x: Float,
+    y: Float,
+    vx: Float,
+    vy: Float,
+    radius: Float,
+}
+impl AsEntity<2> for MyEntity2 {
+    fn as_entity(&self) -> Entity {
+        Entity {
+            x: self.x,
+            y: self.y,
+            vx: self.vx,
+            vy: self.vy,
+            radius: self.radius,
+            mass: 0.5,
+            ..Entity::default()
+        }
+    }
+
+    fn set_entity(&mut self, entity: &Entity) {
+        self.x = entity.x;
+        self.y = entity.y;
+        self.vx = entity.vx;
+        self.vy = entity.vy;
+    }
+}
+
+impl Responsive for MyEntity2 {
+    fn respond(&self, simulation_result: SimulationResult<MyEntity2>, time_step: Float) -> Self {
+        let (ax, ay, _) = simulation_result.gravitational_acceleration;
+        let (vx, vy) = (self.vx + ax * time_step, self.vy + ay * time_step);
+        MyEntity2 {
+            vx,
+            vy,
+            x: self.x + vx,
+            y: self.y + vy,
+            radius: self.radius,
+        }
+    }
+}
+
 fn initialize_data(number_of_particles: usize) -> Vec<MyEntity> {
     let mut vec: Vec<MyEntity> = Vec::new();
     for x in 0..number_of_particles {
-        let entity = MyEntity::new_entity(x as f64, x as f64, x as f64, 10.);
+        let entity = MyEntity::new_entity(x as Float, x as Float, x as Float, 10.);
         vec.push(entity);
     }
     vec
 }
 
-fn initialize_tree(number_of_particles: usize, theta: f64) -> GravTree<MyEntity> {
+// Entities scattered through a cube with small velocities, so that the tree built for one time
+// step still fits them well on the next.
+fn initialize_cloud(number_of_particles: usize) -> Vec<MyEntity> {
+    let mut vec: Vec<MyEntity> = Vec::new();
+    for _ in 0..number_of_particles {
+        let mut entity = MyEntity::new_entity(
+            rand::random::<Float>() * 1000.,
+            rand::random::<Float>() * 1000.,
+            rand::random::<Float>() * 1000.,
+            0.1,
+        );
+        entity.vx = rand::random::<Float>() - 0.5;
+        entity.vy = rand::random::<Float>() - 0.5;
+        entity.vz = rand::random::<Float>() - 0.5;
+        vec.push(entity);
+    }
+    vec
+}
+
+fn initialize_tree(number_of_particles: usize, theta: Float) -> GravTree<MyEntity> {
     let max_entities = 3;
     let time_step = 0.2;
-    let mut data = initialize_data(number_of_particles);
-    GravTree::new(&mut data, time_step, max_entities, theta)
+    let data = initialize_data(number_of_particles);
+    GravTree::new(
+        &data,
+        time_step,
+        max_entities,
+        theta,
+        CalculateCollisions::Yes,
+    )
 }
 
 // Theta isn't used in tree construction so it isn't varied in the benches
@@ -89,14 +171,14 @@ fn tree_construction(c: &mut Criterion) {
     group.bench_function("n=125", |b| {
         b.iter_batched(
             || initialize_data(125),
-            |mut data| GravTree::new(&mut data, 0.2, 3, 0.2),
+            |data| GravTree::new(&data, 0.2, 3, 0.2, CalculateCollisions::Yes),
             BatchSize::SmallInput,
         )
     });
     group.bench_function("n=2000", |b| {
         b.iter_batched(
             || initialize_data(2000),
-            |mut data| GravTree::new(&mut data, 0.2, 3, 0.2),
+            |data| GravTree::new(&data, 0.2, 3, 0.2, CalculateCollisions::Yes),
             BatchSize::SmallInput,
         )
     });
@@ -104,12 +186,30 @@ fn tree_construction(c: &mut Criterion) {
     group.bench_function("n=20_000", |b| {
         b.iter_batched(
             || initialize_data(20_000),
-            |mut data| GravTree::new(&mut data, 0.2, 3, 0.2),
+            |data| GravTree::new(&data, 0.2, 3, 0.2, CalculateCollisions::Yes),
             BatchSize::SmallInput,
         )
     });
 }
 
+// Tree construction for a very large number of entities, where building the two halves of every
+// large node in parallel matters most
+
+fn tree_construction_1m(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tree construction: n=1_000_000");
+    group.sample_size(10);
+    let data = initialize_cloud(1_000_000);
+    group.bench_function("GravTree::new", |b| {
+        b.iter(|| GravTree::new(&data, 0.2, 3, 0.5, CalculateCollisions::No))
+    });
+    for kind in [TreeKind::Octree, TreeKind::Morton, TreeKind::Hilbert] {
+        group.bench_function(format!("set_tree_kind {:?}", kind), |b| {
+            let mut tree = GravTree::new(&data, 0.2, 3, 0.5, CalculateCollisions::No);
+            b.iter(|| tree.set_tree_kind(kind))
+        });
+    }
+}
+
 // Benching time stepping with a low number of entities - 125
 
 fn time_step_0125(c: &mut Criterion) {
@@ -214,11 +314,193 @@ fn time_step_20000(c: &mut Criterion) {
     });
 }
 
+// Comparing a time step which builds a new tree to one which updates the tree in place
+
+fn time_step_in_place(c: &mut Criterion) {
+    let mut group = c.benchmark_group("time step in place: n=2000");
+    group.bench_function("time_step", |b| {
+        b.iter_batched(
+            || initialize_tree(2000, 0.2),
+            |data| data.time_step(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("time_step_mut", |b| {
+        b.iter_batched(
+            || initialize_tree(2000, 0.2),
+            |mut data| {
+                data.time_step_mut();
+                data
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+// Comparing rebuilding the tree at every time step to refitting it. The same tree is stepped on
+// every iteration, so the refit tree's quality degrades as it would in a real simulation.
+
+fn refit_vs_rebuild(c: &mut Criterion) {
+    let mut group = c.benchmark_group("refit vs rebuild: n=20_000");
+    group.sample_size(20);
+    group.bench_function("rebuild", |b| {
+        let data = initialize_cloud(20_000);
+        let mut tree = GravTree::new(&data, 0.2, 3, 0.5, CalculateCollisions::No);
+        tree.set_tree_update(TreeUpdate::Rebuild);
+        b.iter(|| tree.time_step_mut())
+    });
+    group.bench_function("refit", |b| {
+        let data = initialize_cloud(20_000);
+        let mut tree = GravTree::new(&data, 0.2, 3, 0.5, CalculateCollisions::No);
+        tree.set_tree_update(TreeUpdate::Refit(0.1));
+        b.iter(|| tree.time_step_mut())
+    });
+}
+
+fn barnes_hut_vs_fast_multipole(c: &mut Criterion) {
+    let mut group = c.benchmark_group("barnes-hut vs fast multipole: n=20_000");
+    group.sample_size(20);
+    for solver in [
+        Solver::BarnesHut,
+        Solver::GroupedBarnesHut,
+        Solver::FastMultipole,
+    ] {
+        group.bench_function(format!("{:?}", solver), |b| {
+            let data = initialize_cloud(20_000);
+            let mut tree = GravTree::new(&data, 0.2, 3, 0.5, CalculateCollisions::No);
+            tree.set_solver(solver);
+            b.iter(|| tree.accelerations())
+        });
+    }
+}
+
+// Comparing how long it takes to build each kind of tree, and how quickly it is walked
+
+fn tree_kinds(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tree kinds: n=20_000");
+    group.sample_size(20);
+    let kinds = [
+        TreeKind::KdTree,
+        TreeKind::Octree,
+        TreeKind::Morton,
+        TreeKind::Hilbert,
+    ];
+    for kind in kinds {
+        group.bench_function(format!("build {:?}", kind), |b| {
+            let data = initialize_cloud(20_000);
+            let mut tree = GravTree::new(&data, 0.2, 3, 0.5, CalculateCollisions::No);
+            b.iter(|| tree.set_tree_kind(kind))
+        });
+    }
+    for kind in kinds {
+        group.bench_function(format!("walk {:?}", kind), |b| {
+            let data = initialize_cloud(20_000);
+            let mut tree = GravTree::new(&data, 0.2, 3, 0.5, CalculateCollisions::No);
+            tree.set_tree_kind(kind);
+            b.iter(|| tree.accelerations())
+        });
+    }
+}
+
+// Comparing the SIMD leaf kernel to the scalar one, with leaves large enough to fill the SIMD lanes
+
+fn leaf_kernels(c: &mut Criterion) {
+    let mut group = c.benchmark_group("leaf kernels: n=20_000, max_entities=16");
+    group.sample_size(20);
+    for kernel in [LeafKernel::Simd, LeafKernel::Scalar] {
+        group.bench_function(format!("{:?}", kernel), |b| {
+            let data = initialize_cloud(20_000);
+            let mut tree = GravTree::new(&data, 0.2, 16, 0.5, CalculateCollisions::No);
+            tree.set_leaf_kernel(kernel);
+            b.iter(|| tree.accelerations())
+        });
+    }
+}
+
+// Comparing summing the leaves in full precision to summing them in f32
+
+fn precisions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("precision: n=20_000, max_entities=32");
+    group.sample_size(20);
+    for precision in [Precision::Full, Precision::Mixed] {
+        group.bench_function(format!("{:?}", precision), |b| {
+            let data = initialize_cloud(20_000);
+            let mut tree = GravTree::new(&data, 0.2, 32, 0.5, CalculateCollisions::No);
+            tree.set_precision(precision);
+            b.iter(|| tree.accelerations())
+        });
+    }
+}
+
+// Comparing a two dimensional simulation faked in three dimensions, with every z at zero, to the
+// same simulation in the 2-d tree
+
+fn two_dimensions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("two dimensions: n=20_000");
+    group.sample_size(20);
+    let data = initialize_cloud(20_000)
+        .into_iter()
+        .map(|entity| MyEntity { z: 0., ..entity })
+        .collect::<Vec<_>>();
+    group.bench_function("GravTree", |b| {
+        let tree = GravTree::new(&data, 0.2, 3, 0.5, CalculateCollisions::Yes);
+        b.iter(|| tree.time_step())
+    });
+    let data = data
+        .into_iter()
+        .map(|entity| MyEntity2 {
+            x: entity.x,
+            y: entity.y,
+            vx: entity.vx,
+            vy: entity.vy,
+            radius: entity.radius,
+        })
+        .collect::<Vec<_>>();
+    group.bench_function("GravTree2", |b| {
+        let tree = GravTree2::new(&data, 0.2, 3, 0.5, CalculateCollisions::Yes);
+        b.iter(|| tree.time_step())
+    });
+}
+
+// Comparing the broad phase which finds every collision, with and without sweeping the entities
+// along their paths, to a time step with and without it
+
+fn collisions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("collisions: n=20_000");
+    group.sample_size(20);
+    let data = initialize_cloud(20_000);
+    for detection in [CollisionDetection::Discrete, CollisionDetection::Continuous] {
+        group.bench_function(format!("broad phase {:?}", detection), |b| {
+            let mut tree = GravTree::new(&data, 0.2, 3, 0.5, CalculateCollisions::No);
+            tree.set_collision_detection(detection);
+            b.iter(|| tree.collisions())
+        });
+    }
+    for (name, calculate_collisions) in [
+        ("time step without collisions", CalculateCollisions::No),
+        ("time step with collisions", CalculateCollisions::Yes),
+    ] {
+        group.bench_function(name, |b| {
+            let tree = GravTree::new(&data, 0.2, 3, 0.5, calculate_collisions);
+            b.iter(|| tree.time_step())
+        });
+    }
+}
+
 criterion_group!(
     benches,
     tree_construction,
+    tree_construction_1m,
     time_step_0125,
     time_step_2000,
     time_step_20000,
+    time_step_in_place,
+    refit_vs_rebuild,
+    barnes_hut_vs_fast_multipole,
+    tree_kinds,
+    leaf_kernels,
+    precisions,
+    two_dimensions,
+    collisions,
 );
 criterion_main!(benches);
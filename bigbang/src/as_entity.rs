@@ -5,8 +5,23 @@ use crate::entity::Entity;
 /// constructing an [[Entity]] from the type, and defining how to add acceleration to the velocity of your type.
 ///
 /// More generally, this entails that a type must contain, or be able to derive, its velocity, position,
-/// radius and mass, and it must be able to respond to acceleration impulses in the form of triples of `f64`s.
-pub trait AsEntity {
+/// radius and mass, and it must be able to respond to acceleration impulses in the form of triples of `Float`s.
+///
+/// `D` is the number of dimensions the type lives in, which is three unless stated otherwise. A
+/// type which implements `AsEntity<2>` is placed in a two dimensional [[GravTree]] and only moves
+/// in `x` and `y`, and one which implements `AsEntity<1>` only moves in `x`. The [[Entity]] it
+/// returns is three dimensional whatever `D` is, and its coordinates and velocities past the first
+/// `D` must be zero.
+pub trait AsEntity<const D: usize = 3> {
     /// Return an [[Entity]] representation of your struct.
     fn as_entity(&self) -> Entity;
+
+    /// Update the position and velocity of your struct to those of `entity`. This is only used by
+    /// the built-in [[Integrator]]s, which move entities on your behalf, and is implemented for you
+    /// by `#[derive(AsEntity)]`. The default implementation panics, so types which are only ever
+    /// stepped with [[Integrator::None]] do not need to implement it.
+    fn set_entity(&mut self, entity: &Entity) {
+        let _ = entity;
+        panic!("AsEntity::set_entity must be implemented to use a built-in integrator");
+    }
 }
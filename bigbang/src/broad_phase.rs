@@ -0,0 +1,152 @@
+//! Broad-phase collision detection, which finds every pair of colliding entities in the tree
+//! independently of the gravity walk.
+//!
+//! The walk only compares an entity against the entities in the leaves it opens, so it misses
+//! collisions with entities in nodes it approximates. Instead, the tree is walked against itself:
+//! two nodes can only hold a colliding pair if the distance between their bounds is no more than
+//! the sum of their largest radii, plus the distance their fastest entities could close in the
+//! time step, so every other pair of nodes is skipped without being opened. A node is paired with
+//! itself once, and with every other node at most once, so every colliding pair is found exactly
+//! once.
+use crate::as_entity::AsEntity;
+use crate::entity::{Entity, EntityId};
+use crate::simulation_result::ContactGeometry;
+use crate::utilities::join;
+use crate::Float;
+use crate::Node;
+
+/// Two colliding entities, each with its id, and how they collide.
+pub(crate) struct CollisionPair<'a, T> {
+    pub(crate) first: (EntityId, &'a T),
+    pub(crate) second: (EntityId, &'a T),
+    /// How the two entities touch, as seen from the first.
+    pub(crate) contact: ContactGeometry,
+}
+
+/// Returns every pair of entities under `root` which collide within the next `time_step`, as
+/// found by [contact_with](Entity::contact_with), each exactly once. With a `time_step` of
+/// zero, these are the pairs which overlap now.
+pub(crate) fn collision_pairs<T: AsEntity<D> + Sync, const D: usize>(
+    root: &Node<T, D>,
+    time_step: Float,
+) -> Vec<CollisionPair<'_, T>> {
+    root.left
+        .iter()
+        .flat_map(|node| pairs_within(node, time_step))
+        .collect()
+}
+
+/// Returns every colliding pair of entities which are both under `node`.
+fn pairs_within<T: AsEntity<D> + Sync, const D: usize>(
+    node: &Node<T, D>,
+    time_step: Float,
+) -> Vec<CollisionPair<'_, T>> {
+    let (left, right) = match (&node.left, &node.right) {
+        (Some(left), Some(right)) => (left, right),
+        _ => return leaf_pairs_within(node, time_step),
+    };
+    let (mut pairs, (right_pairs, between)) = join(
+        node.number_of_points,
+        || pairs_within(left, time_step),
+        || {
+            (
+                pairs_within(right, time_step),
+                pairs_between(left, right, time_step),
+            )
+        },
+    );
+    pairs.extend(right_pairs);
+    pairs.extend(between);
+    pairs
+}
+
+/// Returns every colliding pair of entities where one is under `a` and the other under `b`, which
+/// must be separate subtrees.
+fn pairs_between<'a, T: AsEntity<D> + Sync, const D: usize>(
+    a: &'a Node<T, D>,
+    b: &'a Node<T, D>,
+    time_step: Float,
+) -> Vec<CollisionPair<'a, T>> {
+    let reach = a.r_max + b.r_max + (a.v_max + b.v_max) * time_step;
+    if a.distance_between_bounds(b) > reach {
+        return Vec::new();
+    }
+    // The larger of the two nodes is opened, so that the pairs of nodes which are compared stay
+    // about the same size.
+    let (a, b) =
+        if a.points.is_some() || (b.points.is_none() && b.max_distance() > a.max_distance()) {
+            (b, a)
+        } else {
+            (a, b)
+        };
+    match (&a.left, &a.right) {
+        (Some(left), Some(right)) => {
+            let (mut pairs, right_pairs) = join(
+                a.number_of_points + b.number_of_points,
+                || pairs_between(left, b, time_step),
+                || pairs_between(right, b, time_step),
+            );
+            pairs.extend(right_pairs);
+            pairs
+        }
+        _ => leaf_pairs_between(a, b, time_step),
+    }
+}
+
+/// Returns every colliding pair of entities in the leaf `leaf`.
+fn leaf_pairs_within<T: AsEntity<D>, const D: usize>(
+    leaf: &Node<T, D>,
+    time_step: Float,
+) -> Vec<CollisionPair<'_, T>> {
+    let entries = leaf_entries(leaf);
+    let mut pairs = Vec::new();
+    for (index, first) in entries.iter().enumerate() {
+        for second in entries[index + 1..].iter() {
+            add_pair(first, second, time_step, &mut pairs);
+        }
+    }
+    pairs
+}
+
+/// Returns every colliding pair of entities where one is in the leaf `a` and the other in the
+/// leaf `b`.
+fn leaf_pairs_between<'a, T: AsEntity<D>, const D: usize>(
+    a: &'a Node<T, D>,
+    b: &'a Node<T, D>,
+    time_step: Float,
+) -> Vec<CollisionPair<'a, T>> {
+    let (a_entries, b_entries) = (leaf_entries(a), leaf_entries(b));
+    let mut pairs = Vec::new();
+    for first in a_entries.iter() {
+        for second in b_entries.iter() {
+            add_pair(first, second, time_step, &mut pairs);
+        }
+    }
+    pairs
+}
+
+/// Returns the id, point and entity of everything in the leaf `leaf`.
+fn leaf_entries<T: AsEntity<D>, const D: usize>(leaf: &Node<T, D>) -> Vec<(EntityId, &T, Entity)> {
+    let points = leaf.points.as_ref().expect("unexpected null node #14");
+    leaf.ids
+        .iter()
+        .zip(points.iter())
+        .map(|(id, x)| (*id, x, x.as_entity()))
+        .collect()
+}
+
+/// Appends `first` and `second` to `pairs` if they collide within the next `time_step`.
+fn add_pair<'a, T>(
+    first: &(EntityId, &'a T, Entity),
+    second: &(EntityId, &'a T, Entity),
+    time_step: Float,
+    pairs: &mut Vec<CollisionPair<'a, T>>,
+) {
+    if let Some(contact) = first.2.contact_with(&second.2, time_step) {
+        pairs.push(CollisionPair {
+            first: (first.0, first.1),
+            second: (second.0, second.1),
+            contact,
+        });
+    }
+}
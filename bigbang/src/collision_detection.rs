@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+/// Selects how [GravTree](crate::GravTree) decides which entities collide in a time step.
+///
+/// Checking whether entities overlap at the start of a time step misses entities which move
+/// further than their radii in a single step: two fast entities heading for each other can be
+/// apart at the start of one step and already past each other at the start of the next, and so
+/// pass straight through each other. [Continuous](CollisionDetection::Continuous) detection sweeps
+/// every entity along its path over the time step instead, which catches them.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CollisionDetection {
+    /// Entities collide if they overlap at the start of the time step. This is the default.
+    Discrete,
+    /// Entities collide if they overlap at any point during the time step, assuming that they
+    /// move in a straight line at their velocity at the start of it. Each collision is reported
+    /// as a [Contact](crate::Contact) at the time at which the entities first touch. The nodes of
+    /// the tree are grown by the distance their fastest entity could travel in the time step
+    /// when looking for collisions, so this becomes more expensive as entities speed up.
+    Continuous,
+}
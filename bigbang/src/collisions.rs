@@ -1,42 +1,268 @@
 //! This module contains functions to be used for collision calculations.
 //! You do not need to use these, they are provided merely for convenience.
 //! All of the functions follow the format of:
-//!  > Given two `T: AsEntity` `p1` and `p2` and some parameters, return the acceleration vector being
-//! > exerted from the collision
+//!  > Given two `T: AsEntity` `p1` and `p2` and some parameters, return the effect of the collision
+//! > on `p1`
+//!
+//! Contact forces, such as [soft_body] and [spring_dashpot], return the acceleration they exert on
+//! `p1`, to be added to its gravitational acceleration. Impulses, such as [restitution] and
+//! [elastic], act instantly, and return the change in the velocity of `p1` instead. Each entity in a
+//! collision receives the same force or impulse in opposite directions, so calling a function from
+//! both of them, as happens when each responds to the other, conserves momentum.
+//!
+//! The functions take the number of dimensions, `D`, which is usually inferred, so that they work
+//! with the entities of any tree. They still return triples, whose components past the first `D`
+//! are zero, since every entity is a three dimensional [Entity]. Entities are treated as spheres
+//! which only touch at the point where they overlap. The normal of a collision is the unit vector
+//! pointing from the center of `p2` towards the center of `p1`, and nothing happens if their
+//! centers coincide, as there is no such direction.
 
 use crate::as_entity::AsEntity;
+use crate::entity::Entity;
+use crate::Float;
 
 /// Uses [Hooke's law](https://en.wikipedia.org/wiki/Hooke%27s_law) exerting an outwards force
 /// proportional to the amount of overlap when two entities are overlapping.
 /// The argument `stiffness` refers to the stiffness coefficient applied to the overlapping value.
-pub fn soft_body<T>(p1: &T, p2: &T, stiffness: f64) -> (f64, f64, f64)
+/// Nothing is exerted if the entities do not overlap.
+pub fn soft_body<T, const D: usize>(p1: &T, p2: &T, stiffness: Float) -> (Float, Float, Float)
 where
-    T: AsEntity,
+    T: AsEntity<D>,
 {
     let p1 = p1.as_entity();
     let p2 = p2.as_entity();
-    // calculate the overlap of the two particles
-    let distance = p1.distance(&p2);
-    let radii_sum = p1.radius + p2.radius;
-    // if the distance is greater than the radii combined, then there actually was no collision and
-    // we can return early.
-    if distance >= radii_sum {
-        return (p1.vx, p1.vy, p1.vz);
+    let overlap = overlap(&p1, &p2);
+    // if the entities don't overlap, then there actually was no collision and we can return early.
+    if overlap <= 0. {
+        return (0., 0., 0.);
     }
-    let overlap = radii_sum - distance;
-    let force = stiffness * overlap;
-    let acceleration_scalar = force / p1.mass;
-    let (unit_x, unit_y, unit_z) = unit_vector(&p2.distance_vector(&p1));
+    scale(normal(&p1, &p2), stiffness * overlap / p1.mass)
+}
+
+/// Returns the change in the velocity of `p1` from an instantaneous impulse along the normal,
+/// which leaves the two entities separating at `coefficient` times the speed at which they
+/// approached. A `coefficient` of one is perfectly elastic and conserves kinetic energy, and one
+/// of zero is perfectly inelastic and stops them moving relative to each other along the normal.
+///
+/// This assumes that the entities are colliding, but does not check whether they overlap, so that
+/// it can respond to collisions found by [Continuous](crate::CollisionDetection::Continuous)
+/// detection before the entities reach each other. Nothing happens if they are already moving
+/// apart along the normal, so that entities which are still overlapping after one impulse are not
+/// pulled back together by the next. Motion along the surfaces of the entities is untouched: see
+/// [friction] for that.
+pub fn restitution<T, const D: usize>(p1: &T, p2: &T, coefficient: Float) -> (Float, Float, Float)
+where
+    T: AsEntity<D>,
+{
+    let p1 = p1.as_entity();
+    let p2 = p2.as_entity();
+    let normal = normal(&p1, &p2);
+    let normal_velocity = dot(relative_velocity(&p1, &p2), normal);
+    if normal_velocity >= 0. {
+        return (0., 0., 0.);
+    }
+    // The impulse which both entities receive, in opposite directions, is shared between them in
+    // inverse proportion to their masses.
+    let impulse = -(1. + coefficient) * normal_velocity / (1. / p1.mass + 1. / p2.mass);
+    scale(normal, impulse / p1.mass)
+}
+
+/// Returns the change in the velocity of `p1` from a perfectly elastic head-on collision with
+/// `p2`, in which `p1` leaves with `(m1 - m2) / (m1 + m2) * v1 + 2 * m2 / (m1 + m2) * v2`.
+///
+/// This is the one-dimensional exchange of velocities, applied to each axis separately, so it
+/// assumes that the entities are moving along the line between their centers. For glancing
+/// collisions it does not conserve kinetic energy, and [restitution] with a coefficient of one
+/// should be used instead. Like [restitution], it does not check whether the entities overlap, but
+/// unlike it, it acts whether or not they are approaching each other, so it should be applied only
+/// once per collision.
+pub fn elastic<T, const D: usize>(p1: &T, p2: &T) -> (Float, Float, Float)
+where
+    T: AsEntity<D>,
+{
+    let p1 = p1.as_entity();
+    let p2 = p2.as_entity();
+    let (vx, vy, vz) = relative_velocity(&p1, &p2);
+    scale((-vx, -vy, -vz), 2. * p2.mass / (p1.mass + p2.mass))
+}
+
+/// Returns the acceleration of `p1` from a linear spring-dashpot contact, which pushes the
+/// entities apart with a force of `stiffness * overlap` and damps their approach along the normal
+/// with a force of `damping` times their relative velocity along it. The damping dissipates
+/// energy, so that entities which collide come to rest against each other instead of bouncing off
+/// forever as they do with [soft_body].
+///
+/// The force is only exerted while the entities overlap, and it never pulls them together: the
+/// damping can only cancel out the spring while they separate, so they do not stick. The spring is
+/// stiff, so the time step must be a small fraction of `sqrt(mass / stiffness)` for the contact to
+/// be resolved over several steps rather than overshooting in one.
+pub fn spring_dashpot<T, const D: usize>(
+    p1: &T,
+    p2: &T,
+    stiffness: Float,
+    damping: Float,
+) -> (Float, Float, Float)
+where
+    T: AsEntity<D>,
+{
+    let p1 = p1.as_entity();
+    let p2 = p2.as_entity();
+    let overlap = overlap(&p1, &p2);
+    if overlap <= 0. {
+        return (0., 0., 0.);
+    }
+    let normal = normal(&p1, &p2);
+    let normal_velocity = dot(relative_velocity(&p1, &p2), normal);
+    let force = Float::max(0., stiffness * overlap - damping * normal_velocity);
+    scale(normal, force / p1.mass)
+}
+
+/// Returns the acceleration of `p1` from a [Hertzian](https://en.wikipedia.org/wiki/Contact_mechanics#Contact_between_two_spheres)
+/// spring-dashpot contact, which is a [spring_dashpot] whose force grows with the overlap to the
+/// power of 3/2, as it does between two elastic spheres, and whose damping grows with its square
+/// root. This makes the contact soft while the entities barely overlap and stiff as they are
+/// pressed together, which keeps the coefficient of restitution the same however fast they
+/// collide.
+///
+/// The same assumptions as for [spring_dashpot] apply. `stiffness` folds in the elastic moduli
+/// and radii of the entities, and must be chosen by the caller.
+pub fn hertzian<T, const D: usize>(
+    p1: &T,
+    p2: &T,
+    stiffness: Float,
+    damping: Float,
+) -> (Float, Float, Float)
+where
+    T: AsEntity<D>,
+{
+    let p1 = p1.as_entity();
+    let p2 = p2.as_entity();
+    let overlap = overlap(&p1, &p2);
+    if overlap <= 0. {
+        return (0., 0., 0.);
+    }
+    let normal = normal(&p1, &p2);
+    let normal_velocity = dot(relative_velocity(&p1, &p2), normal);
+    let root = Float::sqrt(overlap);
+    let force = Float::max(0., root * (stiffness * overlap - damping * normal_velocity));
+    scale(normal, force / p1.mass)
+}
+
+/// Returns the acceleration of `p1` from [Coulomb friction](https://en.wikipedia.org/wiki/Friction#Dry_friction)
+/// against `p2`, which opposes the entities sliding past each other with a force of `coefficient`
+/// times the `normal_force` pressing them together, such as the force of [spring_dashpot] times
+/// the mass of `p1`.
+///
+/// This assumes that the entities do not spin, see [friction_with_spin] otherwise. Only sliding
+/// friction is modelled: nothing happens once the entities stop sliding, and as the force does not
+/// shrink as they slow down, a time step which is too long can make them slide back the other way
+/// instead of stopping. Nothing happens if they do not overlap.
+pub fn friction<T, const D: usize>(
+    p1: &T,
+    p2: &T,
+    normal_force: Float,
+    coefficient: Float,
+) -> (Float, Float, Float)
+where
+    T: AsEntity<D>,
+{
+    friction_with_spin(
+        p1,
+        p2,
+        (0., 0., 0.),
+        (0., 0., 0.),
+        normal_force,
+        coefficient,
+    )
+    .0
+}
+
+/// Returns the acceleration and the angular acceleration of `p1` from [friction] against `p2`,
+/// where `spin_1` and `spin_2` are the angular velocities of `p1` and `p2` in radians per unit of
+/// time. Spinning changes how fast the surfaces of the entities slide past each other where they
+/// touch, and the friction there turns the entities as well as slowing them down.
+///
+/// The entities are assumed to be solid spheres of uniform density, with a moment of inertia of
+/// `2 / 5 * mass * radius²`. The friction acts at the point halfway through their overlap, so
+/// calling this from both entities conserves angular momentum as well as momentum. The same
+/// assumptions as for [friction] apply.
+pub fn friction_with_spin<T, const D: usize>(
+    p1: &T,
+    p2: &T,
+    spin_1: (Float, Float, Float),
+    spin_2: (Float, Float, Float),
+    normal_force: Float,
+    coefficient: Float,
+) -> ((Float, Float, Float), (Float, Float, Float))
+where
+    T: AsEntity<D>,
+{
+    let p1 = p1.as_entity();
+    let p2 = p2.as_entity();
+    let overlap = overlap(&p1, &p2);
+    if overlap <= 0. {
+        return ((0., 0., 0.), (0., 0., 0.));
+    }
+    let normal = normal(&p1, &p2);
+    // The contact point is the same distance into the overlap from either entity, so this is the
+    // arm from the center of p1 to it, and that from the center of p2 points the other way.
+    let arm = scale(normal, -(p1.radius - overlap / 2.));
+    let other_arm = scale(normal, p2.radius - overlap / 2.);
+    let (sx, sy, sz) = cross(spin_1, arm);
+    let (ox, oy, oz) = cross(spin_2, other_arm);
+    let (vx, vy, vz) = relative_velocity(&p1, &p2);
+    let velocity = (vx + sx - ox, vy + sy - oy, vz + sz - oz);
+    let normal_velocity = dot(velocity, normal);
+    let (tx, ty, tz) = (
+        velocity.0 - normal.0 * normal_velocity,
+        velocity.1 - normal.1 * normal_velocity,
+        velocity.2 - normal.2 * normal_velocity,
+    );
+    let sliding_speed = Float::sqrt(tx * tx + ty * ty + tz * tz);
+    if sliding_speed == 0. {
+        return ((0., 0., 0.), (0., 0., 0.));
+    }
+    let force = scale((tx, ty, tz), -coefficient * normal_force / sliding_speed);
+    let moment_of_inertia = 2. / 5. * p1.mass * p1.radius * p1.radius;
+    (
+        scale(force, 1. / p1.mass),
+        scale(cross(arm, force), 1. / moment_of_inertia),
+    )
+}
+
+/// Returns how far the two entities overlap, which is negative if they do not.
+fn overlap(p1: &Entity, p2: &Entity) -> Float {
+    p1.radius + p2.radius - p1.distance(p2)
+}
+
+/// Returns the unit vector pointing from the center of `p2` towards the center of `p1`, or zero if
+/// their centers coincide.
+fn normal(p1: &Entity, p2: &Entity) -> (Float, Float, Float) {
+    let (x, y, z) = p1.distance_vector(p2);
+    let length = Float::sqrt((x * x) + (y * y) + (z * z));
+    if length == 0. {
+        return (0., 0., 0.);
+    }
+    (-x / length, -y / length, -z / length)
+}
+
+/// Returns the velocity of `p1` relative to `p2`.
+fn relative_velocity(p1: &Entity, p2: &Entity) -> (Float, Float, Float) {
+    (p1.vx - p2.vx, p1.vy - p2.vy, p1.vz - p2.vz)
+}
+
+fn dot(a: (Float, Float, Float), b: (Float, Float, Float)) -> Float {
+    a.0 * b.0 + a.1 * b.1 + a.2 * b.2
+}
+
+fn cross(a: (Float, Float, Float), b: (Float, Float, Float)) -> (Float, Float, Float) {
     (
-        unit_x * acceleration_scalar,
-        unit_y * acceleration_scalar,
-        unit_z * acceleration_scalar,
+        a.1 * b.2 - a.2 * b.1,
+        a.2 * b.0 - a.0 * b.2,
+        a.0 * b.1 - a.1 * b.0,
     )
 }
 
-/// Utility function to turn a vector into a unit vector.
-fn unit_vector(vec: &(f64, f64, f64)) -> (f64, f64, f64) {
-    let (x, y, z) = vec;
-    let length = f64::sqrt((x * x) + (y * y) + (z * z));
-    (x / length, y / length, z / length)
+fn scale(vec: (Float, Float, Float), factor: Float) -> (Float, Float, Float) {
+    (vec.0 * factor, vec.1 * factor, vec.2 * factor)
 }
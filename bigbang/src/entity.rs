@@ -1,8 +1,13 @@
+use crate::Float;
 use either::{Either, Left, Right};
+use std::ops::Mul;
 
-use super::Dimension;
 use crate::as_entity::AsEntity;
-use crate::simulation_result::SimulationResult;
+use crate::kernel::{LeafKernel, Precision};
+use crate::multipole::MultipoleOrder;
+use crate::opening_criterion::OpeningCriterion;
+use crate::simulation_result::{Contact, ContactGeometry, SimulationResult};
+use crate::softening::Softening;
 use crate::Node;
 use serde::{Deserialize, Serialize};
 
@@ -12,33 +17,122 @@ pub enum CalculateCollisions {
     No,
 }
 
+/// The law used to turn the mass and distance of another body into an acceleration.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ForceLaw {
+    /// Newton's law of universal gravitation: `a = G * m * r̂ / r²`.
+    Newtonian,
+    /// The force law used by bigbang before the gravitational constant was introduced. The force
+    /// falls off as `1 / r` and the gravitational constant is ignored. This is physically
+    /// incorrect, but existing simulations were tuned against it.
+    Legacy,
+    /// Gravity in a truly two dimensional universe, where the potential of a body is
+    /// `G * m * ln(r)` and its acceleration falls off as `a = G * m * r̂ / r`. This is the natural
+    /// law for a [GravTree2](crate::GravTree2).
+    Logarithmic,
+    /// Gravity in a one dimensional universe, as in the sheet models of a self-gravitating slab:
+    /// every body pulls every other towards it with an acceleration of `a = G * m * r̂`, however far
+    /// apart they are. The mass of each body is then its mass per unit area, and `G` takes in the
+    /// usual factor of `2π`.
+    Sheet,
+}
+
+impl ForceLaw {
+    /// Returns the power of the distance `r` which the offset to another body is divided by, so
+    /// that the acceleration it exerts is `G * m * d / r^power`.
+    pub(crate) fn power(self) -> i32 {
+        match self {
+            ForceLaw::Newtonian => 3,
+            ForceLaw::Legacy | ForceLaw::Logarithmic => 2,
+            ForceLaw::Sheet => 1,
+        }
+    }
+
+    /// Returns `r` raised to the [power](ForceLaw::power) of this force law.
+    pub(crate) fn denominator<F: Copy + Mul<Output = F>>(self, r: F) -> F {
+        match self.power() {
+            3 => r * r * r,
+            2 => r * r,
+            _ => r,
+        }
+    }
+
+    /// Returns the gravitational constant this force law uses in place of `g`. Only the legacy
+    /// force law ignores it.
+    pub(crate) fn gravitational_constant(self, g: Float) -> Float {
+        match self {
+            ForceLaw::Legacy => 1.,
+            _ => g,
+        }
+    }
+}
+
+/// The parameters which control how the gravitational acceleration on an entity is calculated
+/// when walking the tree.
+#[derive(Clone, Copy)]
+pub(crate) struct ForceParameters<'a> {
+    pub(crate) theta: Float,
+    /// Decides which nodes are approximated.
+    pub(crate) opening_criterion: &'a dyn OpeningCriterion,
+    /// The magnitude of the acceleration on the entity being walked for at the end of the last
+    /// time step, if it is known.
+    pub(crate) previous_acceleration: Option<Float>,
+    pub(crate) gravitational_constant: Float,
+    pub(crate) force_law: ForceLaw,
+    pub(crate) softening: Softening,
+    /// Whether the jerk should be calculated alongside the acceleration.
+    pub(crate) calculate_jerk: bool,
+    /// How many terms of the multipole expansion to use for approximated nodes.
+    pub(crate) multipole_order: MultipoleOrder,
+    /// How the gravity of the entities in a leaf is summed.
+    pub(crate) leaf_kernel: LeafKernel,
+    /// The precision in which the gravity of the entities in a leaf is summed.
+    pub(crate) precision: Precision,
+}
+
+/// A stable identifier for an entity in a [GravTree](crate::GravTree). Every entity is given an id
+/// when it is added to the tree, and keeps it across time steps for as long as it stays in the
+/// tree. Ids are never reused within a tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct EntityId(pub u64);
+
 /// An Entity is an object (generalized to be spherical, having only a radius dimension) which has
 /// velocity, position, radius, and mass. This gravitational tree contains many entities and it moves
 /// them around according to the gravity they exert on each other.
 #[derive(Clone, Default)]
 #[repr(C)]
 pub struct Entity {
-    pub vx: f64,
-    pub vy: f64,
-    pub vz: f64,
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
-    pub radius: f64,
-    pub mass: f64,
+    pub vx: Float,
+    pub vy: Float,
+    pub vz: Float,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+    pub radius: Float,
+    pub mass: Float,
+    /// The gravitational softening length of this entity. This only takes effect when the tree
+    /// uses [Softening](crate::Softening), and may be left at zero to use the tree-wide length.
+    pub softening_length: Float,
 }
 
 impl AsEntity for Entity {
     fn as_entity(&self) -> Entity {
         self.clone()
     }
+
+    fn set_entity(&mut self, entity: &Entity) {
+        self.vx = entity.vx;
+        self.vy = entity.vy;
+        self.vz = entity.vz;
+        self.x = entity.x;
+        self.y = entity.y;
+        self.z = entity.z;
+    }
 }
 
 impl PartialEq for Entity {
-    /// This is a workaround to prevent every particle from reporting that it is colliding with
-    /// itself. If two particles truly become identical, they won't be reported as colliding. This
-    /// is a spot for future improvement. The cost of adding some sort of unique ID is too much for
-    /// the time being.
+    /// Two entities are equal if they have the same position, radius and mass. Velocity is
+    /// ignored. The tree tells entities apart by their [EntityId]s, not by this comparison.
     fn eq(&self, other: &Self) -> bool {
         self.x == other.x
             && self.y == other.y
@@ -49,10 +143,93 @@ impl PartialEq for Entity {
 }
 
 impl Entity {
-    /// Needs to be reworked to use min/max position values, but it naively checks
-    /// if two things collide right now.
-    fn did_collide_into(&self, other: &Entity) -> bool {
-        self != other && self.distance(other) <= (self.radius + other.radius)
+    /// Returns how this entity first touches `other` within the next `time_step`, if both move in
+    /// a straight line at their current velocities. Entities which already overlap touch at a
+    /// time of zero, so with a `time_step` of zero this only finds the entities which overlap
+    /// now. The caller is responsible for not checking an entity against itself.
+    pub(crate) fn contact_with(&self, other: &Entity, time_step: Float) -> Option<ContactGeometry> {
+        let offset = (self.x - other.x, self.y - other.y, self.z - other.z);
+        let velocity = (self.vx - other.vx, self.vy - other.vy, self.vz - other.vz);
+        let dot =
+            |a: (Float, Float, Float), b: (Float, Float, Float)| a.0 * b.0 + a.1 * b.1 + a.2 * b.2;
+        let radii_sum = self.radius + other.radius;
+        let c = dot(offset, offset) - radii_sum * radii_sum;
+        let time = if c <= 0. {
+            0.
+        } else {
+            // The entities touch when |offset + velocity * t|² = radii_sum², so the first time
+            // they do is the smaller root of a * t² + 2 * b * t + c = 0. They can only reach it
+            // if they are moving towards each other.
+            let (a, b) = (dot(velocity, velocity), dot(offset, velocity));
+            let discriminant = b * b - a * c;
+            if b >= 0. || discriminant < 0. {
+                return None;
+            }
+            let time = (-b - Float::sqrt(discriminant)) / a;
+            if time > time_step {
+                return None;
+            }
+            time
+        };
+        let offset = (
+            offset.0 + velocity.0 * time,
+            offset.1 + velocity.1 * time,
+            offset.2 + velocity.2 * time,
+        );
+        let distance = Float::sqrt(dot(offset, offset));
+        let normal = if distance == 0. {
+            (0., 0., 0.)
+        } else {
+            (
+                offset.0 / distance,
+                offset.1 / distance,
+                offset.2 / distance,
+            )
+        };
+        let penetration_depth = Float::max(0., radii_sum - distance);
+        // The contact point is found by stepping back from this entity's center towards the
+        // other's, through its radius less half the overlap.
+        let inset = self.radius - penetration_depth / 2.;
+        Some(ContactGeometry {
+            time,
+            normal,
+            penetration_depth,
+            point: (
+                self.x + self.vx * time - normal.0 * inset,
+                self.y + self.vy * time - normal.1 * inset,
+                self.z + self.vz * time - normal.2 * inset,
+            ),
+            normal_velocity: dot(velocity, normal),
+        })
+    }
+
+    /// Returns the entity made by merging this entity and `other`, which conserves their mass,
+    /// momentum and volume. Its mass is the sum of theirs, its position and velocity are the
+    /// averages of theirs weighted by mass, which puts it at their center of mass moving with it,
+    /// and its radius is that of a sphere with the same volume as both of theirs together. Its
+    /// softening length is the larger of theirs.
+    ///
+    /// The kinetic energy of the two entities relative to each other is lost, as it would be in a
+    /// perfectly inelastic collision.
+    pub fn merged_with(&self, other: &Entity) -> Entity {
+        let mass = self.mass + other.mass;
+        let weighted = |a: Float, b: Float| (a * self.mass + b * other.mass) / mass;
+        Entity {
+            vx: weighted(self.vx, other.vx),
+            vy: weighted(self.vy, other.vy),
+            vz: weighted(self.vz, other.vz),
+            x: weighted(self.x, other.x),
+            y: weighted(self.y, other.y),
+            z: weighted(self.z, other.z),
+            radius: Float::cbrt(self.radius.powi(3) + other.radius.powi(3)),
+            mass,
+            softening_length: Float::max(self.softening_length, other.softening_length),
+        }
+    }
+
+    /// Returns the speed of this entity.
+    pub(crate) fn speed(&self) -> Float {
+        Float::sqrt(self.vx * self.vx + self.vy * self.vy + self.vz * self.vz)
     }
 
     /// Returns the entity as a string with space separated values.
@@ -65,7 +242,7 @@ impl Entity {
 
     /// The returns the distance squared between two particles.
     /// Take the sqrt of this to get the distance.
-    fn distance_squared(&self, other: &Entity) -> f64 {
+    fn distance_squared(&self, other: &Entity) -> Float {
         // (x2 - x1) + (y2 - y1) + (z2 - z1)
         // all dist variables  are squared
         // This is being called from somewhere where `other` has NaN values
@@ -74,45 +251,84 @@ impl Entity {
     }
 
     /// Returns the distance between the two entities
-    pub(crate) fn distance(&self, other: &Entity) -> f64 {
+    pub(crate) fn distance(&self, other: &Entity) -> Float {
         // sqrt((x2 - x1) + (y2 - y1) + (z2 - z1))
-        f64::sqrt(self.distance_squared(other))
+        Float::sqrt(self.distance_squared(other))
     }
 
-    /// Returns the distance between two entities as an (x:f64,y:f64,z:f64) tuple.
-    pub(crate) fn distance_vector(&self, other: &Entity) -> (f64, f64, f64) {
+    /// Returns the distance between two entities as an (x:Float,y:Float,z:Float) tuple.
+    pub(crate) fn distance_vector(&self, other: &Entity) -> (Float, Float, Float) {
         let x_dist = other.x - self.x;
         let y_dist = other.y - self.y;
         let z_dist = other.z - self.z;
         (x_dist, y_dist, z_dist)
     }
 
-    pub(crate) fn get_dim(&self, dim: &Dimension) -> &f64 {
-        match *dim {
-            Dimension::X => &self.x,
-            Dimension::Y => &self.y,
-            Dimension::Z => &self.z,
-        }
+    /// Returns the first `D` coordinates of this entity's position.
+    pub(crate) fn position<const D: usize>(&self) -> [Float; D] {
+        let mut position = [0.; D];
+        position.copy_from_slice(&[self.x, self.y, self.z][..D]);
+        position
     }
 
-    /// Returns a boolean representing whether or node the node is within the theta range
-    /// of the entity.
-    fn theta_exceeded<T: AsEntity + Clone>(&self, node: &Node<T>, theta: f64) -> bool {
-        // 1) distance from entity to COM of that node
-        // 2) if 1) * theta > size (max diff) then
-        // This frequently makes a node with NaN positions
-        let node_as_entity = node.as_entity();
-        let dist = self.distance_squared(&node_as_entity);
-        let max_dist = node.max_distance();
-        (dist) * (theta * theta) > (max_dist * max_dist)
+    /// Returns whether `node` may be approximated as a whole for this entity, as decided by the
+    /// tree's [OpeningCriterion].
+    fn accepts<T: AsEntity<D>, const D: usize>(
+        &self,
+        node: &Node<T, D>,
+        params: ForceParameters,
+    ) -> bool {
+        node.accepted(
+            self.distance(&node.as_entity()),
+            node.distance_to_bounds((self.x, self.y, self.z)),
+            params,
+        )
+    }
+
+    /// Returns whether the gravity of the entities in `leaf` on this entity can be summed by the
+    /// [LeafKernel], which handles neither softening nor the jerk. Softening is ruled out when it
+    /// would not apply even at the nearest point of the leaf's bounds.
+    pub(crate) fn uses_leaf_kernel<T: AsEntity<D>, const D: usize>(
+        &self,
+        leaf: &Node<T, D>,
+        params: ForceParameters,
+    ) -> bool {
+        !params.calculate_jerk
+            && params
+                .softening
+                .inverse_cube(
+                    leaf.distance_to_bounds((self.x, self.y, self.z)),
+                    self.softening_length,
+                    leaf.softening_max,
+                )
+                .is_none()
+    }
+
+    /// Adds the acceleration which every entity in `leaf` exerts on this entity to `total`, summed
+    /// by the [LeafKernel]. See [uses_leaf_kernel](Entity::uses_leaf_kernel) for when this applies.
+    pub(crate) fn add_leaf_acceleration<T: AsEntity<D>, const D: usize>(
+        &self,
+        total: (Float, Float, Float),
+        leaf: &Node<T, D>,
+        params: ForceParameters,
+    ) -> (Float, Float, Float) {
+        leaf.arrays.add_acceleration(
+            total,
+            [self.x, self.y, self.z],
+            params.force_law,
+            params.gravitational_constant,
+            params.leaf_kernel,
+            params.precision,
+        )
     }
 
     /// Given two entities, self and other, returns the acceleration that other is exerting on
     /// self. Other can be either an entity or a node.
-    fn get_gravitational_acceleration<T: AsEntity + Clone>(
+    pub(crate) fn get_gravitational_acceleration<T: AsEntity<D>, const D: usize>(
         &self,
-        oth: Either<&Entity, &Node<T>>,
-    ) -> (f64, f64, f64) {
+        oth: Either<&Entity, &Node<T, D>>,
+        params: ForceParameters,
+    ) -> (Float, Float, Float) {
         // TODO get rid of this clone
         let other = match oth {
             Left(entity) => entity.clone(),
@@ -124,162 +340,311 @@ impl Entity {
             return (0., 0., 0.);
         }
         let d_vector = self.distance_vector(&other);
-        let d_mag_cubed = d_magnitude * d_magnitude; // TODO cube this
-        let d_over_d_cubed = (
-            d_vector.0 / d_mag_cubed,
-            d_vector.1 / d_mag_cubed,
-            d_vector.2 / d_mag_cubed,
+        // `d_vector` is not normalized, so dividing it by r³ yields r̂ / r², and so on for the
+        // other powers of r.
+        let denominator = params.force_law.denominator(d_magnitude);
+        let g = params
+            .force_law
+            .gravitational_constant(params.gravitational_constant);
+        let d_over_d_cubed = match params.softening.inverse_cube(
+            d_magnitude,
+            self.softening_length,
+            other.softening_length,
+        ) {
+            Some(inverse_cube) => {
+                // The other force laws fall off one or two powers of r more slowly than the
+                // Newtonian one.
+                let inverse_cube = inverse_cube * d_magnitude.powi(3 - params.force_law.power());
+                (
+                    d_vector.0 * inverse_cube,
+                    d_vector.1 * inverse_cube,
+                    d_vector.2 * inverse_cube,
+                )
+            }
+            None => (
+                d_vector.0 / denominator,
+                d_vector.1 / denominator,
+                d_vector.2 / denominator,
+            ),
+        };
+        (
+            d_over_d_cubed.0 * g * other.mass,
+            d_over_d_cubed.1 * g * other.mass,
+            d_over_d_cubed.2 * g * other.mass,
+        )
+    }
+
+    /// Returns the acceleration which the higher order terms of `node`'s multipole expansion add
+    /// to that of its monopole. These are only used where the force is Newtonian and unsoftened.
+    fn get_multipole_acceleration<T: AsEntity<D>, const D: usize>(
+        &self,
+        node: &Node<T, D>,
+        params: ForceParameters,
+    ) -> (Float, Float, Float) {
+        if params.multipole_order == MultipoleOrder::Monopole
+            || params.force_law != ForceLaw::Newtonian
+        {
+            return (0., 0., 0.);
+        }
+        let node_as_entity = node.as_entity();
+        let d_magnitude = self.distance(&node_as_entity);
+        if d_magnitude == 0.
+            || params
+                .softening
+                .inverse_cube(
+                    d_magnitude,
+                    self.softening_length,
+                    node_as_entity.softening_length,
+                )
+                .is_some()
+        {
+            return (0., 0., 0.);
+        }
+        node.multipole_acceleration(
+            (self.x, self.y, self.z),
+            params.multipole_order,
+            params.gravitational_constant,
+        )
+    }
+
+    /// Given two entities, self and other, returns the jerk (the rate of change of the
+    /// acceleration) that other is exerting on self. Other can be either an entity or a node.
+    fn get_gravitational_jerk<T: AsEntity<D>, const D: usize>(
+        &self,
+        oth: Either<&Entity, &Node<T, D>>,
+        params: ForceParameters,
+    ) -> (Float, Float, Float) {
+        let other = match oth {
+            Left(entity) => entity.clone(),
+            Right(node) => node.as_entity(),
+        };
+        let d_magnitude = self.distance(&other);
+        if d_magnitude == 0. {
+            return (0., 0., 0.);
+        }
+        let d_vector = self.distance_vector(&other);
+        let v_vector = (other.vx - self.vx, other.vy - self.vy, other.vz - self.vz);
+        // The acceleration is G * m * d * f(r), where f(r) is 1 / r³ when unsoftened, so its
+        // derivative is G * m * (v * f(r) + d * f'(r) * dr/dt), where dr/dt = (d · v) / r.
+        let (f, f_prime) = params
+            .softening
+            .inverse_cube_and_derivative(d_magnitude, self.softening_length, other.softening_length)
+            .unwrap_or_else(|| {
+                let d_squared = d_magnitude * d_magnitude;
+                (
+                    1. / (d_squared * d_magnitude),
+                    -3. / (d_squared * d_squared),
+                )
+            });
+        // The weaker force laws use r^k * f(r) in place of f(r), whose derivative is
+        // k * r^(k - 1) * f(r) + r^k * f'(r).
+        let k = 3 - params.force_law.power();
+        let r_k = d_magnitude.powi(k);
+        let (f, f_prime) = (
+            r_k * f,
+            k as Float * d_magnitude.powi(k - 1) * f + r_k * f_prime,
         );
+        let g = params
+            .force_law
+            .gravitational_constant(params.gravitational_constant);
+        let r_dot = (d_vector.0 * v_vector.0 + d_vector.1 * v_vector.1 + d_vector.2 * v_vector.2)
+            / d_magnitude;
         (
-            d_over_d_cubed.0 * other.mass,
-            d_over_d_cubed.1 * other.mass,
-            d_over_d_cubed.2 * other.mass,
+            g * other.mass * (v_vector.0 * f + d_vector.0 * f_prime * r_dot),
+            g * other.mass * (v_vector.1 * f + d_vector.1 * f_prime * r_dot),
+            g * other.mass * (v_vector.2 * f + d_vector.2 * f_prime * r_dot),
         )
     }
 
+    /// Returns the exact acceleration exerted on this entity by every other entity in `entities`,
+    /// found by summing over every pair. `index` is this entity's position in `entities`, so that
+    /// it does not attract itself. This is O(n) per entity and is meant as a reference for the
+    /// accuracy of the tree walk.
+    pub(crate) fn get_direct_sum_acceleration(
+        &self,
+        index: usize,
+        entities: &[Entity],
+        params: ForceParameters,
+    ) -> (Float, Float, Float) {
+        entities
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .fold((0., 0., 0.), |acc, (_, other)| {
+                let tmp_accel =
+                    self.get_gravitational_acceleration::<Entity, 3>(Left(other), params);
+                (
+                    acc.0 + tmp_accel.0,
+                    acc.1 + tmp_accel.1,
+                    acc.2 + tmp_accel.2,
+                )
+            })
+    }
+
     /// Returns the acceleration of an entity after it has had gravity from the specified node applied to it.
-    /// In this function, we approximate some entities if they exceed a certain critera specified in
-    /// "exceeds_theta()". If we reach a node and it is a leaf, then we automatically get the
+    /// In this function, we approximate some entities if they are accepted by the tree's
+    /// [OpeningCriterion]. If we reach a node and it is a leaf, then we automatically get the
     /// acceleration from every entity in that node, but if we reach a node that is not a leaf and
-    /// exceeds_theta() is true, then we treat the node as one giant entity and get the
+    /// the criterion accepts it, then we treat the node as one giant entity and get the
     /// acceleration from it.
-    pub(crate) fn get_acceleration_and_collisions<'a, T: AsEntity + Clone>(
-        &'a self,
-        node: &'a Node<T>,
-        theta: f64,
-    ) -> SimulationResult<T> {
+    /// `id` is the id of this entity in the tree, so that it is not attracted to itself. The walk
+    /// does not look for collisions, which [get_collisions](Entity::get_collisions) finds instead,
+    /// so the result holds none.
+    pub(crate) fn get_acceleration_without_collisions<'a, T: AsEntity<D>, const D: usize>(
+        &self,
+        id: EntityId,
+        node: &'a Node<T, D>,
+        params: ForceParameters,
+    ) -> SimulationResult<'a, T> {
+        let mut totals = WalkTotals::new();
+        self.walk(id, node, params, &mut totals);
+        totals.into_simulation_result(params)
+    }
+
+    /// Returns every entity under `node` which this entity collides with within the next
+    /// `time_step`, as found by [contact_with](Entity::contact_with), other than itself.
+    /// Unlike the tree walk, this never approximates a node, and only descends into the nodes
+    /// whose bounds are close enough to hold an entity this one could touch.
+    pub(crate) fn get_collisions<'a, T: AsEntity<D>, const D: usize>(
+        &self,
+        id: EntityId,
+        node: &'a Node<T, D>,
+        time_step: Float,
+    ) -> Vec<Contact<'a, T>> {
         let mut collisions = Vec::new();
-        let mut acceleration = (0., 0., 0.);
-        if let Some(node) = &node.left {
-            if node.points.is_some() {
-                // if this node has some points, calculate their gravitational acceleration
-                for i in node.points.as_ref().expect("unexpected null node 2") {
-                    if self.did_collide_into(&i.as_entity()) {
-                        collisions.push(i);
-                    }
-                    let tmp_accel =
-                        self.get_gravitational_acceleration::<Entity>(Left(&(i.as_entity())));
-                    acceleration.0 += tmp_accel.0;
-                    acceleration.1 += tmp_accel.1;
-                    acceleration.2 += tmp_accel.2;
-                }
-            } else if self.theta_exceeded(node, theta) {
-                // otherwise, if theta is exceeded, calculate the entire node as a big boi particle
-                let tmp_accel = self.get_gravitational_acceleration(Right(node));
-                acceleration.0 += tmp_accel.0;
-                acceleration.1 += tmp_accel.1;
-                acceleration.2 += tmp_accel.2;
-            } else {
-                // otherwise, theta has not been exceeded and this is not a leaf. recurse
-                let mut res = self.get_acceleration_and_collisions(node, theta);
-                let tmp_accel = res.gravitational_acceleration;
-                collisions.append(&mut res.collisions);
-                acceleration.0 += tmp_accel.0;
-                acceleration.1 += tmp_accel.1;
-                acceleration.2 += tmp_accel.2;
+        self.find_collisions(id, node, time_step, &mut collisions);
+        collisions
+    }
+
+    fn find_collisions<'a, T: AsEntity<D>, const D: usize>(
+        &self,
+        id: EntityId,
+        node: &'a Node<T, D>,
+        time_step: Float,
+        collisions: &mut Vec<Contact<'a, T>>,
+    ) {
+        for node in node.left.iter().chain(node.right.iter()) {
+            // Nothing in the node can come closer to this entity than the distance to its bounds,
+            // less how far the two of them could travel towards each other.
+            let reach = self.radius + node.r_max + (self.speed() + node.v_max) * time_step;
+            if node.distance_to_bounds((self.x, self.y, self.z)) > reach {
+                continue;
             }
-        };
-        if let Some(node) = &node.right {
-            if node.points.is_some() {
-                // same logic as above
-                for i in node.points.as_ref().expect("unexpected null node 2") {
-                    if self.did_collide_into(&i.as_entity()) {
-                        collisions.push(i);
+            if let Some(points) = &node.points {
+                for (i, other_id) in points.iter().zip(node.ids.iter()) {
+                    if *other_id == id {
+                        continue;
+                    }
+                    if let Some(contact) = self.contact_with(&i.as_entity(), time_step) {
+                        collisions.push(contact.with(i));
                     }
-                    let tmp_accel =
-                        self.get_gravitational_acceleration::<Entity>(Left(&(i.as_entity())));
-                    acceleration.0 += tmp_accel.0;
-                    acceleration.1 += tmp_accel.1;
-                    acceleration.2 += tmp_accel.2;
                 }
-            } else if self.theta_exceeded(node, theta) {
-                // otherwise, if theta is exceeded, calculate the entire node as a big boi particle
-                let tmp_accel = self.get_gravitational_acceleration(Right(node));
-                acceleration.0 += tmp_accel.0;
-                acceleration.1 += tmp_accel.1;
-                acceleration.2 += tmp_accel.2;
             } else {
-                // otherwise, theta has not been exceeded and this is not a leaf. recurse
-                let mut res = self.get_acceleration_and_collisions(node, theta);
-                let tmp_accel = res.gravitational_acceleration;
-                collisions.append(&mut res.collisions);
-                acceleration.0 += tmp_accel.0;
-                acceleration.1 += tmp_accel.1;
-                acceleration.2 += tmp_accel.2;
+                self.find_collisions(id, node, time_step, collisions);
             }
-        };
-        SimulationResult {
-            collisions,
-            gravitational_acceleration: (
-                acceleration.0 + acceleration.0,
-                acceleration.1 + acceleration.1,
-                acceleration.2 + acceleration.2,
-            ),
         }
     }
-    pub(crate) fn get_acceleration_without_collisions<'a, T: AsEntity + Clone>(
-        &'a self,
-        node: &'a Node<T>,
-        theta: f64,
-    ) -> SimulationResult<T> {
-        let mut acceleration = (0., 0., 0.);
-        if let Some(node) = &node.left {
+
+    /// Recursively walks the children of `node`, adding their gravitational effect on this entity
+    /// to `totals`.
+    fn walk<T: AsEntity<D>, const D: usize>(
+        &self,
+        id: EntityId,
+        node: &Node<T, D>,
+        params: ForceParameters,
+        totals: &mut WalkTotals,
+    ) {
+        for node in node.left.iter().chain(node.right.iter()) {
             if node.points.is_some() {
                 // if this node has some points, calculate their gravitational acceleration
-                for i in node.points.as_ref().expect("unexpected null node 2") {
-                    let tmp_accel =
-                        self.get_gravitational_acceleration::<Entity>(Left(&(i.as_entity())));
-                    acceleration.0 += tmp_accel.0;
-                    acceleration.1 += tmp_accel.1;
-                    acceleration.2 += tmp_accel.2;
-                }
-            } else if self.theta_exceeded(node, theta) {
-                // otherwise, if theta is exceeded, calculate the entire node as a big boi particle
-                let tmp_accel = self.get_gravitational_acceleration(Right(node));
-                acceleration.0 += tmp_accel.0;
-                acceleration.1 += tmp_accel.1;
-                acceleration.2 += tmp_accel.2;
+                self.add_leaf(id, node, params, totals);
+            } else if self.accepts(node, params) {
+                // otherwise, if the node is accepted, calculate the entire node as a big boi particle
+                self.add_gravity(Right(node), params, totals);
             } else {
-                // otherwise, theta has not been exceeded and this is not a leaf. recurse
-                let res = self.get_acceleration_without_collisions(node, theta);
-                let tmp_accel = res.gravitational_acceleration;
-                acceleration.0 += tmp_accel.0;
-                acceleration.1 += tmp_accel.1;
-                acceleration.2 += tmp_accel.2;
+                // otherwise, the node has been opened and this is not a leaf. recurse
+                self.walk(id, node, params, totals);
             }
-        };
-        if let Some(node) = &node.right {
-            if node.points.is_some() {
-                // same logic as above
-                for i in node.points.as_ref().expect("unexpected null node 2") {
-                    let tmp_accel =
-                        self.get_gravitational_acceleration::<Entity>(Left(&(i.as_entity())));
-                    acceleration.0 += tmp_accel.0;
-                    acceleration.1 += tmp_accel.1;
-                    acceleration.2 += tmp_accel.2;
-                }
-            } else if self.theta_exceeded(node, theta) {
-                // otherwise, if theta is exceeded, calculate the entire node as a big boi particle
-                let tmp_accel = self.get_gravitational_acceleration(Right(node));
-                acceleration.0 += tmp_accel.0;
-                acceleration.1 += tmp_accel.1;
-                acceleration.2 += tmp_accel.2;
-            } else {
-                // otherwise, theta has not been exceeded and this is not a leaf. recurse
-                let res = self.get_acceleration_without_collisions(node, theta);
-                let tmp_accel = res.gravitational_acceleration;
-                acceleration.0 += tmp_accel.0;
-                acceleration.1 += tmp_accel.1;
-                acceleration.2 += tmp_accel.2;
+        }
+    }
+
+    /// Adds the gravitational effect of every entity in `leaf` other than this one on this entity
+    /// to `totals`.
+    pub(crate) fn add_leaf<T: AsEntity<D>, const D: usize>(
+        &self,
+        id: EntityId,
+        leaf: &Node<T, D>,
+        params: ForceParameters,
+        totals: &mut WalkTotals,
+    ) {
+        let points = leaf.points.as_ref().expect("unexpected null node #13");
+        if self.uses_leaf_kernel(leaf, params) {
+            totals.acceleration = self.add_leaf_acceleration(totals.acceleration, leaf, params);
+            return;
+        }
+        for (i, other_id) in points.iter().zip(leaf.ids.iter()) {
+            if *other_id == id {
+                continue;
             }
-        };
+            self.add_gravity::<T, D>(Left(&i.as_entity()), params, totals);
+        }
+    }
+
+    /// Adds the acceleration, and the jerk if it is being calculated, that `oth` exerts on this
+    /// entity to `totals`.
+    pub(crate) fn add_gravity<T: AsEntity<D>, const D: usize>(
+        &self,
+        oth: Either<&Entity, &Node<T, D>>,
+        params: ForceParameters,
+        totals: &mut WalkTotals,
+    ) {
+        let tmp_accel = self.get_gravitational_acceleration(oth, params);
+        totals.acceleration.0 += tmp_accel.0;
+        totals.acceleration.1 += tmp_accel.1;
+        totals.acceleration.2 += tmp_accel.2;
+        if let Right(node) = oth {
+            let tmp_accel = self.get_multipole_acceleration(node, params);
+            totals.acceleration.0 += tmp_accel.0;
+            totals.acceleration.1 += tmp_accel.1;
+            totals.acceleration.2 += tmp_accel.2;
+        }
+        if params.calculate_jerk {
+            let tmp_jerk = self.get_gravitational_jerk(oth, params);
+            totals.jerk.0 += tmp_jerk.0;
+            totals.jerk.1 += tmp_jerk.1;
+            totals.jerk.2 += tmp_jerk.2;
+        }
+    }
+}
+
+/// The running totals accumulated while walking the tree for a single entity.
+pub(crate) struct WalkTotals {
+    acceleration: (Float, Float, Float),
+    jerk: (Float, Float, Float),
+}
+
+impl WalkTotals {
+    pub(crate) fn new() -> WalkTotals {
+        WalkTotals {
+            acceleration: (0., 0., 0.),
+            jerk: (0., 0., 0.),
+        }
+    }
+
+    /// Returns the totals as a [SimulationResult] without any collisions, which are found
+    /// separately.
+    pub(crate) fn into_simulation_result<'a, T>(
+        self,
+        params: ForceParameters,
+    ) -> SimulationResult<'a, T> {
         SimulationResult {
-            collisions: vec![],
-            gravitational_acceleration: (
-                acceleration.0 + acceleration.0,
-                acceleration.1 + acceleration.1,
-                acceleration.2 + acceleration.2,
-            ),
+            collisions: Vec::new(),
+            gravitational_acceleration: self.acceleration,
+            jerk: if params.calculate_jerk {
+                Some(self.jerk)
+            } else {
+                None
+            },
         }
     }
 }
@@ -0,0 +1,39 @@
+use crate::as_entity::AsEntity;
+use crate::entity::Entity;
+use crate::Float;
+use serde::{Deserialize, Serialize};
+
+/// The two dimensional counterpart of an [Entity]: a disc with a position, velocity, radius and
+/// mass in the plane, which a [GravTree2](crate::GravTree2) moves around according to the gravity
+/// it and the other entities exert on each other.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[repr(C)]
+pub struct Entity2 {
+    pub vx: Float,
+    pub vy: Float,
+    pub x: Float,
+    pub y: Float,
+    pub radius: Float,
+    pub mass: Float,
+}
+
+impl AsEntity<2> for Entity2 {
+    fn as_entity(&self) -> Entity {
+        Entity {
+            vx: self.vx,
+            vy: self.vy,
+            x: self.x,
+            y: self.y,
+            radius: self.radius,
+            mass: self.mass,
+            ..Entity::default()
+        }
+    }
+
+    fn set_entity(&mut self, entity: &Entity) {
+        self.vx = entity.vx;
+        self.vy = entity.vy;
+        self.x = entity.x;
+        self.y = entity.y;
+    }
+}
@@ -0,0 +1,321 @@
+//! The fast multipole method. See [Solver::FastMultipole](crate::Solver::FastMultipole).
+//!
+//! The tree is walked against itself (a dual tree walk). Whenever a target node and a source node
+//! are far enough apart, the field of the source's multipole expansion is turned into a local
+//! expansion about the target's center of mass (M2L). Pairs of leaves which are too close are
+//! summed directly (P2P). Once the walk is done, the local expansions are shifted down the tree
+//! (L2L) and evaluated at every entity (L2P).
+use crate::as_entity::AsEntity;
+use crate::entity::{Entity, ForceParameters};
+use crate::multipole::pair;
+use crate::Float;
+use crate::Node;
+use either::Left;
+use rayon::prelude::*;
+
+/// Target nodes with more entities than this have their children walked in parallel.
+const PARALLEL_CUTOFF: usize = 1024;
+
+/// A second order Taylor expansion of the acceleration about the center of mass of a node.
+#[derive(Clone, Copy, Default)]
+struct Local {
+    /// The acceleration at the center.
+    acceleration: [Float; 3],
+    /// The derivatives of the acceleration, `∂aᵢ/∂xⱼ`.
+    gradient: [[Float; 3]; 3],
+    /// The second derivatives of the acceleration, `∂²aᵢ/∂xⱼ∂xₖ`.
+    hessian: [[[Float; 3]; 3]; 3],
+}
+
+impl Local {
+    fn add(&mut self, other: &Local) {
+        for i in 0..3 {
+            self.acceleration[i] += other.acceleration[i];
+            for j in 0..3 {
+                self.gradient[i][j] += other.gradient[i][j];
+                for k in 0..3 {
+                    self.hessian[i][j][k] += other.hessian[i][j][k];
+                }
+            }
+        }
+    }
+
+    /// Returns the acceleration at an offset `d` from the center.
+    fn evaluate(&self, d: [Float; 3]) -> [Float; 3] {
+        let mut acceleration = self.acceleration;
+        for (i, a) in acceleration.iter_mut().enumerate() {
+            for j in 0..3 {
+                *a += self.gradient[i][j] * d[j];
+                for k in 0..3 {
+                    *a += 0.5 * self.hessian[i][j][k] * d[j] * d[k];
+                }
+            }
+        }
+        acceleration
+    }
+
+    /// Returns the same expansion about a center at an offset `d` from this one.
+    fn shifted(&self, d: [Float; 3]) -> Local {
+        let mut gradient = self.gradient;
+        for (i, row) in gradient.iter_mut().enumerate() {
+            for (j, g) in row.iter_mut().enumerate() {
+                *g += (0..3).map(|k| self.hessian[i][j][k] * d[k]).sum::<Float>();
+            }
+        }
+        Local {
+            acceleration: self.evaluate(d),
+            gradient,
+            hessian: self.hessian,
+        }
+    }
+
+    /// Adds the field of `source`, expanded about `center`. The source's monopole contributes up
+    /// to the second derivatives and its quadrupole up to the first, which keeps every neglected
+    /// term at the same order in the ratio of the nodes' sizes to their distance.
+    fn add_node<T: AsEntity<D>, const D: usize>(
+        &mut self,
+        center: [Float; 3],
+        source: &Node<T, D>,
+        g: Float,
+    ) {
+        let r = [
+            center[0] - source.center_of_mass.0,
+            center[1] - source.center_of_mass.1,
+            center[2] - source.center_of_mass.2,
+        ];
+        let r_squared = r[0] * r[0] + r[1] * r[1] + r[2] * r[2];
+        let r_cubed = r_squared * Float::sqrt(r_squared);
+        let r_fifth = r_cubed * r_squared;
+        let r_seventh = r_fifth * r_squared;
+        let r_ninth = r_seventh * r_squared;
+        let g_m = g * source.total_mass;
+        let quadrupole = &source.quadrupole;
+        let mut q_r = [0.; 3];
+        for (i, q_r) in q_r.iter_mut().enumerate() {
+            *q_r = (0..3).map(|j| pair(quadrupole, i, j) * r[j]).sum();
+        }
+        let r_q_r = (0..3).map(|i| r[i] * q_r[i]).sum::<Float>();
+        let delta = |i: usize, j: usize| if i == j { 1. } else { 0. };
+
+        for i in 0..3 {
+            self.acceleration[i] +=
+                -g_m * r[i] / r_cubed + g * (q_r[i] / r_fifth - 2.5 * r_q_r * r[i] / r_seventh);
+            for j in 0..3 {
+                self.gradient[i][j] += -g_m * (delta(i, j) / r_cubed - 3. * r[i] * r[j] / r_fifth)
+                    + g * (pair(quadrupole, i, j) / r_fifth
+                        - 5. * (q_r[i] * r[j] + q_r[j] * r[i]) / r_seventh
+                        - 2.5 * r_q_r * delta(i, j) / r_seventh
+                        + 17.5 * r_q_r * r[i] * r[j] / r_ninth);
+                for k in 0..3 {
+                    self.hessian[i][j][k] += g_m
+                        * (3. * (delta(i, j) * r[k] + delta(i, k) * r[j] + delta(j, k) * r[i])
+                            / r_fifth
+                            - 15. * r[i] * r[j] * r[k] / r_seventh);
+                }
+            }
+        }
+    }
+}
+
+/// The state which the fast multipole method accumulates for a node of the tree, mirroring its
+/// structure.
+struct Cell {
+    /// The local expansion of the field of every node which this node has interacted with as a
+    /// whole.
+    local: Local,
+    /// For a leaf, the accelerations from the entities which were summed directly, in the same
+    /// order as its entities.
+    direct: Vec<[Float; 3]>,
+    children: Vec<Cell>,
+}
+
+impl Cell {
+    fn new<T: AsEntity<D>, const D: usize>(node: &Node<T, D>) -> Cell {
+        Cell {
+            local: Local::default(),
+            direct: vec![[0.; 3]; if is_leaf(node) { node.ids.len() } else { 0 }],
+            children: children(node).map(Cell::new).collect(),
+        }
+    }
+}
+
+fn is_leaf<T: AsEntity<D>, const D: usize>(node: &Node<T, D>) -> bool {
+    node.left.is_none() && node.right.is_none()
+}
+
+fn children<T: AsEntity<D>, const D: usize>(
+    node: &Node<T, D>,
+) -> impl Iterator<Item = &Node<T, D>> {
+    node.left
+        .iter()
+        .chain(node.right.iter())
+        .map(|node| &**node)
+}
+
+fn center<T: AsEntity<D>, const D: usize>(node: &Node<T, D>) -> [Float; 3] {
+    [
+        node.center_of_mass.0,
+        node.center_of_mass.1,
+        node.center_of_mass.2,
+    ]
+}
+
+/// Returns the gravitational acceleration on every entity under `root`, in traversal order.
+pub(crate) fn accelerations<T: AsEntity<D> + Sync, const D: usize>(
+    root: &Node<T, D>,
+    params: ForceParameters,
+) -> Vec<(Float, Float, Float)> {
+    let mut accelerations = Vec::with_capacity(root.number_of_points);
+    for node in children(root) {
+        let mut cell = Cell::new(node);
+        interact(node, &mut cell, node, params);
+        evaluate(node, &cell, &Local::default(), &mut accelerations);
+    }
+    accelerations
+}
+
+/// Adds the field of every entity under `source` to the cells of `target`.
+fn interact<T: AsEntity<D> + Sync, const D: usize>(
+    target: &Node<T, D>,
+    cell: &mut Cell,
+    source: &Node<T, D>,
+    params: ForceParameters,
+) {
+    if source.number_of_points == 0 || target.number_of_points == 0 {
+        return;
+    }
+    let (target_radius, source_radius) = (
+        target.radius_about_center_of_mass(),
+        source.radius_about_center_of_mass(),
+    );
+    let (dx, dy, dz) = (
+        target.center_of_mass.0 - source.center_of_mass.0,
+        target.center_of_mass.1 - source.center_of_mass.1,
+        target.center_of_mass.2 - source.center_of_mass.2,
+    );
+    let distance = Float::sqrt(dx * dx + dy * dy + dz * dz);
+    let size = target_radius + source_radius;
+    if size < params.theta * distance
+        && params
+            .softening
+            .inverse_cube(distance - size, target.softening_max, source.softening_max)
+            .is_none()
+    {
+        cell.local
+            .add_node(center(target), source, params.gravitational_constant);
+        return;
+    }
+
+    match (is_leaf(target), is_leaf(source)) {
+        (true, true) => sum_directly(target, cell, source, params),
+        (false, true) => split_target(target, cell, source, params),
+        (true, false) => split_source(target, cell, source, params),
+        (false, false) if target_radius >= source_radius => {
+            split_target(target, cell, source, params)
+        }
+        (false, false) => split_source(target, cell, source, params),
+    }
+}
+
+fn split_target<T: AsEntity<D> + Sync, const D: usize>(
+    target: &Node<T, D>,
+    cell: &mut Cell,
+    source: &Node<T, D>,
+    params: ForceParameters,
+) {
+    let targets = children(target).collect::<Vec<_>>();
+    if target.number_of_points > PARALLEL_CUTOFF {
+        cell.children
+            .par_iter_mut()
+            .zip(targets.par_iter())
+            .for_each(|(cell, target)| interact(target, cell, source, params));
+    } else {
+        for (cell, target) in cell.children.iter_mut().zip(targets) {
+            interact(target, cell, source, params);
+        }
+    }
+}
+
+fn split_source<T: AsEntity<D> + Sync, const D: usize>(
+    target: &Node<T, D>,
+    cell: &mut Cell,
+    source: &Node<T, D>,
+    params: ForceParameters,
+) {
+    for source in children(source) {
+        interact(target, cell, source, params);
+    }
+}
+
+/// Adds the acceleration of every entity in the leaf `source` on every entity in the leaf
+/// `target`, skipping pairs of an entity with itself.
+fn sum_directly<T: AsEntity<D>, const D: usize>(
+    target: &Node<T, D>,
+    cell: &mut Cell,
+    source: &Node<T, D>,
+    params: ForceParameters,
+) {
+    let (targets, sources) = match (&target.points, &source.points) {
+        (Some(targets), Some(sources)) => (targets, sources),
+        _ => return,
+    };
+    for ((x, id), direct) in targets
+        .iter()
+        .zip(target.ids.iter())
+        .zip(cell.direct.iter_mut())
+    {
+        let entity = x.as_entity();
+        if entity.uses_leaf_kernel(source, params) {
+            // The kernel skips entities at the same position, including this one.
+            let acceleration =
+                entity.add_leaf_acceleration((direct[0], direct[1], direct[2]), source, params);
+            *direct = [acceleration.0, acceleration.1, acceleration.2];
+            continue;
+        }
+        for (other, other_id) in sources.iter().zip(source.ids.iter()) {
+            if other_id == id {
+                continue;
+            }
+            let acceleration = entity
+                .get_gravitational_acceleration::<Entity, 3>(Left(&other.as_entity()), params);
+            direct[0] += acceleration.0;
+            direct[1] += acceleration.1;
+            direct[2] += acceleration.2;
+        }
+    }
+}
+
+/// Shifts the local expansions down the tree, adding `inherited`, which is the expansion
+/// collected by the node's ancestors about its center of mass, and appends the acceleration on
+/// every entity to `accelerations` in traversal order.
+fn evaluate<T: AsEntity<D>, const D: usize>(
+    node: &Node<T, D>,
+    cell: &Cell,
+    inherited: &Local,
+    accelerations: &mut Vec<(Float, Float, Float)>,
+) {
+    let mut local = cell.local;
+    local.add(inherited);
+    let node_center = center(node);
+    if is_leaf(node) {
+        for (x, direct) in node.points.iter().flatten().zip(cell.direct.iter()) {
+            let entity = x.as_entity();
+            let far = local.evaluate([
+                entity.x - node_center[0],
+                entity.y - node_center[1],
+                entity.z - node_center[2],
+            ]);
+            accelerations.push((far[0] + direct[0], far[1] + direct[1], far[2] + direct[2]));
+        }
+        return;
+    }
+    for (child, cell) in children(node).zip(cell.children.iter()) {
+        let child_center = center(child);
+        let shifted = local.shifted([
+            child_center[0] - node_center[0],
+            child_center[1] - node_center[1],
+            child_center[2] - node_center[2],
+        ]);
+        evaluate(child, cell, &shifted, accelerations);
+    }
+}
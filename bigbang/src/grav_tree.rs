@@ -1,23 +1,89 @@
 use crate::responsive::Responsive;
-use crate::Node;
-use crate::{as_entity::AsEntity, entity::CalculateCollisions};
+use crate::simulation_result::{Contact, SimulationResult};
+use crate::Float;
+use crate::{
+    as_entity::AsEntity,
+    collision_detection::CollisionDetection,
+    entity::{CalculateCollisions, Entity, EntityId, ForceLaw, ForceParameters},
+    gravitational_constant,
+    integrator::{self, Integrator},
+    kernel::{LeafKernel, Precision},
+    merge_policy::MergePolicy,
+    multipole::MultipoleOrder,
+    opening_criterion::{OpeningAngle, OpeningCriterion},
+    softening::Softening,
+    solver::Solver,
+    time_step_criterion::{self, TimeStepCriterion},
+    tree_kind::TreeKind,
+    tree_update::TreeUpdate,
+};
+use crate::{broad_phase, fmm, group_walk, Node};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+/// An acceleration or jerk along each axis.
+type Vector = (Float, Float, Float);
+
+/// How far, as a fraction of the time step, the time left by [advance_to](GravTree::advance_to)
+/// may exceed a single time step for that step to still be the last one. Adding up many time steps
+/// rounds the simulation time, and this keeps the rounding from forcing a vanishingly small extra
+/// step.
+const LAST_STEP_TOLERANCE: Float = 1e-6;
+
+/// The two dimensional [GravTree], for simulations which take place in a plane. It works with any
+/// type which implements `AsEntity<2>`, as `#[derive(AsEntity2)]` does, and [Responsive].
+pub type GravTree2<T> = GravTree<T, 2>;
 
 /// The main struct you will interact with. This is a k-d tree containing all of your gravitational
 /// entities.
+///
+/// `D` is the number of dimensions the entities live in, which is inferred from the
+/// [AsEntity](crate::AsEntity) implementation of `T` and is three unless stated otherwise. A tree
+/// in one or two dimensions only splits along and measures distances in the axes its entities move
+/// in, for simulations which take place on a line, such as sheet models, or in a plane. `D` only
+/// masks the axes the tree works in: entities are still stored as three dimensional [Entity]s,
+/// and accelerations, jerks, [Contact](crate::Contact)s and the [collisions](crate::collisions)
+/// functions still use triples, whose components past the first `D` are zero. Only one, two and
+/// three dimensions are supported, and any other number is refused when the tree is compiled:
+///
+/// ```compile_fail
+/// use bigbang::{AsEntity, CalculateCollisions, Entity, GravTree, Responsive, SimulationResult};
+///
+/// #[derive(Clone)]
+/// struct Body(Entity);
+///
+/// impl AsEntity<4> for Body {
+///     fn as_entity(&self) -> Entity {
+///         self.0.clone()
+///     }
+///
+///     fn set_entity(&mut self, entity: &Entity) {
+///         self.0 = entity.clone();
+///     }
+/// }
+///
+/// impl Responsive for Body {
+///     fn respond(&self, _: SimulationResult<Self>, _: bigbang::Float) -> Self {
+///         self.clone()
+///     }
+/// }
+///
+/// let tree = GravTree::<Body, 4>::new(&[], 0.1, 3, 0.2, CalculateCollisions::No);
+/// ```
 #[derive(Serialize, Deserialize)]
-pub struct GravTree<T: AsEntity + Responsive + Clone> {
+pub struct GravTree<T: AsEntity<D> + Responsive, const D: usize = 3> {
     /// A GravTree consists of a root [[Node]]. A [[Node]] is a recursive binary tree data structure.
     /// Tragically must be public for now for testing reasons. Perhaps could be replaced by various
     /// getter methods later.
-    pub(crate) root: Node<T>,
+    pub(crate) root: Node<T, D>,
     /// This is just the number of entities in the tree. This is used in testing to verify that no
     /// entities are being dropped.
     number_of_entities: usize,
     /// This coefficient determines the granularity of the simulation, i.e. how much each frame of
     /// the simulation actually moves the individual entities.
-    time_step: f64, // the time coefficient; how large each simulation frame is time-wise.
+    time_step: Float, // the time coefficient; how large each simulation frame is time-wise.
     /// The maximum number of entities to be contained within any leaf node. Defaults to 3 but is
     /// configurable. This is _not_ the maximum number of entities in the simulation. A higher
     /// number here will result in lower simulation granularity.
@@ -25,26 +91,113 @@ pub struct GravTree<T: AsEntity + Responsive + Clone> {
     /// `theta` is how far away a node has to be before the simulation starts approximating its
     /// contained entities by treating them as one large node instead of individually addressing
     /// them.
-    /// More specifically, this is the tolerance for the distance from an entity to the center of mass of an entity
-    /// If the distance is beyond this threshold, we treat the entire node as one giant
-    /// entity instead of recursing into it.
-    theta: f64,
+    /// With the default [OpeningAngle] criterion, a node is approximated when its size divided by
+    /// its distance from an entity is less than `theta`, so a smaller `theta` is more accurate and
+    /// a `theta` of zero never approximates.
+    theta: Float,
+    /// Decides which nodes are approximated by the tree walk. Defaults to [OpeningAngle]. Custom
+    /// criteria cannot be serialized, so a deserialized tree uses the default.
+    #[serde(skip, default = "default_opening_criterion")]
+    opening_criterion: Arc<dyn OpeningCriterion>,
     /// Whether or not to calculate collisions when time stepping
     calculate_collisions: CalculateCollisions,
+    /// How the tree decides which entities collide. Defaults to
+    /// [Discrete](CollisionDetection::Discrete).
+    collision_detection: CollisionDetection,
+    /// How colliding entities are merged at the end of each time step. Defaults to `None`, in
+    /// which case they never are. Custom policies cannot be serialized, so a deserialized tree
+    /// has none.
+    #[serde(skip)]
+    merge_policy: Option<Arc<dyn MergePolicy<T>>>,
+    /// The gravitational constant `G`. Defaults to the SI value. See the
+    /// [gravitational_constant](crate::gravitational_constant) module for other presets.
+    gravitational_constant: Float,
+    /// The force law used to calculate gravitational acceleration. Defaults to
+    /// [Newtonian](ForceLaw::Newtonian).
+    force_law: ForceLaw,
+    /// The gravitational softening applied to close encounters. Defaults to
+    /// [None](Softening::None).
+    softening: Softening,
+    /// The built-in integrator used to move entities. Defaults to [None](Integrator::None), in
+    /// which case moving entities is left to [Responsive::respond].
+    integrator: Integrator,
+    /// The gravitational acceleration on every entity at the end of the last time step, by id.
+    /// The built-in integrators reuse these instead of calculating forces twice per step. Empty if
+    /// they have not been calculated.
+    accelerations: HashMap<EntityId, (Float, Float, Float)>,
+    /// The jerk on every entity at the end of the last time step, by id. Only used by the Hermite
+    /// integrator and the Aarseth time step criterion.
+    jerks: HashMap<EntityId, (Float, Float, Float)>,
+    /// How each entity's own time step is chosen. Defaults to [None](TimeStepCriterion::None), in
+    /// which case every entity uses `time_step`.
+    time_step_criterion: TimeStepCriterion,
+    /// The time step each entity took at the end of the last time step, by id. Empty unless block
+    /// time steps are in use.
+    time_steps: HashMap<EntityId, Float>,
+    /// How the nodes are updated after the entities have moved. Defaults to
+    /// [Rebuild](TreeUpdate::Rebuild).
+    tree_update: TreeUpdate,
+    /// How the tree is built from its entities. Defaults to [KdTree](TreeKind::KdTree).
+    tree_kind: TreeKind,
+    /// How many terms of the multipole expansion are used for approximated nodes. Defaults to
+    /// [Monopole](MultipoleOrder::Monopole).
+    multipole_order: MultipoleOrder,
+    /// How the gravity of the entities in a leaf is summed. Defaults to
+    /// [Simd](LeafKernel::Simd).
+    leaf_kernel: LeafKernel,
+    /// The precision in which the gravity of the entities in a leaf is summed. Defaults to
+    /// [Full](Precision::Full).
+    precision: Precision,
+    /// The algorithm used to calculate accelerations. Defaults to
+    /// [BarnesHut](Solver::BarnesHut).
+    solver: Solver,
+    /// The simulation time, i.e. the sum of all time steps taken so far.
+    time: Float,
+    /// The id that will be given to the next entity added to the tree.
+    next_id: u64,
+    /// The path from the real root to the leaf holding every entity, by id, where `true` takes the
+    /// right child. This lets an entity be found by its id without searching the whole tree.
+    paths: HashMap<EntityId, Vec<bool>>,
 }
 
-impl<T: AsEntity + Responsive + Clone + Send + Sync> GravTree<T> {
-    pub fn new(
-        pts: &[T],
-        time_step: f64,
+impl<T: AsEntity<D> + Responsive + Send + Sync, const D: usize> GravTree<T, D> {
+    /// Creates a tree which takes ownership of `pts`, the same as [new](GravTree::new) but without
+    /// requiring `Clone`.
+    pub fn from_vec(
+        pts: Vec<T>,
+        time_step: Float,
         max_entities: i32,
-        theta: f64,
+        theta: Float,
         calculate_collisions: CalculateCollisions,
-    ) -> GravTree<T>
-    where
-        T: AsEntity,
-    {
+    ) -> GravTree<T, D> {
+        let ids = (0..pts.len() as u64).map(EntityId).collect();
+        GravTree::from_parts(
+            pts,
+            ids,
+            time_step,
+            max_entities,
+            theta,
+            calculate_collisions,
+        )
+    }
+
+    /// Creates a tree containing `pts`, which have already been given the ids `ids`.
+    fn from_parts(
+        pts: Vec<T>,
+        ids: Vec<EntityId>,
+        time_step: Float,
+        max_entities: i32,
+        theta: Float,
+        calculate_collisions: CalculateCollisions,
+    ) -> GravTree<T, D> {
+        const {
+            assert!(
+                D >= 1 && D <= 3,
+                "a GravTree must have one, two or three dimensions"
+            )
+        };
         let size_of_vec = pts.len();
+        let next_id = ids.iter().map(|id| id.0 + 1).max().unwrap_or(0);
         // Handle the case where a grav tree is initialized without any points...
         if size_of_vec == 0 {
             return GravTree {
@@ -53,7 +206,27 @@ impl<T: AsEntity + Responsive + Clone + Send + Sync> GravTree<T> {
                 time_step,
                 max_entities,
                 theta,
+                opening_criterion: default_opening_criterion(),
                 calculate_collisions,
+                collision_detection: CollisionDetection::Discrete,
+                merge_policy: None,
+                gravitational_constant: gravitational_constant::SI,
+                force_law: ForceLaw::Newtonian,
+                softening: Softening::None,
+                integrator: Integrator::None,
+                accelerations: HashMap::new(),
+                jerks: HashMap::new(),
+                time_step_criterion: TimeStepCriterion::None,
+                time_steps: HashMap::new(),
+                tree_update: TreeUpdate::Rebuild,
+                tree_kind: TreeKind::KdTree,
+                multipole_order: MultipoleOrder::Monopole,
+                leaf_kernel: LeafKernel::Simd,
+                precision: Precision::Full,
+                solver: Solver::BarnesHut,
+                time: 0.,
+                next_id,
+                paths: HashMap::new(),
             };
         }
 
@@ -63,23 +236,1170 @@ impl<T: AsEntity + Responsive + Clone + Send + Sync> GravTree<T> {
         // and can be made more elegant in the future, if need be.
         // The real root of the tree is therefore tree.root.left
         let mut phantom_parent = Node::new();
-        phantom_parent.left = Some(Box::new(Node::<T>::new_root_node(pts, max_entities)));
+        phantom_parent.left = Some(Box::new(Node::<T, D>::new_root_node(
+            pts,
+            ids,
+            max_entities,
+        )));
         phantom_parent.points = Some(Vec::new());
 
-        GravTree {
+        let mut tree = GravTree {
             root: phantom_parent,
             number_of_entities: size_of_vec,
             time_step,
             max_entities,
             theta,
+            opening_criterion: default_opening_criterion(),
             calculate_collisions,
-        }
+            collision_detection: CollisionDetection::Discrete,
+            merge_policy: None,
+            gravitational_constant: gravitational_constant::SI,
+            force_law: ForceLaw::Newtonian,
+            softening: Softening::None,
+            integrator: Integrator::None,
+            accelerations: HashMap::new(),
+            jerks: HashMap::new(),
+            time_step_criterion: TimeStepCriterion::None,
+            time_steps: HashMap::new(),
+            tree_update: TreeUpdate::Rebuild,
+            tree_kind: TreeKind::KdTree,
+            multipole_order: MultipoleOrder::Monopole,
+            leaf_kernel: LeafKernel::Simd,
+            precision: Precision::Full,
+            solver: Solver::BarnesHut,
+            time: 0.,
+            next_id,
+            paths: HashMap::new(),
+        };
+        tree.record_all_paths();
+        tree
     }
     /// Sets the `theta` value of the simulation.
-    pub fn set_theta(&mut self, theta: f64) {
+    pub fn set_theta(&mut self, theta: Float) {
         self.theta = theta;
     }
 
+    /// Sets the criterion which decides which nodes the tree walk approximates. See
+    /// [OpeningCriterion] for the available criteria.
+    pub fn set_opening_criterion<C: OpeningCriterion + 'static>(&mut self, opening_criterion: C) {
+        self.opening_criterion = Arc::new(opening_criterion);
+    }
+
+    /// Sets the gravitational constant `G` of the simulation. Presets for common systems of
+    /// units are available in the [gravitational_constant](crate::gravitational_constant) module.
+    pub fn set_gravitational_constant(&mut self, gravitational_constant: Float) {
+        self.gravitational_constant = gravitational_constant;
+    }
+
+    /// Sets the force law of the simulation. Use [ForceLaw::Legacy] to keep the behavior of
+    /// simulations written against older versions of bigbang.
+    pub fn set_force_law(&mut self, force_law: ForceLaw) {
+        self.force_law = force_law;
+    }
+
+    /// Sets the gravitational softening of the simulation. See [Softening] for the available
+    /// kernels.
+    pub fn set_softening(&mut self, softening: Softening) {
+        self.softening = softening;
+    }
+
+    /// Sets the built-in integrator used to move entities at every time step. See [Integrator]
+    /// for how this changes what [Responsive::respond] should do.
+    ///
+    /// Panics if the tree uses block time steps and `integrator` does not support them. See
+    /// [TimeStepCriterion] for the integrators which do.
+    pub fn set_integrator(&mut self, integrator: Integrator) {
+        assert!(
+            self.time_step_criterion.supports(integrator),
+            "block time steps require the Leapfrog or VelocityVerlet integrator, not {:?}",
+            integrator
+        );
+        self.integrator = integrator;
+    }
+
+    /// Sets how each entity's own time step is chosen. See [TimeStepCriterion] for the available
+    /// criteria and the integrators they work with.
+    ///
+    /// Panics if `time_step_criterion` needs block time steps and the tree's integrator does not
+    /// support them, so the integrator must be set first.
+    pub fn set_time_step_criterion(&mut self, time_step_criterion: TimeStepCriterion) {
+        assert!(
+            time_step_criterion.supports(self.integrator),
+            "block time steps require the Leapfrog or VelocityVerlet integrator, not {:?}",
+            self.integrator
+        );
+        self.time_step_criterion = time_step_criterion;
+    }
+
+    /// Sets how the nodes are updated after the entities have moved. See [TreeUpdate] for the
+    /// trade-off between refitting the tree and rebuilding it.
+    pub fn set_tree_update(&mut self, tree_update: TreeUpdate) {
+        self.tree_update = tree_update;
+    }
+
+    /// Sets how the tree is built from its entities, and rebuilds it straight away. This is best
+    /// called right after creating the tree. See [TreeKind] for the available kinds of tree.
+    pub fn set_tree_kind(&mut self, tree_kind: TreeKind) {
+        self.tree_kind = tree_kind;
+        let max_entities = self.max_entities;
+        // The entities may be reordered, but the values stored for them are kept by id.
+        if let Some(node) = &mut self.root.left {
+            node.rebuild(max_entities, tree_kind);
+        }
+        self.record_all_paths();
+    }
+
+    /// Sets how many terms of the multipole expansion are used when a distant node is approximated
+    /// as a single body. See [MultipoleOrder] for how this trades off against `theta`.
+    pub fn set_multipole_order(&mut self, multipole_order: MultipoleOrder) {
+        self.multipole_order = multipole_order;
+    }
+
+    /// Sets how the gravity of the entities in a leaf of the tree is summed. The kernels give
+    /// identical results, so this is only useful for checking that they do, with
+    /// [Validate](LeafKernel::Validate). See [LeafKernel].
+    pub fn set_leaf_kernel(&mut self, leaf_kernel: LeafKernel) {
+        self.leaf_kernel = leaf_kernel;
+    }
+
+    /// Sets the precision in which the gravity of the entities in a leaf of the tree is summed.
+    /// [Mixed](Precision::Mixed) sums twice as many entities at a time, at the cost of a relative
+    /// error of around `1e-7` in each leaf's sum. See [Precision].
+    pub fn set_precision(&mut self, precision: Precision) {
+        self.precision = precision;
+    }
+
+    /// Sets how the tree decides which entities collide in a time step.
+    /// [Continuous](CollisionDetection::Continuous) also catches entities which would pass
+    /// through each other within a single time step. See [CollisionDetection].
+    pub fn set_collision_detection(&mut self, collision_detection: CollisionDetection) {
+        self.collision_detection = collision_detection;
+    }
+
+    /// Sets how colliding entities are merged into a single entity at the end of each time step.
+    /// This can be any function or closure which builds the merged entity, or a custom
+    /// [MergePolicy] which also decides which colliding entities merge.
+    pub fn set_merge_policy<M: MergePolicy<T> + 'static>(&mut self, merge_policy: M) {
+        self.merge_policy = Some(Arc::new(merge_policy));
+    }
+
+    /// Sets the algorithm used to calculate accelerations. See [Solver] for when the fast
+    /// multipole method is used.
+    pub fn set_solver(&mut self, solver: Solver) {
+        self.solver = solver;
+    }
+
+    /// Returns the simulation time, i.e. the sum of all time steps taken so far.
+    pub fn time(&self) -> Float {
+        self.time
+    }
+
+    /// Returns the time step that every entity took at the end of the last time step. Without
+    /// block time steps, this is the tree's time step for every entity. The time steps are in the
+    /// same order as the entities returned by [as_vec](GravTree::as_vec).
+    pub fn time_steps(&self) -> Vec<Float> {
+        stored(&self.time_steps, &self.ids())
+            .unwrap_or_else(|| vec![self.time_step; self.number_of_entities])
+    }
+
+    /// Bundles up the settings which the tree walk needs to calculate accelerations.
+    fn force_parameters(&self) -> ForceParameters<'_> {
+        ForceParameters {
+            theta: self.theta,
+            opening_criterion: &*self.opening_criterion,
+            previous_acceleration: None,
+            gravitational_constant: self.gravitational_constant,
+            force_law: self.force_law,
+            softening: self.softening,
+            calculate_jerk: self.integrator == Integrator::Hermite,
+            multipole_order: self.multipole_order,
+            leaf_kernel: self.leaf_kernel,
+            precision: self.precision,
+        }
+    }
+
+    /// Returns an iterator over references to all entities in the tree, in the same order as
+    /// [as_vec](GravTree::as_vec) returns them.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let mut points = Vec::with_capacity(self.number_of_entities);
+        self.root.collect_points(&mut points);
+        points.into_iter()
+    }
+
+    /// Returns the id of every entity in the tree, in the same order as [as_vec](GravTree::as_vec)
+    /// returns the entities. The entities passed to [new](GravTree::new) are given the ids
+    /// `EntityId(0)`, `EntityId(1)`, and so on, in the order they were passed, unless they are
+    /// given their own ids with [new_with_ids](GravTree::new_with_ids). The tree orders its
+    /// entities by position, so this is generally not the order in which they are returned.
+    pub fn ids(&self) -> Vec<EntityId> {
+        let mut ids = Vec::with_capacity(self.number_of_entities);
+        self.root.collect_ids(&mut ids);
+        ids
+    }
+
+    /// Returns the entity with the given id, or `None` if there is no such entity in the tree. The
+    /// tree keeps track of the leaf holding every entity, so this does not search the whole tree.
+    pub fn get(&self, id: EntityId) -> Option<&T> {
+        let leaf = self.root.left.as_ref()?.descendant(self.paths.get(&id)?)?;
+        let index = leaf.ids.iter().position(|other_id| *other_id == id)?;
+        leaf.points.as_ref()?.get(index)
+    }
+
+    /// Applies `update` to the entity with the given id, and then updates the nodes along the way
+    /// to its leaf to account for any change in the entity's position or mass. Returns `false` if
+    /// there is no such entity in the tree.
+    pub fn update<F: FnOnce(&mut T)>(&mut self, id: EntityId, update: F) -> bool {
+        let updated = match (&mut self.root.left, self.paths.get(&id)) {
+            (Some(node), Some(path)) => node.update(id, path, update),
+            _ => false,
+        };
+        if !updated {
+            return false;
+        }
+        // The stored accelerations no longer match the entity's position.
+        self.accelerations.clear();
+        self.jerks.clear();
+        true
+    }
+
+    /// Adds `entity` to the tree and returns its newly assigned id. The aggregate values of the
+    /// nodes along the way to the entity's leaf are updated, rather than rebuilding the tree. If
+    /// the tree is storing accelerations for a built-in integrator, the new entity's acceleration
+    /// is calculated straight away.
+    pub fn insert(&mut self, entity: T) -> EntityId {
+        let id = EntityId(self.next_id);
+        self.next_id += 1;
+        let state = entity.as_entity();
+        let stored = self.accelerations.len() == self.number_of_entities;
+        let mut path = Vec::new();
+        match &mut self.root.left {
+            Some(node) => {
+                let changed = node.insert(entity, id, &mut path, self.max_entities, self.tree_kind);
+                path.truncate(changed);
+            }
+            None => {
+                self.root.left = Some(Box::new(self.tree_kind.build(
+                    vec![entity],
+                    vec![id],
+                    self.max_entities,
+                )));
+                self.root.points = Some(Vec::new());
+            }
+        }
+        self.number_of_entities += 1;
+        self.record_paths(path);
+
+        if stored && self.number_of_entities > 1 {
+            let mut params = self.force_parameters();
+            params.calculate_jerk = self.jerks.len() == self.number_of_entities - 1;
+            let result = state.get_acceleration_without_collisions(id, &self.root, params);
+            let acceleration = result.gravitational_acceleration;
+            let jerk = result.jerk;
+            self.accelerations.insert(id, acceleration);
+            if let Some(jerk) = jerk {
+                self.jerks.insert(id, jerk);
+            }
+        } else {
+            self.accelerations.clear();
+            self.jerks.clear();
+        }
+        if self.time_steps.len() == self.number_of_entities - 1 {
+            self.time_steps.insert(id, self.time_step);
+        }
+        id
+    }
+
+    /// Removes the entity with the given id from the tree and returns it, or returns `None` if
+    /// there is no such entity in the tree. The ids of the other entities do not change. The
+    /// aggregate values of the nodes along the way to the entity's leaf are updated, rather than
+    /// rebuilding the tree.
+    pub fn remove(&mut self, id: EntityId) -> Option<T> {
+        let mut path = self.paths.get(&id)?.clone();
+        let (removed, changed) =
+            self.root
+                .left
+                .as_mut()?
+                .remove(id, &path, self.max_entities, self.tree_kind)?;
+        self.forget(&[id]);
+        path.truncate(changed);
+        self.record_paths(path);
+        Some(removed)
+    }
+
+    /// Removes every entity for which `keep` returns `false`. The aggregate values of the nodes
+    /// are updated in a single pass over the tree, rather than rebuilding it.
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut keep: F) {
+        let mask = self.iter().map(&mut keep).collect::<Vec<_>>();
+        let removed = self
+            .ids()
+            .into_iter()
+            .zip(mask.iter())
+            .filter(|(_, keep)| !**keep)
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        if let Some(node) = &mut self.root.left {
+            node.retain(&mut mask.into_iter(), self.max_entities, self.tree_kind);
+        }
+        self.forget(&removed);
+        self.record_all_paths();
+    }
+
+    /// Updates the tree's bookkeeping after the entities with the given ids have been removed from
+    /// its nodes.
+    fn forget(&mut self, removed: &[EntityId]) {
+        for id in removed {
+            self.accelerations.remove(id);
+            self.jerks.remove(id);
+            self.time_steps.remove(id);
+            self.paths.remove(id);
+        }
+        self.number_of_entities -= removed.len();
+        if self.number_of_entities == 0 {
+            self.root = Node::new();
+        }
+    }
+
+    /// Records the path to the leaf holding every entity below the node at the end of `path`,
+    /// after the structure of the tree has changed there.
+    fn record_paths(&mut self, mut path: Vec<bool>) {
+        if let Some(node) = self
+            .root
+            .left
+            .as_ref()
+            .and_then(|node| node.descendant(&path))
+        {
+            node.record_paths(&mut path, &mut self.paths);
+        }
+    }
+
+    /// Records the path to the leaf holding every entity in the tree afresh, after the whole tree
+    /// may have been rebuilt.
+    fn record_all_paths(&mut self) {
+        self.paths.clear();
+        self.record_paths(Vec::new());
+    }
+
+    /// Returns every entity in the tree along with its id, in the same order as
+    /// [as_vec](GravTree::as_vec).
+    fn entries(&self) -> Vec<(EntityId, &T)> {
+        self.ids().into_iter().zip(self.iter()).collect()
+    }
+
+    /// Returns the gravitational acceleration on every entity in the tree, as calculated by the
+    /// tree's [Solver] with the current `theta`. The accelerations are in the same order as the
+    /// entities returned by [as_vec](GravTree::as_vec).
+    pub fn accelerations(&self) -> Vec<(Float, Float, Float)> {
+        let params = self.force_parameters();
+        if self.uses_fast_multipole(params) {
+            return fmm::accelerations(&self.root, params);
+        }
+        if self.solver == Solver::GroupedBarnesHut {
+            return self
+                .grouped_simulation_results(params)
+                .into_iter()
+                .map(|result| result.gravitational_acceleration)
+                .collect();
+        }
+        self.entries()
+            .par_iter()
+            .map(|(id, x)| {
+                x.as_entity()
+                    .get_acceleration_without_collisions(
+                        *id,
+                        &self.root,
+                        self.entity_parameters(*id, params),
+                    )
+                    .gravitational_acceleration
+            })
+            .collect()
+    }
+
+    /// Returns the ids of every pair of entities in the tree which collide, as decided by the
+    /// tree's [CollisionDetection]. With [Discrete](CollisionDetection::Discrete) detection, these
+    /// are the entities whose centers are no further apart than the sum of their radii, and with
+    /// [Continuous](CollisionDetection::Continuous) detection, those which will be at some point
+    /// during the next time step. Every pair is reported exactly once, with the smaller id first,
+    /// and the pairs are sorted.
+    ///
+    /// Unlike the tree walk for gravity, this never approximates a node, so the result does not
+    /// depend on `theta` or the [OpeningCriterion]. Instead, it only compares the entities in
+    /// nodes whose bounds, grown by the largest radius in them, overlap. This works whether or not
+    /// the tree was built with [CalculateCollisions::Yes], which makes every time step report the
+    /// same collisions to [Responsive::respond].
+    pub fn collisions(&self) -> Vec<(EntityId, EntityId)> {
+        self.colliding_ids(self.sweep_time())
+    }
+
+    /// Returns the ids of every pair of entities which collide within the next `sweep_time`, with
+    /// the smaller id first, sorted. With a `sweep_time` of zero, these are the pairs which
+    /// overlap now.
+    fn colliding_ids(&self, sweep_time: Float) -> Vec<(EntityId, EntityId)> {
+        let mut pairs = broad_phase::collision_pairs(&self.root, sweep_time)
+            .into_iter()
+            .map(|pair| {
+                let (id, other_id) = (pair.first.0, pair.second.0);
+                (Ord::min(id, other_id), Ord::max(id, other_id))
+            })
+            .collect::<Vec<_>>();
+        pairs.sort_unstable();
+        pairs
+    }
+
+    /// Merges every group of overlapping entities which the tree's [MergePolicy] merges into a
+    /// single entity, if the tree has one. The entities are removed and the merged entities are
+    /// inserted, rather than rebuilding the tree.
+    fn merge_collisions(&mut self) {
+        let policy = match &self.merge_policy {
+            Some(policy) => policy.clone(),
+            None => return,
+        };
+        let (groups, merged) = {
+            let entities = self.entries().into_iter().collect::<HashMap<_, _>>();
+            // Only entities which overlap where they are now are merged, even when continuous
+            // detection reports the collisions coming up in the next time step.
+            let pairs = self
+                .colliding_ids(0.)
+                .into_iter()
+                .filter(|(a, b)| policy.merges(entities[a], entities[b]))
+                .collect::<Vec<_>>();
+            let groups = connected_groups(&pairs);
+            let merged = groups
+                .iter()
+                .map(|group| {
+                    let (a, b) = (entities[&group[0]], entities[&group[1]]);
+                    let mut merged = policy.merge(a, b, &a.as_entity().merged_with(&b.as_entity()));
+                    for id in &group[2..] {
+                        let next = entities[id];
+                        let entity = merged.as_entity().merged_with(&next.as_entity());
+                        merged = policy.merge(&merged, next, &entity);
+                    }
+                    merged
+                })
+                .collect::<Vec<_>>();
+            (groups, merged)
+        };
+        if merged.is_empty() {
+            return;
+        }
+        let removed = groups.into_iter().flatten().collect::<HashSet<_>>();
+        let mask = self
+            .ids()
+            .iter()
+            .map(|id| !removed.contains(id))
+            .collect::<Vec<_>>();
+        if let Some(node) = &mut self.root.left {
+            node.retain(&mut mask.into_iter(), self.max_entities, self.tree_kind);
+        }
+        self.forget(&removed.into_iter().collect::<Vec<_>>());
+        self.record_all_paths();
+        // The stored accelerations include the gravity of the removed entities, so they are
+        // calculated afresh rather than kept.
+        self.accelerations.clear();
+        self.jerks.clear();
+        for entity in merged {
+            self.insert(entity);
+        }
+    }
+
+    /// Returns how far ahead entities are swept when looking for collisions, which is the time
+    /// step with [Continuous](CollisionDetection::Continuous) detection and zero otherwise.
+    fn sweep_time(&self) -> Float {
+        match self.collision_detection {
+            CollisionDetection::Discrete => 0.,
+            CollisionDetection::Continuous => self.time_step,
+        }
+    }
+
+    /// Returns `params` with the acceleration on the entity with the given id at the end of the
+    /// last time step filled in, if the opening criterion uses it and it is known.
+    fn entity_parameters<'a>(
+        &self,
+        id: EntityId,
+        params: ForceParameters<'a>,
+    ) -> ForceParameters<'a> {
+        if !params.opening_criterion.uses_previous_acceleration() {
+            return params;
+        }
+        ForceParameters {
+            previous_acceleration: self
+                .accelerations
+                .get(&id)
+                .map(|(ax, ay, az)| Float::sqrt(ax * ax + ay * ay + az * az)),
+            ..params
+        }
+    }
+
+    /// Returns the exact gravitational acceleration on every entity in the tree, found by direct
+    /// summation over every pair of entities. This is O(n²) and is intended as a reference to
+    /// check the tree against, not for running simulations. The accelerations are in the same
+    /// order as the entities returned by [as_vec](GravTree::as_vec).
+    pub fn direct_sum_accelerations(&self) -> Vec<(Float, Float, Float)> {
+        let params = self.force_parameters();
+        let entities = self.iter().map(|x| x.as_entity()).collect::<Vec<_>>();
+        entities
+            .par_iter()
+            .enumerate()
+            .map(|(i, x)| x.get_direct_sum_acceleration(i, &entities, params))
+            .collect()
+    }
+
+    /// Returns, for every entity in the tree, the relative error of the acceleration calculated by
+    /// the tree walk compared to the exact acceleration found by direct summation, i.e.
+    /// `|a_tree - a_direct| / |a_direct|`. If the exact acceleration on an entity is zero, the
+    /// absolute error is reported instead. The errors are in the same order as the entities
+    /// returned by [as_vec](GravTree::as_vec).
+    pub fn acceleration_errors(&self) -> Vec<Float> {
+        self.accelerations()
+            .iter()
+            .zip(self.direct_sum_accelerations().iter())
+            .map(|(tree, direct)| {
+                let (dx, dy, dz) = (tree.0 - direct.0, tree.1 - direct.1, tree.2 - direct.2);
+                let error = Float::sqrt(dx * dx + dy * dy + dz * dz);
+                let magnitude =
+                    Float::sqrt(direct.0 * direct.0 + direct.1 * direct.1 + direct.2 * direct.2);
+                if magnitude == 0. {
+                    error
+                } else {
+                    error / magnitude
+                }
+            })
+            .collect()
+    }
+
+    /// Gets the total number of entities contained by this tree.
+    pub fn get_number_of_entities(&self) -> usize {
+        self.number_of_entities
+    }
+
+    /// Returns whether the fast multipole method can be used to calculate accelerations with
+    /// `params`. See [Solver::FastMultipole] for the cases where it falls back to Barnes-Hut.
+    fn uses_fast_multipole(&self, params: ForceParameters) -> bool {
+        self.solver == Solver::FastMultipole
+            && params.force_law == ForceLaw::Newtonian
+            && !params.calculate_jerk
+            && !matches!(params.softening, Softening::Plummer(_))
+    }
+
+    /// Calculates the gravitational acceleration on every entity with the tree's [Solver], along
+    /// with their collisions if the tree calculates them. The results are in the same order as the
+    /// entities returned by [as_vec](GravTree::as_vec).
+    fn simulation_results(&self, params: ForceParameters) -> Vec<SimulationResult<'_, T>> {
+        let entries = self.entries();
+        let mut results = if self.solver == Solver::GroupedBarnesHut {
+            self.grouped_simulation_results(params)
+        } else if self.uses_fast_multipole(params) {
+            fmm::accelerations(&self.root, params)
+                .into_iter()
+                .map(|acceleration| SimulationResult {
+                    collisions: Vec::new(),
+                    gravitational_acceleration: acceleration,
+                    jerk: None,
+                })
+                .collect()
+        } else {
+            entries
+                .par_iter()
+                .map(|(id, x)| {
+                    let params = self.entity_parameters(*id, params);
+                    x.as_entity()
+                        .get_acceleration_without_collisions(*id, &self.root, params)
+                })
+                .collect()
+        };
+        if let CalculateCollisions::Yes = self.calculate_collisions {
+            let mut contacts = self.contacts_by_id();
+            for ((id, _), result) in entries.iter().zip(results.iter_mut()) {
+                result.collisions = contacts.remove(id).unwrap_or_default();
+            }
+        }
+        results
+    }
+
+    /// Calculates the gravitational acceleration on every entity by walking the tree once for
+    /// every leaf. The results are in the same order as the entities returned by
+    /// [as_vec](GravTree::as_vec), and hold no collisions.
+    fn grouped_simulation_results(&self, params: ForceParameters) -> Vec<SimulationResult<'_, T>> {
+        group_walk::simulation_results(&self.root, |id| self.entity_parameters(id, params))
+    }
+
+    /// Returns the collisions of every entity, by id, from a single
+    /// [broad phase](GravTree::collisions) over the whole tree. Entities which collide with
+    /// nothing are left out.
+    fn contacts_by_id(&self) -> HashMap<EntityId, Vec<Contact<'_, T>>> {
+        let mut contacts = HashMap::<_, Vec<_>>::new();
+        for pair in broad_phase::collision_pairs(&self.root, self.sweep_time()) {
+            let ((id, x), (other_id, other)) = (pair.first, pair.second);
+            contacts
+                .entry(id)
+                .or_default()
+                .push(pair.contact.with(other));
+            contacts
+                .entry(other_id)
+                .or_default()
+                .push(pair.contact.reversed().with(x));
+        }
+        contacts
+    }
+
+    /// Recalculates the aggregate values of every node after entities have been moved in place,
+    /// keeping the structure of the tree and the order of its entities.
+    fn refit(&mut self) {
+        if let Some(node) = &mut self.root.left {
+            node.refit();
+        }
+    }
+
+    /// Updates the nodes after entities have been moved in place, as chosen by the tree's
+    /// [TreeUpdate]. Unlike [refit](GravTree::refit), this may change the order of the entities.
+    fn update_nodes(&mut self) {
+        let (max_entities, kind) = (self.max_entities, self.tree_kind);
+        let rebuilt = match &mut self.root.left {
+            Some(node) => match self.tree_update {
+                TreeUpdate::Rebuild => {
+                    node.rebuild(max_entities, kind);
+                    true
+                }
+                TreeUpdate::Refit(max_overlap) => {
+                    node.refit_or_rebuild(max_overlap, max_entities, kind)
+                }
+            },
+            None => false,
+        };
+        if rebuilt {
+            self.record_all_paths();
+        }
+    }
+
+    /// Returns a tree with the same structure and settings as this one, with its entities replaced
+    /// by `pts`, in traversal order. The stored accelerations and time steps are not copied. If
+    /// the replacements have moved, the new tree must be refit.
+    fn with_points(&self, pts: Vec<T>) -> GravTree<T, D> {
+        GravTree {
+            root: self.root.with_points(&mut pts.into_iter()),
+            number_of_entities: self.number_of_entities,
+            time_step: self.time_step,
+            max_entities: self.max_entities,
+            theta: self.theta,
+            opening_criterion: self.opening_criterion.clone(),
+            calculate_collisions: self.calculate_collisions,
+            collision_detection: self.collision_detection,
+            merge_policy: self.merge_policy.clone(),
+            gravitational_constant: self.gravitational_constant,
+            force_law: self.force_law,
+            softening: self.softening,
+            integrator: self.integrator,
+            accelerations: HashMap::new(),
+            jerks: HashMap::new(),
+            time_step_criterion: self.time_step_criterion,
+            time_steps: HashMap::new(),
+            tree_update: self.tree_update,
+            tree_kind: self.tree_kind,
+            multipole_order: self.multipole_order,
+            leaf_kernel: self.leaf_kernel,
+            precision: self.precision,
+            solver: self.solver,
+            time: self.time,
+            next_id: self.next_id,
+            paths: self.paths.clone(),
+        }
+    }
+
+    /// Returns a tree containing `pts`, which are this tree's entities after being moved, in
+    /// traversal order. The new tree is either built from scratch or refit, as chosen by the
+    /// tree's [TreeUpdate].
+    fn moved(&self, pts: Vec<T>) -> GravTree<T, D> {
+        let mut tree = self.with_points(pts);
+        tree.update_nodes();
+        tree
+    }
+
+    /// Advances the simulation by one time step in place, leaving it entirely to
+    /// [Responsive::respond] to move the entities.
+    fn responsive_time_step_mut(&mut self) {
+        let params = self.force_parameters();
+        let results = self.simulation_results(params);
+        let accelerations = self.kept_accelerations(&results);
+        let responded = self
+            .iter()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .zip(results)
+            .map(|(x, result)| x.respond(result, self.time_step))
+            .collect::<Vec<_>>();
+        self.root.replace_points(&mut responded.into_iter());
+        self.update_nodes();
+        self.accelerations = accelerations;
+    }
+
+    /// Returns the accelerations in `results`, by id, if the tree keeps them without an
+    /// integrator. Otherwise, returns an empty map.
+    fn kept_accelerations(
+        &self,
+        results: &[SimulationResult<T>],
+    ) -> HashMap<EntityId, (Float, Float, Float)> {
+        if !self.opening_criterion.uses_previous_acceleration() {
+            return HashMap::new();
+        }
+        self.ids()
+            .into_iter()
+            .zip(
+                results
+                    .iter()
+                    .map(|result| result.gravitational_acceleration),
+            )
+            .collect()
+    }
+
+    /// Advances the simulation by one time step, the same as [time_step](GravTree::time_step), but
+    /// updates this tree in place instead of building a new one. The entities are moved in place
+    /// rather than copied, so this does not need them to be `Clone`, and with [TreeUpdate::Refit]
+    /// the nodes are kept as well, so only the parts of the tree which have become poor fits are
+    /// reallocated.
+    pub fn time_step_mut(&mut self) {
+        if self.number_of_entities == 0 {
+            self.time += self.time_step;
+            return;
+        }
+        if self.time_step_criterion != TimeStepCriterion::None {
+            self.block_time_step_mut(self.time_step);
+        } else {
+            match self.integrator {
+                Integrator::None => self.responsive_time_step_mut(),
+                Integrator::Leapfrog | Integrator::VelocityVerlet => {
+                    self.integrated_time_step_mut()
+                }
+                Integrator::Hermite => self.hermite_time_step_mut(),
+                Integrator::RungeKutta4 => self.runge_kutta_time_step_mut(),
+            }
+        }
+        self.time += self.time_step;
+        self.merge_collisions();
+    }
+
+    /// Moves every entity in the tree in place, in traversal order, by applying `step` to it as an
+    /// [Entity] along with the matching value from `values`. The nodes are not updated, so they
+    /// must be refit or rebuilt before they are used again.
+    fn move_entities<V: Sync>(&mut self, values: &[V], step: impl Fn(&mut Entity, &V) + Sync) {
+        let mut points = Vec::with_capacity(self.number_of_entities);
+        self.root.collect_points_mut(&mut points);
+        points
+            .into_par_iter()
+            .zip(values.par_iter())
+            .for_each(|(x, value)| {
+                let mut entity = x.as_entity();
+                step(&mut entity, value);
+                x.set_entity(&entity);
+            });
+    }
+
+    /// Passes every entity to [Responsive::respond] with the given acceleration and jerk, and with
+    /// its collisions if the tree calculates them, and puts the responses back in place. `ids`,
+    /// `accelerations` and `jerks` are in traversal order. The nodes are not updated afterwards.
+    fn respond_mut(
+        &mut self,
+        ids: &[EntityId],
+        accelerations: &[(Float, Float, Float)],
+        jerks: Option<&[(Float, Float, Float)]>,
+    ) {
+        let mut contacts = if let CalculateCollisions::Yes = self.calculate_collisions {
+            // The entities have been moved in place, so the nodes must fit them again.
+            self.refit();
+            self.contacts_by_id()
+        } else {
+            HashMap::new()
+        };
+        let time_step = self.time_step;
+        let responded = self
+            .iter()
+            .zip(ids)
+            .enumerate()
+            .map(|(i, (x, id))| {
+                let result = SimulationResult {
+                    collisions: contacts.remove(id).unwrap_or_default(),
+                    gravitational_acceleration: accelerations[i],
+                    jerk: jerks.map(|jerks| jerks[i]),
+                };
+                (x, result)
+            })
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(x, result)| x.respond(result, time_step))
+            .collect::<Vec<_>>();
+        self.root.replace_points(&mut responded.into_iter());
+    }
+
+    /// Returns the gravitational acceleration and jerk on every entity in the tree, without looking
+    /// for collisions, in traversal order. `ids` are the ids of the entities in the same order.
+    fn accelerations_and_jerks(&self, ids: &[EntityId]) -> (Vec<Vector>, Vec<Vector>) {
+        let mut params = self.force_parameters();
+        params.calculate_jerk = true;
+        self.iter()
+            .zip(ids)
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(x, id)| {
+                let params = self.entity_parameters(*id, params);
+                let result = x
+                    .as_entity()
+                    .get_acceleration_without_collisions(*id, &self.root, params);
+                (
+                    result.gravitational_acceleration,
+                    result.jerk.expect("the jerk was requested"),
+                )
+            })
+            .unzip()
+    }
+
+    /// Replaces the accelerations stored for the next time step with `accelerations`, which are
+    /// for the entities with the ids `ids`, in the same order.
+    fn store_accelerations(&mut self, ids: &[EntityId], accelerations: &[(Float, Float, Float)]) {
+        self.accelerations.clear();
+        self.accelerations
+            .extend(ids.iter().copied().zip(accelerations.iter().copied()));
+    }
+
+    /// Advances the simulation by one time step in place with the built-in integrator. The
+    /// entities are first moved using the accelerations from the end of the last step, then the
+    /// nodes are updated around their new positions and the new accelerations are calculated to
+    /// finish the step.
+    fn integrated_time_step_mut(&mut self) {
+        // Nothing to reuse on the first step, so this is the only time forces are calculated twice.
+        let accelerations =
+            stored(&self.accelerations, &self.ids()).unwrap_or_else(|| self.accelerations());
+        let (integrator, time_step) = (self.integrator, self.time_step);
+        self.move_entities(&accelerations, |entity, acceleration| {
+            integrator.begin_step(entity, *acceleration, time_step)
+        });
+        self.update_nodes();
+
+        // Updating the nodes may have reordered the entities.
+        let ids = self.ids();
+        let accelerations = self.accelerations();
+        self.move_entities(&accelerations, |entity, acceleration| {
+            integrator.end_step(entity, *acceleration, time_step)
+        });
+        self.respond_mut(&ids, &accelerations, None);
+        // Responding to collisions only moves entities a little, so refitting is enough here.
+        self.refit();
+        self.store_accelerations(&ids, &accelerations);
+    }
+
+    /// Advances the simulation by one time step in place with the Hermite integrator. The
+    /// entities are moved to their predicted positions using the accelerations and jerks from the
+    /// end of the last step, the nodes are refit around the predicted positions to calculate the
+    /// forces there, and the entities are then moved to their corrected positions.
+    fn hermite_time_step_mut(&mut self) {
+        let ids = self.ids();
+        let (accelerations, jerks) =
+            match (stored(&self.accelerations, &ids), stored(&self.jerks, &ids)) {
+                (Some(accelerations), Some(jerks)) => (accelerations, jerks),
+                // Nothing to reuse yet, so this is the only time forces are calculated twice.
+                _ => self.accelerations_and_jerks(&ids),
+            };
+        let time_step = self.time_step;
+        let initial = self
+            .iter()
+            .zip(accelerations.into_iter().zip(jerks))
+            .map(|(x, old)| (x.as_entity(), old))
+            .collect::<Vec<_>>();
+        self.move_entities(&initial, |entity, (_, (acceleration, jerk))| {
+            integrator::hermite_predict(entity, *acceleration, *jerk, time_step)
+        });
+        // Refitting keeps the order of the entities, so they still line up with `initial`.
+        self.refit();
+        let (accelerations, jerks) = self.accelerations_and_jerks(&ids);
+        let corrections = initial
+            .into_iter()
+            .zip(accelerations.iter().copied().zip(jerks.iter().copied()))
+            .collect::<Vec<_>>();
+        self.move_entities(&corrections, |entity, ((initial, old), new)| {
+            *entity = initial.clone();
+            integrator::hermite_correct(entity, *old, *new, time_step);
+        });
+
+        self.respond_mut(&ids, &accelerations, Some(&jerks));
+        self.update_nodes();
+        self.store_accelerations(&ids, &accelerations);
+        self.jerks.clear();
+        self.jerks.extend(ids.into_iter().zip(jerks));
+    }
+
+    /// Advances the simulation by one time step in place with the classic fourth order
+    /// Runge-Kutta integrator. The first stage reuses the accelerations from the end of the last
+    /// step, and for each of the other three stages the entities are moved to their intermediate
+    /// positions and the nodes are refit around them to calculate the accelerations there.
+    fn runge_kutta_time_step_mut(&mut self) {
+        let first_accelerations =
+            stored(&self.accelerations, &self.ids()).unwrap_or_else(|| self.accelerations());
+        let dt = self.time_step;
+        let initial = self.iter().map(|x| x.as_entity()).collect::<Vec<_>>();
+        let velocity = |e: &Entity| (e.vx, e.vy, e.vz);
+
+        let second = initial
+            .iter()
+            .zip(first_accelerations.iter())
+            .map(|(e, a)| integrator::runge_kutta_stage(e, velocity(e), *a, dt / 2.))
+            .collect::<Vec<_>>();
+        let second_accelerations = self.stage_accelerations(&second);
+        let third = initial
+            .iter()
+            .zip(second.iter().zip(second_accelerations.iter()))
+            .map(|(e, (s, a))| integrator::runge_kutta_stage(e, velocity(s), *a, dt / 2.))
+            .collect::<Vec<_>>();
+        let third_accelerations = self.stage_accelerations(&third);
+        let fourth = initial
+            .iter()
+            .zip(third.iter().zip(third_accelerations.iter()))
+            .map(|(e, (s, a))| integrator::runge_kutta_stage(e, velocity(s), *a, dt))
+            .collect::<Vec<_>>();
+        let fourth_accelerations = self.stage_accelerations(&fourth);
+
+        let advanced = initial
+            .iter()
+            .enumerate()
+            .map(|(i, e)| {
+                let mut entity = e.clone();
+                integrator::runge_kutta_combine(
+                    &mut entity,
+                    [
+                        velocity(e),
+                        velocity(&second[i]),
+                        velocity(&third[i]),
+                        velocity(&fourth[i]),
+                    ],
+                    [
+                        first_accelerations[i],
+                        second_accelerations[i],
+                        third_accelerations[i],
+                        fourth_accelerations[i],
+                    ],
+                    dt,
+                );
+                entity
+            })
+            .collect::<Vec<_>>();
+        self.move_entities(&advanced, |entity, advanced| *entity = advanced.clone());
+        self.update_nodes();
+
+        // The accelerations at the end of the step are calculated here, so that collisions can
+        // be responded to, and reused as the first stage of the next step. Updating the nodes may
+        // have reordered the entities.
+        let ids = self.ids();
+        let accelerations = self.accelerations();
+        self.respond_mut(&ids, &accelerations, None);
+        self.refit();
+        self.store_accelerations(&ids, &accelerations);
+    }
+
+    /// Returns the accelerations on the entities when they are moved to the positions and
+    /// velocities of `stage`, which must be in traversal order. The entities are left there.
+    fn stage_accelerations(&mut self, stage: &[Entity]) -> Vec<(Float, Float, Float)> {
+        self.move_entities(stage, |entity, stage| *entity = stage.clone());
+        // Refitting keeps the order of the entities, so they still line up with `stage`.
+        self.refit();
+        self.accelerations()
+    }
+
+    /// Advances the simulation in place by `length` with block time steps. Every entity is put on
+    /// the level of the block hierarchy chosen by the time step criterion, and kick-drift-kick
+    /// leapfrog is run on the finest level needed. On every substep, all entities drift, but only
+    /// the entities whose own steps end there have their forces calculated, are kicked, and are
+    /// passed to [Responsive::respond]. Those entities then choose their next level.
+    fn block_time_step_mut(&mut self, length: Float) {
+        let calculate_jerk = self.time_step_criterion.needs_jerk();
+        let n = self.number_of_entities;
+        let ids = self.ids();
+        let stored_jerks = if calculate_jerk {
+            stored(&self.jerks, &ids)
+        } else {
+            Some(vec![Default::default(); n])
+        };
+        let (mut accelerations, mut jerks): (Vec<_>, Vec<_>) =
+            if let (Some(accelerations), Some(jerks)) =
+                (stored(&self.accelerations, &ids), stored_jerks)
+            {
+                (accelerations, jerks)
+            } else if calculate_jerk {
+                self.accelerations_and_jerks(&ids)
+            } else {
+                let accelerations = self.accelerations();
+                (accelerations, vec![Default::default(); n])
+            };
+
+        // Time is counted in ticks of the finest level, so that the ends of the steps on every
+        // level can be compared exactly.
+        let ticks = 1u64 << time_step_criterion::MAX_LEVEL;
+        let tick_length = length / ticks as Float;
+        let (criterion, softening) = (self.time_step_criterion, self.softening);
+        let level = |entity: &Entity, acceleration, jerk| {
+            criterion.level(
+                entity,
+                acceleration,
+                if calculate_jerk { Some(jerk) } else { None },
+                softening,
+                length,
+            )
+        };
+        let mut states = self.iter().map(|x| x.as_entity()).collect::<Vec<_>>();
+        let mut levels = states
+            .iter()
+            .enumerate()
+            .map(|(i, e)| level(e, accelerations[i], jerks[i]))
+            .collect::<Vec<_>>();
+        let mut ends = levels.iter().map(|k| ticks >> k).collect::<Vec<_>>();
+        for (i, state) in states.iter_mut().enumerate() {
+            let dt = (ticks >> levels[i]) as Float * tick_length;
+            integrator::kick(state, accelerations[i], dt / 2.);
+        }
+
+        // The tree is refit in place on every substep, so its entities stay in the same order
+        // as `states` and `ids`.
+        let mut tick = 0;
+        while tick < ticks {
+            let next = *ends.iter().min().expect("there are entities to step");
+            let dt = (next - tick) as Float * tick_length;
+            for state in states.iter_mut() {
+                integrator::drift(state, dt);
+            }
+            self.move_entities(&states, |entity, state| *entity = state.clone());
+            self.refit();
+            tick = next;
+
+            let active = (0..n).filter(|i| ends[*i] == tick).collect::<Vec<_>>();
+            let forces = {
+                let mut params = self.force_parameters();
+                params.calculate_jerk = calculate_jerk;
+                active
+                    .par_iter()
+                    .map(|i| {
+                        let params = self.entity_parameters(ids[*i], params);
+                        let result = states[*i]
+                            .get_acceleration_without_collisions(ids[*i], &self.root, params);
+                        (result.gravitational_acceleration, result.jerk)
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let mut points = Vec::with_capacity(n);
+            self.root.collect_points_mut(&mut points);
+            for (i, (acceleration, _)) in active.iter().zip(forces.iter()) {
+                let dt = (ticks >> levels[*i]) as Float * tick_length;
+                integrator::kick(&mut states[*i], *acceleration, dt / 2.);
+                points[*i].set_entity(&states[*i]);
+            }
+            if let CalculateCollisions::Yes = self.calculate_collisions {
+                // The kicked entities must be found by the nodes' velocities.
+                self.refit();
+            }
+            let responded = {
+                let points = self.iter().collect::<Vec<_>>();
+                let sweep_time = self.sweep_time();
+                active
+                    .par_iter()
+                    .zip(forces.par_iter())
+                    .map(|(i, (acceleration, jerk))| {
+                        let collisions = match self.calculate_collisions {
+                            CalculateCollisions::Yes => {
+                                states[*i].get_collisions(ids[*i], &self.root, sweep_time)
+                            }
+                            CalculateCollisions::No => Vec::new(),
+                        };
+                        let result = SimulationResult {
+                            collisions,
+                            gravitational_acceleration: *acceleration,
+                            jerk: *jerk,
+                        };
+                        let dt = (ticks >> levels[*i]) as Float * tick_length;
+                        points[*i].respond(result, dt)
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            let mut points = Vec::with_capacity(n);
+            self.root.collect_points_mut(&mut points);
+            for ((i, x), (acceleration, jerk)) in active.into_iter().zip(responded).zip(forces) {
+                states[i] = x.as_entity();
+                *points[i] = x;
+                accelerations[i] = acceleration;
+                let jerk = jerk.unwrap_or_default();
+                if calculate_jerk {
+                    jerks[i] = jerk;
+                }
+                if tick == ticks {
+                    continue;
+                }
+                // A longer step can only be taken if it starts on a boundary of its level.
+                let mut k = level(&states[i], acceleration, jerk);
+                while tick % (ticks >> k) != 0 {
+                    k += 1;
+                }
+                levels[i] = k;
+                ends[i] = tick + (ticks >> k);
+                let dt = (ticks >> k) as Float * tick_length;
+                integrator::kick(&mut states[i], acceleration, dt / 2.);
+            }
+        }
+
+        self.store_accelerations(&ids, &accelerations);
+        if calculate_jerk {
+            self.jerks.clear();
+            self.jerks.extend(ids.iter().copied().zip(jerks));
+        }
+        self.time_steps = ids
+            .iter()
+            .zip(levels.iter())
+            .map(|(id, k)| (*id, (ticks >> k) as Float * tick_length))
+            .collect();
+        // The entities have moved over the whole step, and may have been moved by collisions.
+        self.update_nodes();
+    }
+}
+
+impl<T: AsEntity<D> + Responsive + Clone + Send + Sync, const D: usize> GravTree<T, D> {
+    pub fn new(
+        pts: &[T],
+        time_step: Float,
+        max_entities: i32,
+        theta: Float,
+        calculate_collisions: CalculateCollisions,
+    ) -> GravTree<T, D> {
+        GravTree::from_vec(
+            pts.to_vec(),
+            time_step,
+            max_entities,
+            theta,
+            calculate_collisions,
+        )
+    }
+
+    /// Creates a tree containing `pts`, the same as [new](GravTree::new), but gives the entities
+    /// the ids in `ids`, in the same order, rather than `EntityId(0)`, `EntityId(1)`, and so on.
+    /// Entities inserted later are given ids larger than any of these.
+    ///
+    /// Panics if there is not exactly one id for every entity, or if any id is given twice.
+    pub fn new_with_ids(
+        pts: &[T],
+        ids: &[EntityId],
+        time_step: Float,
+        max_entities: i32,
+        theta: Float,
+        calculate_collisions: CalculateCollisions,
+    ) -> GravTree<T, D> {
+        assert_eq!(pts.len(), ids.len(), "every entity needs exactly one id");
+        assert_eq!(
+            ids.iter().collect::<HashSet<_>>().len(),
+            ids.len(),
+            "every entity needs a different id"
+        );
+        GravTree::from_parts(
+            pts.to_vec(),
+            ids.to_vec(),
+            time_step,
+            max_entities,
+            theta,
+            calculate_collisions,
+        )
+    }
+
     /// Traverses the tree and returns a vector of all entities in the tree.
     pub fn as_vec(&self) -> Vec<T> {
         let node = self.root.clone();
@@ -100,10 +1420,6 @@ impl<T: AsEntity + Responsive + Clone + Send + Sync> GravTree<T> {
         }
         to_return
     }
-    /// Gets the total number of entities contained by this tree.
-    pub fn get_number_of_entities(&self) -> usize {
-        self.number_of_entities
-    }
 
     /// This function creates a vector of all entities from the tree and applies gravity to them.
     /// Returns a new GravTree.
@@ -113,33 +1429,129 @@ impl<T: AsEntity + Responsive + Clone + Send + Sync> GravTree<T> {
     // some memory is saved.
     // I am not sure if this will be necessary or very practical in the rust
     // implementation (I would have to implement indexing in my GravTree struct).
-    pub fn time_step(&self) -> GravTree<T> {
-        // TODO currently there is a time when the entities are stored twice.
-        // Store only accelerations perhaps?
-        // First, we extract the entities out into a vector
-        let post_gravity_entity_vec: Vec<T> = self.root.traverse_tree_helper();
-        // Then, we construct a new grav tree after the gravitational acceleration for each
-        // entity has been calculated.
-        GravTree::<T>::new(
-            &post_gravity_entity_vec
-                .par_iter()
-                .map(|x| {
-                    let x_entity = x.as_entity();
-                    let accel = match self.calculate_collisions {
-                        CalculateCollisions::Yes => {
-                            x_entity.get_acceleration_and_collisions(&self.root, self.theta)
-                        }
-                        CalculateCollisions::No => {
-                            x_entity.get_acceleration_without_collisions(&self.root, self.theta)
-                        }
-                    };
-                    x.respond(accel, self.time_step)
-                })
+    pub fn time_step(&self) -> GravTree<T, D> {
+        if self.integrator != Integrator::None
+            || self.time_step_criterion != TimeStepCriterion::None
+        {
+            let mut tree = self.duplicate();
+            tree.time_step_mut();
+            return tree;
+        }
+        let mut tree = self.responsive_time_step();
+        tree.time = self.time + self.time_step;
+        tree.merge_collisions();
+        tree
+    }
+
+    /// Advances the simulation until its time reaches `t`, by taking as many time steps as needed.
+    /// The last step is shortened so that the simulation ends exactly at `t`. If the simulation
+    /// time is already `t` or later, the tree is returned unchanged.
+    pub fn advance_to(&self, t: Float) -> GravTree<T, D> {
+        let mut tree: Option<GravTree<T, D>> = None;
+        loop {
+            let current = tree.as_ref().unwrap_or(self);
+            let remaining = t - current.time;
+            if remaining <= 0. {
+                break;
+            }
+            let mut next = if remaining >= current.time_step {
+                current.time_step()
+            } else if current.time_step_criterion != TimeStepCriterion::None {
+                let mut next = current.duplicate();
+                next.block_time_step_mut(remaining);
+                next.merge_collisions();
+                next
+            } else {
+                // Take a single step with a shortened copy of the tree.
+                let mut shortened = current.duplicate();
+                shortened.time_step = remaining;
+                let mut next = shortened.time_step();
+                next.time_step = current.time_step;
+                next
+            };
+            // Rounding could otherwise leave a vanishingly small step to take.
+            if remaining <= current.time_step * (1. + LAST_STEP_TOLERANCE) {
+                next.time = t;
+            }
+            tree = Some(next);
+        }
+        tree.unwrap_or_else(|| self.duplicate())
+    }
+
+    /// Advances the simulation by one time step, leaving it entirely to [Responsive::respond] to
+    /// move the entities.
+    fn responsive_time_step(&self) -> GravTree<T, D> {
+        let params = self.force_parameters();
+        let results = self.simulation_results(params);
+        let accelerations = self.kept_accelerations(&results);
+        // We construct a new grav tree after the gravitational acceleration for each entity has
+        // been calculated.
+        let mut tree = self.moved(
+            self.iter()
+                .collect::<Vec<_>>()
+                .into_par_iter()
+                .zip(results)
+                .map(|(x, result)| x.respond(result, self.time_step))
                 .collect::<Vec<_>>(),
-            self.time_step,
-            self.max_entities,
-            self.theta,
-            self.calculate_collisions,
-        )
+        );
+        tree.accelerations = accelerations;
+        tree
+    }
+
+    /// Returns a copy of this tree, including the accelerations and time steps stored from the
+    /// last time step.
+    fn duplicate(&self) -> GravTree<T, D> {
+        let mut tree = self.with_points(self.iter().cloned().collect());
+        tree.accelerations = self.accelerations.clone();
+        tree.jerks = self.jerks.clone();
+        tree.time_steps = self.time_steps.clone();
+        tree
+    }
+}
+
+fn default_opening_criterion() -> Arc<dyn OpeningCriterion> {
+    Arc::new(OpeningAngle)
+}
+
+/// Returns the values stored for the entities with the given ids, in the same order, or `None` if
+/// any of them has no value stored.
+fn stored<V: Copy>(values: &HashMap<EntityId, V>, ids: &[EntityId]) -> Option<Vec<V>> {
+    ids.iter().map(|id| values.get(id).copied()).collect()
+}
+
+/// Returns the groups of ids which are joined together by `pairs`, either directly or through a
+/// chain of other pairs. Each group is sorted, and the groups are in the order of their smallest
+/// ids. Ids which are in no pair are left out.
+fn connected_groups(pairs: &[(EntityId, EntityId)]) -> Vec<Vec<EntityId>> {
+    // Every id which is not the smallest in its group so far points towards a smaller one.
+    let mut parents = HashMap::new();
+    for (a, b) in pairs {
+        let (a, b) = (group_root(&parents, *a), group_root(&parents, *b));
+        if a != b {
+            parents.insert(Ord::max(a, b), Ord::min(a, b));
+        }
+    }
+    let mut groups = BTreeMap::<EntityId, Vec<EntityId>>::new();
+    for id in parents.keys() {
+        groups
+            .entry(group_root(&parents, *id))
+            .or_default()
+            .push(*id);
+    }
+    groups
+        .into_iter()
+        .map(|(root, mut group)| {
+            group.push(root);
+            group.sort_unstable();
+            group
+        })
+        .collect()
+}
+
+/// Returns the smallest id in the group which `id` is in, as recorded in `parents`.
+fn group_root(parents: &HashMap<EntityId, EntityId>, mut id: EntityId) -> EntityId {
+    while let Some(parent) = parents.get(&id) {
+        id = *parent;
     }
+    id
 }
@@ -0,0 +1,18 @@
+//! Preset values of the gravitational constant `G` for common systems of units.
+//! Pass one of these to [`GravTree::set_gravitational_constant`](crate::GravTree::set_gravitational_constant)
+//! to match the units your positions, masses and time step are expressed in.
+
+use crate::Float;
+
+/// The CODATA 2018 value of `G` in SI units (m³ kg⁻¹ s⁻²). This is the default.
+pub const SI: Float = 6.674_30e-11;
+
+/// `G` in N-body (Hénon) units, where the gravitational constant is defined to be 1.
+pub const NBODY: Float = 1.0;
+
+/// `G` in astronomical units, with lengths in AU, masses in solar masses and time in days
+/// (AU³ M☉⁻¹ day⁻²). This is the square of the Gaussian gravitational constant.
+// Written to the full precision of `f64`, which is rounded to the nearest `f32` with the `f32`
+// feature.
+#[allow(clippy::excessive_precision)]
+pub const ASTRONOMICAL: Float = 2.959_122_082_855_911e-4;
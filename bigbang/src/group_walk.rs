@@ -0,0 +1,123 @@
+//! The grouped Barnes-Hut walk. See [Solver::GroupedBarnesHut](crate::Solver::GroupedBarnesHut).
+//!
+//! The entities in a leaf are close together, so the walks for each of them open almost the same
+//! nodes. Instead, the tree is walked once for the whole leaf, and every node is opened unless it
+//! is accepted from wherever in the leaf's bounds an entity could be (Barnes, 1990). The nodes and
+//! leaves which the walk reaches form an interaction list which is then evaluated for every entity
+//! in the leaf.
+use crate::as_entity::AsEntity;
+use crate::entity::{EntityId, ForceParameters, WalkTotals};
+use crate::simulation_result::SimulationResult;
+use crate::Float;
+use crate::Node;
+use either::Right;
+use rayon::prelude::*;
+
+/// A node on a leaf's interaction list.
+enum Interaction<'a, T: AsEntity<D>, const D: usize> {
+    /// A leaf whose entities act on every entity in the group one by one.
+    Leaf(&'a Node<T, D>),
+    /// A node which acts on every entity in the group as a whole.
+    Node(&'a Node<T, D>),
+}
+
+/// Walks the tree under `root` once for every leaf, and returns the gravitational acceleration on
+/// every entity in traversal order, without any collisions. `entity_parameters` gives the
+/// parameters for the entity with the given id.
+pub(crate) fn simulation_results<'a, 'p, T: AsEntity<D> + Sync, const D: usize>(
+    root: &'a Node<T, D>,
+    entity_parameters: impl Fn(EntityId) -> ForceParameters<'p> + Sync,
+) -> Vec<SimulationResult<'a, T>> {
+    let mut leaves = Vec::new();
+    collect_leaves(root, &mut leaves);
+    leaves
+        .par_iter()
+        .flat_map(|leaf| {
+            let params = leaf
+                .ids
+                .iter()
+                .map(|id| entity_parameters(*id))
+                .collect::<Vec<_>>();
+            let mut interactions = Vec::new();
+            interaction_list(leaf, group_parameters(&params), root, &mut interactions);
+            leaf.points
+                .iter()
+                .flatten()
+                .zip(leaf.ids.iter())
+                .zip(params)
+                .map(|((x, id), params)| {
+                    let entity = x.as_entity();
+                    let mut totals = WalkTotals::new();
+                    for interaction in interactions.iter() {
+                        match interaction {
+                            Interaction::Leaf(other) => {
+                                entity.add_leaf(*id, other, params, &mut totals)
+                            }
+                            Interaction::Node(node) => {
+                                entity.add_gravity(Right(*node), params, &mut totals)
+                            }
+                        }
+                    }
+                    totals.into_simulation_result(params)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Appends every non-empty leaf under `node` to `leaves`, in traversal order.
+fn collect_leaves<'a, T: AsEntity<D>, const D: usize>(
+    node: &'a Node<T, D>,
+    leaves: &mut Vec<&'a Node<T, D>>,
+) {
+    for node in node.left.iter().chain(node.right.iter()) {
+        if node.points.is_some() {
+            if !node.ids.is_empty() {
+                leaves.push(node);
+            }
+        } else {
+            collect_leaves(node, leaves);
+        }
+    }
+}
+
+/// Returns the parameters which the group as a whole is walked with, given those of its entities.
+/// The previous acceleration is the smallest of any entity's, as a node accepted for that is
+/// accepted for all of them.
+fn group_parameters<'a>(params: &[ForceParameters<'a>]) -> ForceParameters<'a> {
+    let previous_acceleration = params
+        .iter()
+        .map(|params| params.previous_acceleration)
+        .try_fold(Float::INFINITY, |min, previous| {
+            previous.map(|previous| Float::min(min, previous))
+        });
+    ForceParameters {
+        previous_acceleration,
+        ..params[0]
+    }
+}
+
+/// Recursively walks the children of `node`, appending the nodes which act on the entities in
+/// `group` to `interactions`, in the same order as the walk for a single entity would reach them.
+/// A node is only accepted as a whole if it would be accepted at the nearest point of the group's
+/// bounds, which is at least as close as any entity in the group.
+fn interaction_list<'a, T: AsEntity<D>, const D: usize>(
+    group: &Node<T, D>,
+    params: ForceParameters,
+    node: &'a Node<T, D>,
+    interactions: &mut Vec<Interaction<'a, T, D>>,
+) {
+    for node in node.left.iter().chain(node.right.iter()) {
+        if node.points.is_some() {
+            interactions.push(Interaction::Leaf(node));
+        } else if node.accepted(
+            group.distance_to_bounds(node.center_of_mass),
+            group.distance_between_bounds(node),
+            params,
+        ) {
+            interactions.push(Interaction::Node(node));
+        } else {
+            interaction_list(group, params, node, interactions);
+        }
+    }
+}
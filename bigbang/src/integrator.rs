@@ -0,0 +1,190 @@
+use crate::entity::Entity;
+use crate::Float;
+use serde::{Deserialize, Serialize};
+
+/// Selects how [GravTree](crate::GravTree) advances entities under gravity at every time step.
+///
+/// With [None](Integrator::None), the tree only calculates accelerations and
+/// [respond](crate::Responsive::respond) is entirely responsible for moving each entity. With any
+/// of the built-in integrators, the tree moves entities itself, and `respond` is then only needed
+/// for non-gravitational behavior such as collisions. In that case, the entity passed to `respond`
+/// has already been advanced by the integrator, so `respond` should not apply
+/// `gravitational_acceleration` or move the entity again.
+///
+/// The built-in integrators move entities with
+/// [AsEntity::set_entity](crate::AsEntity::set_entity), which must be implemented to use them.
+/// They reuse the accelerations calculated at the end of the previous time step, so
+/// [Leapfrog](Integrator::Leapfrog), [VelocityVerlet](Integrator::VelocityVerlet) and
+/// [Hermite](Integrator::Hermite) calculate forces once per step and
+/// [RungeKutta4](Integrator::RungeKutta4) calculates them four times.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Integrator {
+    /// No built-in integration. This is the default.
+    None,
+    /// Kick-drift-kick leapfrog: a half kick of the velocity with the old acceleration, a full
+    /// drift of the position, and a half kick with the new acceleration. Second order and
+    /// symplectic, so long-running orbital simulations do not drift in energy.
+    Leapfrog,
+    /// Velocity Verlet: the position is advanced with a second order Taylor expansion, and the
+    /// velocity with the average of the old and new accelerations. This is algebraically
+    /// equivalent to [Leapfrog](Integrator::Leapfrog) and differs only in rounding.
+    VelocityVerlet,
+    /// The fourth order Hermite predictor-corrector of Makino & Aarseth (1992). Positions and
+    /// velocities are predicted with a Taylor expansion using the acceleration and jerk, then
+    /// corrected using the acceleration and jerk at the predicted positions. The jerk is passed to
+    /// [respond](crate::Responsive::respond) in the [SimulationResult](crate::SimulationResult).
+    Hermite,
+    /// The classic fourth order Runge-Kutta method.
+    RungeKutta4,
+}
+
+impl Integrator {
+    /// Advances `entity` up to the point where the acceleration at its new position is needed,
+    /// given the acceleration at its old position. Only applies to the kick-drift-kick family of
+    /// integrators, [Leapfrog](Integrator::Leapfrog) and [VelocityVerlet](Integrator::VelocityVerlet).
+    pub(crate) fn begin_step(
+        &self,
+        entity: &mut Entity,
+        acceleration: (Float, Float, Float),
+        dt: Float,
+    ) {
+        let (ax, ay, az) = acceleration;
+        match *self {
+            Integrator::Leapfrog => {
+                entity.vx += ax * dt / 2.;
+                entity.vy += ay * dt / 2.;
+                entity.vz += az * dt / 2.;
+                entity.x += entity.vx * dt;
+                entity.y += entity.vy * dt;
+                entity.z += entity.vz * dt;
+            }
+            Integrator::VelocityVerlet => {
+                entity.x += entity.vx * dt + ax * dt * dt / 2.;
+                entity.y += entity.vy * dt + ay * dt * dt / 2.;
+                entity.z += entity.vz * dt + az * dt * dt / 2.;
+                // The old acceleration's half of the velocity update can be applied now, which
+                // saves storing it until the new acceleration is known.
+                entity.vx += ax * dt / 2.;
+                entity.vy += ay * dt / 2.;
+                entity.vz += az * dt / 2.;
+            }
+            _ => unreachable!("begin_step is only used by kick-drift-kick integrators"),
+        }
+    }
+
+    /// Finishes advancing `entity`, given the acceleration at its new position. Only applies to
+    /// the kick-drift-kick family of integrators.
+    pub(crate) fn end_step(
+        &self,
+        entity: &mut Entity,
+        acceleration: (Float, Float, Float),
+        dt: Float,
+    ) {
+        let (ax, ay, az) = acceleration;
+        match *self {
+            Integrator::Leapfrog | Integrator::VelocityVerlet => {
+                entity.vx += ax * dt / 2.;
+                entity.vy += ay * dt / 2.;
+                entity.vz += az * dt / 2.;
+            }
+            _ => unreachable!("end_step is only used by kick-drift-kick integrators"),
+        }
+    }
+}
+
+/// The predictor step of the Hermite integrator. Advances `entity` with a Taylor expansion of its
+/// motion, given its acceleration and jerk.
+pub(crate) fn hermite_predict(
+    entity: &mut Entity,
+    acceleration: (Float, Float, Float),
+    jerk: (Float, Float, Float),
+    dt: Float,
+) {
+    let (ax, ay, az) = acceleration;
+    let (jx, jy, jz) = jerk;
+    let (dt2, dt3) = (dt * dt / 2., dt * dt * dt / 6.);
+    entity.x += entity.vx * dt + ax * dt2 + jx * dt3;
+    entity.y += entity.vy * dt + ay * dt2 + jy * dt3;
+    entity.z += entity.vz * dt + az * dt2 + jz * dt3;
+    entity.vx += ax * dt + jx * dt2;
+    entity.vy += ay * dt + jy * dt2;
+    entity.vz += az * dt + jz * dt2;
+}
+
+/// The corrector step of the Hermite integrator. Advances `entity` from the start of the step,
+/// given the acceleration and jerk at the start of the step (`old`) and at the predicted position
+/// (`new`).
+pub(crate) fn hermite_correct(
+    entity: &mut Entity,
+    old: ((Float, Float, Float), (Float, Float, Float)),
+    new: ((Float, Float, Float), (Float, Float, Float)),
+    dt: Float,
+) {
+    let ((a0x, a0y, a0z), (j0x, j0y, j0z)) = old;
+    let ((a1x, a1y, a1z), (j1x, j1y, j1z)) = new;
+    let dt2 = dt * dt / 12.;
+    let (vx, vy, vz) = (entity.vx, entity.vy, entity.vz);
+    entity.vx += (a0x + a1x) * dt / 2. + (j0x - j1x) * dt2;
+    entity.vy += (a0y + a1y) * dt / 2. + (j0y - j1y) * dt2;
+    entity.vz += (a0z + a1z) * dt / 2. + (j0z - j1z) * dt2;
+    entity.x += (vx + entity.vx) * dt / 2. + (a0x - a1x) * dt2;
+    entity.y += (vy + entity.vy) * dt / 2. + (a0y - a1y) * dt2;
+    entity.z += (vz + entity.vz) * dt / 2. + (a0z - a1z) * dt2;
+}
+
+/// Returns an intermediate stage of the Runge-Kutta integrator: `entity` moved by `dt` with the
+/// given velocity and accelerated by `dt` with the given acceleration.
+pub(crate) fn runge_kutta_stage(
+    entity: &Entity,
+    velocity: (Float, Float, Float),
+    acceleration: (Float, Float, Float),
+    dt: Float,
+) -> Entity {
+    let mut stage = entity.clone();
+    stage.x += velocity.0 * dt;
+    stage.y += velocity.1 * dt;
+    stage.z += velocity.2 * dt;
+    stage.vx += acceleration.0 * dt;
+    stage.vy += acceleration.1 * dt;
+    stage.vz += acceleration.2 * dt;
+    stage
+}
+
+/// Combines the four stages of the Runge-Kutta integrator to advance `entity` by `dt`, given the
+/// velocity and acceleration at each stage.
+pub(crate) fn runge_kutta_combine(
+    entity: &mut Entity,
+    velocities: [(Float, Float, Float); 4],
+    accelerations: [(Float, Float, Float); 4],
+    dt: Float,
+) {
+    let weights = [1., 2., 2., 1.];
+    for ((v, a), weight) in velocities
+        .iter()
+        .zip(accelerations.iter())
+        .zip(weights.iter())
+    {
+        let h = dt * weight / 6.;
+        entity.x += v.0 * h;
+        entity.y += v.1 * h;
+        entity.z += v.2 * h;
+        entity.vx += a.0 * h;
+        entity.vy += a.1 * h;
+        entity.vz += a.2 * h;
+    }
+}
+
+/// Changes the velocity of `entity` by `acceleration` applied for `dt`. Used by block time steps,
+/// which separate leapfrog's kicks and drifts.
+pub(crate) fn kick(entity: &mut Entity, acceleration: (Float, Float, Float), dt: Float) {
+    entity.vx += acceleration.0 * dt;
+    entity.vy += acceleration.1 * dt;
+    entity.vz += acceleration.2 * dt;
+}
+
+/// Moves `entity` along its velocity for `dt`.
+pub(crate) fn drift(entity: &mut Entity, dt: Float) {
+    entity.x += entity.vx * dt;
+    entity.y += entity.vy * dt;
+    entity.z += entity.vz * dt;
+}
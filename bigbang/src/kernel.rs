@@ -0,0 +1,509 @@
+//! The kernel which sums the gravity of every entity in a leaf on a single position. This is the
+//! innermost loop of the tree walk, so the positions and masses of each leaf's entities are kept
+//! in [LeafArrays], one array per value, where they can be loaded several at a time into SIMD
+//! registers.
+use crate::entity::{Entity, ForceLaw};
+use crate::Float;
+use serde::{Deserialize, Serialize};
+
+/// Selects how the gravity of the entities in a leaf of the tree is summed.
+///
+/// The SIMD kernel sums four entities at a time, each in its own lane, and the scalar kernel
+/// follows exactly the same order of operations one entity at a time. The two give
+/// bit-for-bit identical results, so switching between them, or running on a machine without
+/// the SIMD instructions, never changes a simulation.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LeafKernel {
+    /// Use AVX instructions where the processor supports them, and the scalar kernel otherwise.
+    /// This is the default.
+    Simd,
+    /// Always use the scalar kernel.
+    Scalar,
+    /// Run both kernels for every leaf and panic if their results are not bit-for-bit identical.
+    /// This is much slower, and is meant for testing.
+    Validate,
+}
+
+/// Selects the precision in which the gravity of the entities in a leaf of the tree is summed.
+///
+/// With [Mixed](Precision::Mixed), the offsets between each pair of bodies are still found in full
+/// precision, so distant bodies do not lose their relative positions, but everything after that
+/// is calculated in `f32`, eight entities at a time. Each leaf's sum is then added to the total in
+/// full precision. Twice as many entities fit in each register, which pays off when large leaves
+/// dominate the walk, and the relative error it introduces, around `1e-7` per leaf, is usually far
+/// below that of the tree approximation itself. When the `f32` feature is enabled
+/// everything is already calculated in `f32`, and both settings are the same.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Precision {
+    /// Sum every leaf in the precision of [Float]. This is the default.
+    Full,
+    /// Sum every leaf in `f32`, and add the sums up in the precision of [Float].
+    Mixed,
+}
+
+/// The number of entities summed at a time by the SIMD kernel: four `f64`s fill an AVX register.
+const LANES: usize = 4;
+
+/// The number of entities summed at a time by the mixed precision kernel: eight `f32`s fill an AVX
+/// register.
+const MIXED_LANES: usize = 8;
+
+/// Converts `x` to `f32` for the mixed precision kernel.
+#[cfg(not(feature = "f32"))]
+fn to_f32(x: Float) -> f32 {
+    x as f32
+}
+
+/// With the `f32` feature, [Float] is already `f32`, so there is nothing to convert.
+#[cfg(feature = "f32")]
+fn to_f32(x: Float) -> f32 {
+    x
+}
+
+/// A kernel which adds the acceleration of a leaf's entities on a position to a running total.
+type Kernel = fn(&LeafArrays, [Float; 3], [Float; 3], ForceLaw, Float) -> [Float; 3];
+
+/// The positions and masses of the entities in a leaf, in the same order as its points.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub(crate) struct LeafArrays {
+    x: Vec<Float>,
+    y: Vec<Float>,
+    z: Vec<Float>,
+    mass: Vec<Float>,
+}
+
+impl LeafArrays {
+    pub(crate) fn new(entities: &[Entity]) -> LeafArrays {
+        LeafArrays {
+            x: entities.iter().map(|entity| entity.x).collect(),
+            y: entities.iter().map(|entity| entity.y).collect(),
+            z: entities.iter().map(|entity| entity.z).collect(),
+            mass: entities.iter().map(|entity| entity.mass).collect(),
+        }
+    }
+
+    /// Adds the unsoftened acceleration which every entity in the leaf exerts on a body at
+    /// `position` to `total`, and returns the sum. Entities at exactly `position` exert none, so a
+    /// body is not attracted to itself.
+    pub(crate) fn add_acceleration(
+        &self,
+        total: (Float, Float, Float),
+        position: [Float; 3],
+        force_law: ForceLaw,
+        gravitational_constant: Float,
+        kernel: LeafKernel,
+        precision: Precision,
+    ) -> (Float, Float, Float) {
+        // Each pair's acceleration is `d / r³ * G * m`, calculated in the same order as
+        // `get_gravitational_acceleration` does. The other force laws use other powers of `r`, and
+        // the legacy force law ignores `G`.
+        let g = force_law.gravitational_constant(gravitational_constant);
+        let (simd, scalar): (Kernel, Kernel) = match precision {
+            _ if cfg!(feature = "f32") => (LeafArrays::simd, LeafArrays::scalar),
+            Precision::Full => (LeafArrays::simd, LeafArrays::scalar),
+            Precision::Mixed => (LeafArrays::mixed_simd, LeafArrays::mixed_scalar),
+        };
+        let total = [total.0, total.1, total.2];
+        let acceleration = match kernel {
+            LeafKernel::Simd => simd(self, total, position, force_law, g),
+            LeafKernel::Scalar => scalar(self, total, position, force_law, g),
+            LeafKernel::Validate => {
+                let (simd, scalar) = (
+                    simd(self, total, position, force_law, g),
+                    scalar(self, total, position, force_law, g),
+                );
+                assert!(
+                    simd.iter()
+                        .zip(scalar.iter())
+                        .all(|(a, b)| a.to_bits() == b.to_bits()),
+                    "the SIMD kernel gave {:?} where the scalar kernel gave {:?}",
+                    simd,
+                    scalar
+                );
+                scalar
+            }
+        };
+        (acceleration[0], acceleration[1], acceleration[2])
+    }
+
+    /// The number of entities which are summed in full chunks of [LANES]. The rest are added one
+    /// at a time at the end, so that a leaf with fewer entities than that gives exactly the same
+    /// sum as adding up each pair in turn.
+    fn chunked_len(&self) -> usize {
+        self.x.len() / LANES * LANES
+    }
+
+    /// Returns the acceleration which the entity at `index` exerts on a body at `position`.
+    fn pair(
+        &self,
+        index: usize,
+        position: [Float; 3],
+        force_law: ForceLaw,
+        g: Float,
+    ) -> [Float; 3] {
+        let d = [
+            self.x[index] - position[0],
+            self.y[index] - position[1],
+            self.z[index] - position[2],
+        ];
+        let r = Float::sqrt(d[0] * d[0] + d[1] * d[1] + d[2] * d[2]);
+        if r == 0. {
+            return [0.; 3];
+        }
+        let denominator = force_law.denominator(r);
+        let m = self.mass[index];
+        [
+            d[0] / denominator * g * m,
+            d[1] / denominator * g * m,
+            d[2] / denominator * g * m,
+        ]
+    }
+
+    /// Adds the sums of the lanes, and then the entities past the full chunks, to `total`.
+    fn finish(
+        &self,
+        mut total: [Float; 3],
+        lanes: [[Float; LANES]; 3],
+        position: [Float; 3],
+        force_law: ForceLaw,
+        g: Float,
+    ) -> [Float; 3] {
+        if self.chunked_len() > 0 {
+            for (total, lanes) in total.iter_mut().zip(lanes.iter()) {
+                *total += lanes[0] + lanes[1] + lanes[2] + lanes[3];
+            }
+        }
+        for index in self.chunked_len()..self.x.len() {
+            let acceleration = self.pair(index, position, force_law, g);
+            for (total, a) in total.iter_mut().zip(acceleration.iter()) {
+                *total += a;
+            }
+        }
+        total
+    }
+
+    /// The scalar kernel, which sums each lane separately to match the SIMD kernel.
+    fn scalar(
+        &self,
+        total: [Float; 3],
+        position: [Float; 3],
+        force_law: ForceLaw,
+        g: Float,
+    ) -> [Float; 3] {
+        let mut lanes = [[0.; LANES]; 3];
+        for start in (0..self.chunked_len()).step_by(LANES) {
+            for lane in 0..LANES {
+                let acceleration = self.pair(start + lane, position, force_law, g);
+                for (sums, a) in lanes.iter_mut().zip(acceleration.iter()) {
+                    sums[lane] += a;
+                }
+            }
+        }
+        self.finish(total, lanes, position, force_law, g)
+    }
+
+    #[cfg(all(target_arch = "x86_64", not(feature = "f32")))]
+    fn simd(
+        &self,
+        total: [Float; 3],
+        position: [Float; 3],
+        force_law: ForceLaw,
+        g: Float,
+    ) -> [Float; 3] {
+        if is_x86_feature_detected!("avx") {
+            // Safe because the processor has just been checked for AVX.
+            unsafe { self.avx(total, position, force_law, g) }
+        } else {
+            self.scalar(total, position, force_law, g)
+        }
+    }
+
+    #[cfg(not(all(target_arch = "x86_64", not(feature = "f32"))))]
+    fn simd(
+        &self,
+        total: [Float; 3],
+        position: [Float; 3],
+        force_law: ForceLaw,
+        g: Float,
+    ) -> [Float; 3] {
+        self.scalar(total, position, force_law, g)
+    }
+
+    /// The SIMD kernel. Every lane performs the same operations, in the same order, as
+    /// [pair](LeafArrays::pair), and the lanes are then summed like the scalar kernel's.
+    #[cfg(all(target_arch = "x86_64", not(feature = "f32")))]
+    #[target_feature(enable = "avx")]
+    unsafe fn avx(
+        &self,
+        total: [Float; 3],
+        position: [Float; 3],
+        force_law: ForceLaw,
+        g: Float,
+    ) -> [Float; 3] {
+        use std::arch::x86_64::*;
+        let (px, py, pz) = (
+            _mm256_set1_pd(position[0]),
+            _mm256_set1_pd(position[1]),
+            _mm256_set1_pd(position[2]),
+        );
+        let (g_lanes, zero) = (_mm256_set1_pd(g), _mm256_setzero_pd());
+        let (mut ax, mut ay, mut az) = (zero, zero, zero);
+        for start in (0..self.chunked_len()).step_by(LANES) {
+            let dx = _mm256_sub_pd(_mm256_loadu_pd(self.x.as_ptr().add(start)), px);
+            let dy = _mm256_sub_pd(_mm256_loadu_pd(self.y.as_ptr().add(start)), py);
+            let dz = _mm256_sub_pd(_mm256_loadu_pd(self.z.as_ptr().add(start)), pz);
+            let r = _mm256_sqrt_pd(_mm256_add_pd(
+                _mm256_add_pd(_mm256_mul_pd(dx, dx), _mm256_mul_pd(dy, dy)),
+                _mm256_mul_pd(dz, dz),
+            ));
+            let r_squared = _mm256_mul_pd(r, r);
+            let denominator = match force_law.power() {
+                3 => _mm256_mul_pd(r_squared, r),
+                2 => r_squared,
+                _ => r,
+            };
+            let m = _mm256_loadu_pd(self.mass.as_ptr().add(start));
+            // Lanes at zero distance are cleared to exactly zero.
+            let coincident = _mm256_cmp_pd::<_CMP_EQ_OQ>(r, zero);
+            let factor = (denominator, g_lanes, m, coincident);
+            ax = _mm256_add_pd(ax, component(dx, factor));
+            ay = _mm256_add_pd(ay, component(dy, factor));
+            az = _mm256_add_pd(az, component(dz, factor));
+        }
+        let mut lanes = [[0.; LANES]; 3];
+        _mm256_storeu_pd(lanes[0].as_mut_ptr(), ax);
+        _mm256_storeu_pd(lanes[1].as_mut_ptr(), ay);
+        _mm256_storeu_pd(lanes[2].as_mut_ptr(), az);
+        self.finish(total, lanes, position, force_law, g)
+    }
+
+    /// The number of entities which the mixed precision kernel sums in full chunks of
+    /// [MIXED_LANES].
+    fn mixed_chunked_len(&self) -> usize {
+        self.x.len() / MIXED_LANES * MIXED_LANES
+    }
+
+    /// Returns the acceleration which the entity at `index` exerts on a body at `position`, in
+    /// `f32` once the offset between them has been found.
+    fn mixed_pair(
+        &self,
+        index: usize,
+        position: [Float; 3],
+        force_law: ForceLaw,
+        g: f32,
+    ) -> [f32; 3] {
+        let d = [
+            to_f32(self.x[index] - position[0]),
+            to_f32(self.y[index] - position[1]),
+            to_f32(self.z[index] - position[2]),
+        ];
+        let r = f32::sqrt(d[0] * d[0] + d[1] * d[1] + d[2] * d[2]);
+        if r == 0. {
+            return [0.; 3];
+        }
+        let denominator = force_law.denominator(r);
+        let m = to_f32(self.mass[index]);
+        [
+            d[0] / denominator * g * m,
+            d[1] / denominator * g * m,
+            d[2] / denominator * g * m,
+        ]
+    }
+
+    /// Sums the lanes, and then the entities past the full chunks, in `f32`, and adds the leaf's
+    /// sum to `total`.
+    fn mixed_finish(
+        &self,
+        mut total: [Float; 3],
+        lanes: [[f32; MIXED_LANES]; 3],
+        position: [Float; 3],
+        force_law: ForceLaw,
+        g: f32,
+    ) -> [Float; 3] {
+        let mut sums = [0.; 3];
+        if self.mixed_chunked_len() > 0 {
+            for (sum, lanes) in sums.iter_mut().zip(lanes.iter()) {
+                *sum = lanes.iter().fold(0., |sum, lane| sum + lane);
+            }
+        }
+        for index in self.mixed_chunked_len()..self.x.len() {
+            let acceleration = self.mixed_pair(index, position, force_law, g);
+            for (sum, a) in sums.iter_mut().zip(acceleration.iter()) {
+                *sum += a;
+            }
+        }
+        for (total, sum) in total.iter_mut().zip(sums.iter()) {
+            *total += *sum as Float;
+        }
+        total
+    }
+
+    /// The scalar mixed precision kernel, which sums each lane separately to match the SIMD one.
+    fn mixed_scalar(
+        &self,
+        total: [Float; 3],
+        position: [Float; 3],
+        force_law: ForceLaw,
+        g: Float,
+    ) -> [Float; 3] {
+        let g = to_f32(g);
+        let mut lanes = [[0.; MIXED_LANES]; 3];
+        for start in (0..self.mixed_chunked_len()).step_by(MIXED_LANES) {
+            for lane in 0..MIXED_LANES {
+                let acceleration = self.mixed_pair(start + lane, position, force_law, g);
+                for (sums, a) in lanes.iter_mut().zip(acceleration.iter()) {
+                    sums[lane] += a;
+                }
+            }
+        }
+        self.mixed_finish(total, lanes, position, force_law, g)
+    }
+
+    #[cfg(all(target_arch = "x86_64", not(feature = "f32")))]
+    fn mixed_simd(
+        &self,
+        total: [Float; 3],
+        position: [Float; 3],
+        force_law: ForceLaw,
+        g: Float,
+    ) -> [Float; 3] {
+        if is_x86_feature_detected!("avx") {
+            // Safe because the processor has just been checked for AVX.
+            unsafe { self.mixed_avx(total, position, force_law, g) }
+        } else {
+            self.mixed_scalar(total, position, force_law, g)
+        }
+    }
+
+    #[cfg(not(all(target_arch = "x86_64", not(feature = "f32"))))]
+    fn mixed_simd(
+        &self,
+        total: [Float; 3],
+        position: [Float; 3],
+        force_law: ForceLaw,
+        g: Float,
+    ) -> [Float; 3] {
+        self.mixed_scalar(total, position, force_law, g)
+    }
+
+    /// The SIMD mixed precision kernel. The offsets are found four at a time in `f64` and narrowed
+    /// to `f32`, and every lane then performs the same operations as
+    /// [mixed_pair](LeafArrays::mixed_pair).
+    #[cfg(all(target_arch = "x86_64", not(feature = "f32")))]
+    #[target_feature(enable = "avx")]
+    unsafe fn mixed_avx(
+        &self,
+        total: [Float; 3],
+        position: [Float; 3],
+        force_law: ForceLaw,
+        g: Float,
+    ) -> [Float; 3] {
+        use std::arch::x86_64::*;
+        let g = g as f32;
+        let (px, py, pz) = (
+            _mm256_set1_pd(position[0]),
+            _mm256_set1_pd(position[1]),
+            _mm256_set1_pd(position[2]),
+        );
+        let (g_lanes, zero) = (_mm256_set1_ps(g), _mm256_setzero_ps());
+        let (mut ax, mut ay, mut az) = (zero, zero, zero);
+        for start in (0..self.mixed_chunked_len()).step_by(MIXED_LANES) {
+            let dx = offsets(self.x.as_ptr().add(start), px);
+            let dy = offsets(self.y.as_ptr().add(start), py);
+            let dz = offsets(self.z.as_ptr().add(start), pz);
+            let r = _mm256_sqrt_ps(_mm256_add_ps(
+                _mm256_add_ps(_mm256_mul_ps(dx, dx), _mm256_mul_ps(dy, dy)),
+                _mm256_mul_ps(dz, dz),
+            ));
+            let r_squared = _mm256_mul_ps(r, r);
+            let denominator = match force_law.power() {
+                3 => _mm256_mul_ps(r_squared, r),
+                2 => r_squared,
+                _ => r,
+            };
+            let mass = self.mass.as_ptr().add(start);
+            let m = narrow(_mm256_loadu_pd(mass), _mm256_loadu_pd(mass.add(LANES)));
+            let coincident = _mm256_cmp_ps::<_CMP_EQ_OQ>(r, zero);
+            let factor = (denominator, g_lanes, m, coincident);
+            ax = _mm256_add_ps(ax, mixed_component(dx, factor));
+            ay = _mm256_add_ps(ay, mixed_component(dy, factor));
+            az = _mm256_add_ps(az, mixed_component(dz, factor));
+        }
+        let mut lanes = [[0.; MIXED_LANES]; 3];
+        _mm256_storeu_ps(lanes[0].as_mut_ptr(), ax);
+        _mm256_storeu_ps(lanes[1].as_mut_ptr(), ay);
+        _mm256_storeu_ps(lanes[2].as_mut_ptr(), az);
+        self.mixed_finish(total, lanes, position, force_law, g)
+    }
+}
+
+/// Returns the eight offsets from `position` to the values starting at `values`, found in `f64`
+/// and narrowed to `f32`.
+#[cfg(all(target_arch = "x86_64", not(feature = "f32")))]
+#[target_feature(enable = "avx")]
+#[inline]
+unsafe fn offsets(
+    values: *const f64,
+    position: std::arch::x86_64::__m256d,
+) -> std::arch::x86_64::__m256 {
+    use std::arch::x86_64::*;
+    narrow(
+        _mm256_sub_pd(_mm256_loadu_pd(values), position),
+        _mm256_sub_pd(_mm256_loadu_pd(values.add(LANES)), position),
+    )
+}
+
+/// Narrows two registers of four `f64`s to one of eight `f32`s, `low` in the first four lanes.
+#[cfg(all(target_arch = "x86_64", not(feature = "f32")))]
+#[target_feature(enable = "avx")]
+#[inline]
+unsafe fn narrow(
+    low: std::arch::x86_64::__m256d,
+    high: std::arch::x86_64::__m256d,
+) -> std::arch::x86_64::__m256 {
+    use std::arch::x86_64::*;
+    _mm256_insertf128_ps::<1>(
+        _mm256_castps128_ps256(_mm256_cvtpd_ps(low)),
+        _mm256_cvtpd_ps(high),
+    )
+}
+
+/// Returns `d / r³ * G * m` in every `f32` lane, or zero in the lanes which are `coincident`.
+#[cfg(all(target_arch = "x86_64", not(feature = "f32")))]
+#[target_feature(enable = "avx")]
+#[inline]
+unsafe fn mixed_component(
+    d: std::arch::x86_64::__m256,
+    (denominator, g, m, coincident): (
+        std::arch::x86_64::__m256,
+        std::arch::x86_64::__m256,
+        std::arch::x86_64::__m256,
+        std::arch::x86_64::__m256,
+    ),
+) -> std::arch::x86_64::__m256 {
+    use std::arch::x86_64::*;
+    _mm256_andnot_ps(
+        coincident,
+        _mm256_mul_ps(_mm256_mul_ps(_mm256_div_ps(d, denominator), g), m),
+    )
+}
+
+/// Returns `d / r³ * G * m` in every lane, or zero in the lanes which are `coincident`.
+#[cfg(all(target_arch = "x86_64", not(feature = "f32")))]
+#[target_feature(enable = "avx")]
+#[inline]
+unsafe fn component(
+    d: std::arch::x86_64::__m256d,
+    (denominator, g, m, coincident): (
+        std::arch::x86_64::__m256d,
+        std::arch::x86_64::__m256d,
+        std::arch::x86_64::__m256d,
+        std::arch::x86_64::__m256d,
+    ),
+) -> std::arch::x86_64::__m256d {
+    use std::arch::x86_64::*;
+    _mm256_andnot_pd(
+        coincident,
+        _mm256_mul_pd(_mm256_mul_pd(_mm256_div_pd(d, denominator), g), m),
+    )
+}
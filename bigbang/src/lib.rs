@@ -2,21 +2,59 @@
 extern crate either;
 extern crate rayon;
 mod as_entity;
-mod dimension;
+mod broad_phase;
+mod collision_detection;
 mod entity;
+mod entity2;
+mod fmm;
 mod grav_tree;
+mod group_walk;
+mod integrator;
+mod kernel;
+mod merge_policy;
+mod multipole;
 mod node;
+mod opening_criterion;
 mod responsive;
 mod simulation_result;
+mod softening;
+mod solver;
+mod time_step_criterion;
+mod tree_kind;
+mod tree_update;
 mod utilities;
 
-use dimension::Dimension;
+/// The floating point type of every position, velocity, mass and acceleration. This is `f64`,
+/// unless the `f32` feature is enabled, which halves the memory used by the tree and its entities
+/// at the cost of precision.
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+/// The floating point type of every position, velocity, mass and acceleration. This is `f32`
+/// because the `f32` feature is enabled.
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
 use node::Node;
 /*  public-facing entry points */
 pub use as_entity::AsEntity;
-pub use bigbang_derive::AsEntity;
-pub use entity::{CalculateCollisions, Entity};
-pub use grav_tree::GravTree;
+pub use bigbang_derive::{AsEntity, AsEntity2};
+pub use collision_detection::CollisionDetection;
+pub use entity::{CalculateCollisions, Entity, EntityId, ForceLaw};
+pub use entity2::Entity2;
+pub use grav_tree::{GravTree, GravTree2};
+pub use integrator::Integrator;
+pub use kernel::{LeafKernel, Precision};
+pub use merge_policy::MergePolicy;
+pub use multipole::MultipoleOrder;
+pub use opening_criterion::{
+    CandidateNode, OpeningAngle, OpeningCriterion, RelativeForce, SalmonWarren,
+};
 pub use responsive::Responsive;
-pub use simulation_result::SimulationResult;
+pub use simulation_result::{Contact, SimulationResult};
+pub use softening::Softening;
+pub use solver::Solver;
+pub use time_step_criterion::TimeStepCriterion;
+pub use tree_kind::TreeKind;
+pub use tree_update::TreeUpdate;
 pub mod collisions;
+pub mod gravitational_constant;
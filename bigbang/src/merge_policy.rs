@@ -0,0 +1,46 @@
+use crate::entity::Entity;
+
+/// Decides which colliding entities merge into one, and how the merged entity is built from them.
+/// A merge policy is chosen per tree with
+/// [set_merge_policy](crate::GravTree::set_merge_policy), and by default the tree has none, so
+/// that entities are never removed.
+///
+/// At the end of every time step, every pair of entities which overlap, and which this policy
+/// [merges](MergePolicy::merges), is merged. This uses the entities' positions at the end of the
+/// step, even with [Continuous](crate::CollisionDetection::Continuous) collision detection, so
+/// entities which are only about to collide are left alone. Entities which are chained together by such pairs,
+/// such as three entities in a row which each touch the next, all merge into a single entity. The
+/// merged entity is given a new id, and the ids of the entities which it replaces are no longer in
+/// the tree.
+///
+/// Any function or closure which takes the two entities and the merged [Entity] is a merge
+/// policy, which merges every colliding pair. Policies which only merge some pairs, for example
+/// those which collide slowly enough to stay bound to each other, implement this trait directly.
+/// Custom policies cannot be serialized, so a deserialized tree has none.
+pub trait MergePolicy<T>: Send + Sync {
+    /// Returns `true` if the colliding entities `a` and `b` merge. Every colliding pair merges by
+    /// default.
+    fn merges(&self, a: &T, b: &T) -> bool {
+        let _ = (a, b);
+        true
+    }
+
+    /// Returns the entity made by merging `a` and `b`. `merged` is what the merged entity should
+    /// look like to the tree, as found by [merged_with](Entity::merged_with). The returned entity
+    /// must have the mass, radius, position and velocity of `merged` for the merge to conserve
+    /// mass and momentum, and any other fields of `T` can be combined however the simulation
+    /// needs.
+    ///
+    /// When more than two entities merge, they are merged one at a time in the order of their
+    /// ids, so `a` may be the result of an earlier merge.
+    fn merge(&self, a: &T, b: &T, merged: &Entity) -> T;
+}
+
+impl<T, F> MergePolicy<T> for F
+where
+    F: Fn(&T, &T, &Entity) -> T + Send + Sync,
+{
+    fn merge(&self, a: &T, b: &T, merged: &Entity) -> T {
+        self(a, b, merged)
+    }
+}
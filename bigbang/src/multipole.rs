@@ -0,0 +1,178 @@
+use crate::Float;
+use serde::{Deserialize, Serialize};
+
+/// Selects how many terms of the multipole expansion are used when a distant node of the tree is
+/// treated as a single body.
+///
+/// Every node's expansion is taken about its center of mass, so it has no dipole term, and the
+/// first correction to the monopole is the quadrupole. Each extra term makes the approximation of
+/// a node more accurate at a given distance, so the same accuracy can be reached with a larger
+/// `theta`, at the cost of a little more work for every node that is approximated. The
+/// corrections apply to the [Newtonian](crate::ForceLaw::Newtonian) force law wherever it is not
+/// softened, and are not included in the jerk.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum MultipoleOrder {
+    /// Treat a node as a point mass at its center of mass. This is the default.
+    Monopole,
+    /// Add the node's traceless quadrupole moment.
+    Quadrupole,
+    /// Add the node's traceless quadrupole and octupole moments.
+    Octupole,
+}
+
+/// The independent components of a symmetric rank 2 tensor, in the order they are stored.
+const PAIRS: [(usize, usize); 6] = [(0, 0), (0, 1), (0, 2), (1, 1), (1, 2), (2, 2)];
+
+/// The independent components of a symmetric rank 3 tensor, in the order they are stored.
+const TRIPLES: [(usize, usize, usize); 10] = [
+    (0, 0, 0),
+    (0, 0, 1),
+    (0, 0, 2),
+    (0, 1, 1),
+    (0, 1, 2),
+    (0, 2, 2),
+    (1, 1, 1),
+    (1, 1, 2),
+    (1, 2, 2),
+    (2, 2, 2),
+];
+
+fn delta(i: usize, j: usize) -> Float {
+    if i == j {
+        1.
+    } else {
+        0.
+    }
+}
+
+/// The position of every component of a symmetric rank 2 tensor among `PAIRS`.
+const PAIR_INDEX: [[usize; 3]; 3] = [[0, 1, 2], [1, 3, 4], [2, 4, 5]];
+
+/// The position of every component of a symmetric rank 3 tensor among `TRIPLES`.
+const TRIPLE_INDEX: [[[usize; 3]; 3]; 3] = [
+    [[0, 1, 2], [1, 3, 4], [2, 4, 5]],
+    [[1, 3, 4], [3, 6, 7], [4, 7, 8]],
+    [[2, 4, 5], [4, 7, 8], [5, 8, 9]],
+];
+
+/// Returns component `(i, j)` of a symmetric rank 2 tensor stored as its independent components.
+pub(crate) fn pair(tensor: &[Float; 6], i: usize, j: usize) -> Float {
+    tensor[PAIR_INDEX[i][j]]
+}
+
+/// Returns component `(i, j, k)` of a symmetric rank 3 tensor stored as its independent
+/// components.
+fn triple(tensor: &[Float; 10], i: usize, j: usize, k: usize) -> Float {
+    tensor[TRIPLE_INDEX[i][j][k]]
+}
+
+/// Returns the traceless quadrupole moment, `m (3 d dᵀ - |d|² I)`, of a point mass `mass` at an
+/// offset `d` from the center of mass.
+pub(crate) fn point_quadrupole(mass: Float, d: [Float; 3]) -> [Float; 6] {
+    let d_squared = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+    let mut quadrupole = [0.; 6];
+    for (q, (i, j)) in quadrupole.iter_mut().zip(PAIRS.iter()) {
+        *q = mass * (3. * d[*i] * d[*j] - d_squared * delta(*i, *j));
+    }
+    quadrupole
+}
+
+/// Returns the traceless octupole moment, `m (15 dᵢdⱼdₖ - 3|d|² (dᵢδⱼₖ + dⱼδᵢₖ + dₖδᵢⱼ))`, of a
+/// point mass `mass` at an offset `d` from the center of mass.
+pub(crate) fn point_octupole(mass: Float, d: [Float; 3]) -> [Float; 10] {
+    let d_squared = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+    let mut octupole = [0.; 10];
+    for (o, (i, j, k)) in octupole.iter_mut().zip(TRIPLES.iter()) {
+        let (i, j, k) = (*i, *j, *k);
+        *o = mass
+            * (15. * d[i] * d[j] * d[k]
+                - 3. * d_squared * (d[i] * delta(j, k) + d[j] * delta(i, k) + d[k] * delta(i, j)));
+    }
+    octupole
+}
+
+/// Returns the quadrupole moment, about a new center, of a body whose quadrupole moment about its
+/// own center of mass is `quadrupole`. `d` is the offset of its center of mass from the new center.
+pub(crate) fn shift_quadrupole(quadrupole: &[Float; 6], mass: Float, d: [Float; 3]) -> [Float; 6] {
+    let mut shifted = point_quadrupole(mass, d);
+    for (s, q) in shifted.iter_mut().zip(quadrupole.iter()) {
+        *s += q;
+    }
+    shifted
+}
+
+/// Returns the octupole moment, about a new center, of a body whose quadrupole and octupole moments
+/// about its own center of mass are `quadrupole` and `octupole`. `d` is the offset of its center of
+/// mass from the new center. Shifting the traceless moments directly, rather than the raw moments,
+/// means the trace of the second moment is never needed.
+pub(crate) fn shift_octupole(
+    octupole: &[Float; 10],
+    quadrupole: &[Float; 6],
+    mass: Float,
+    d: [Float; 3],
+) -> [Float; 10] {
+    let mut q_d = [0.; 3];
+    for (i, q_d) in q_d.iter_mut().enumerate() {
+        *q_d = (0..3).map(|j| pair(quadrupole, i, j) * d[j]).sum();
+    }
+    let mut shifted = point_octupole(mass, d);
+    for ((s, o), (i, j, k)) in shifted.iter_mut().zip(octupole.iter()).zip(TRIPLES.iter()) {
+        let (i, j, k) = (*i, *j, *k);
+        *s += o + 5.
+            * (d[i] * pair(quadrupole, j, k)
+                + d[j] * pair(quadrupole, i, k)
+                + d[k] * pair(quadrupole, i, j))
+            - 2. * (q_d[i] * delta(j, k) + q_d[j] * delta(i, k) + q_d[k] * delta(i, j));
+    }
+    shifted
+}
+
+/// Returns the acceleration beyond the monopole's exerted by the given moments on a point at an
+/// offset `r` from their center of mass, up to `order`.
+pub(crate) fn acceleration(
+    order: MultipoleOrder,
+    quadrupole: &[Float; 6],
+    octupole: &[Float; 10],
+    r: [Float; 3],
+    gravitational_constant: Float,
+) -> (Float, Float, Float) {
+    if order == MultipoleOrder::Monopole {
+        return (0., 0., 0.);
+    }
+    let mut acceleration = [0.; 3];
+    let r_squared = r[0] * r[0] + r[1] * r[1] + r[2] * r[2];
+    let r_fifth = r_squared * r_squared * Float::sqrt(r_squared);
+    let r_seventh = r_fifth * r_squared;
+
+    // The quadrupole potential is -G (r · Q · r) / (2 |r|⁵).
+    let mut q_r = [0.; 3];
+    for (i, q_r) in q_r.iter_mut().enumerate() {
+        *q_r = (0..3).map(|j| pair(quadrupole, i, j) * r[j]).sum();
+    }
+    let r_q_r = (0..3).map(|i| r[i] * q_r[i]).sum::<Float>();
+    for (i, a) in acceleration.iter_mut().enumerate() {
+        *a += q_r[i] / r_fifth - 2.5 * r_q_r * r[i] / r_seventh;
+    }
+
+    if order == MultipoleOrder::Octupole {
+        // The octupole potential is -G O(r, r, r) / (6 |r|⁷).
+        let r_ninth = r_seventh * r_squared;
+        let mut o_r_r = [0.; 3];
+        for (i, o_r_r) in o_r_r.iter_mut().enumerate() {
+            for j in 0..3 {
+                for k in 0..3 {
+                    *o_r_r += triple(octupole, i, j, k) * r[j] * r[k];
+                }
+            }
+        }
+        let o_r_r_r = (0..3).map(|i| r[i] * o_r_r[i]).sum::<Float>();
+        for (i, a) in acceleration.iter_mut().enumerate() {
+            *a += o_r_r[i] / (2. * r_seventh) - 7. / 6. * o_r_r_r * r[i] / r_ninth;
+        }
+    }
+    (
+        gravitational_constant * acceleration[0],
+        gravitational_constant * acceleration[1],
+        gravitational_constant * acceleration[2],
+    )
+}
@@ -1,8 +1,14 @@
 use crate::as_entity::AsEntity;
-use crate::dimension::Dimension;
-use crate::entity::Entity;
-use crate::utilities::{find_median, max_min_xyz, xyz_distances};
+use crate::entity::{Entity, EntityId, ForceParameters};
+use crate::kernel::LeafArrays;
+use crate::multipole::{self, MultipoleOrder};
+use crate::opening_criterion::CandidateNode;
+use crate::tree_kind::TreeKind;
+use crate::utilities::{bounds, find_median, join};
+use crate::Float;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// This is internal to the tree and is not exposed to the consumer.
 ///
@@ -13,236 +19,948 @@ use serde::{Deserialize, Serialize};
 ///
 /// If a [[Node]] is a leaf, then it contains up to `max_entities` particles, as swell as the aggregate values of these particles.
 /// These aggregate values are the center of mass, the total mass, and max/min values for each dimension.
+///
+/// The tree only splits along the first `D` axes, and only measures its bounds along them, so in
+/// two dimensions it is a 2-d tree and in one dimension a binary tree of intervals.
 #[derive(Serialize, Deserialize, Clone)]
-pub(crate) struct Node<T: AsEntity + Clone> {
-    split_dimension: Option<Dimension>, // Dimension that this node splits at.
-    split_value: f64,                   // Value that this node splits at.
-    pub(crate) left: Option<Box<Node<T>>>, // Left subtree.
-    pub(crate) right: Option<Box<Node<T>>>, // Right subtree.
-    pub(crate) points: Option<Vec<T>>,  // Vector of the points if this node is a Leaf.
-    pub(crate) center_of_mass: (f64, f64, f64), /* The center of mass for this node and it's children all
-                                                 * together. (x, y, z). */
-    total_mass: f64, // Total mass of all entities under this node.
-    r_max: f64,      // Maximum radius that is a child of this node.
-    x_min: f64,
-    x_max: f64,
-    y_min: f64,
-    y_max: f64,
-    z_min: f64,
-    z_max: f64,
+pub(crate) struct Node<T: AsEntity<D>, const D: usize> {
+    split_dimension: Option<usize>, // Axis that this node splits at.
+    split_value: Float,             // Value that this node splits at.
+    pub(crate) left: Option<Box<Node<T, D>>>, // Left subtree.
+    pub(crate) right: Option<Box<Node<T, D>>>, // Right subtree.
+    pub(crate) points: Option<Vec<T>>, // Vector of the points if this node is a Leaf.
+    pub(crate) ids: Vec<EntityId>,  // The ids of the points, in the same order.
+    pub(crate) arrays: LeafArrays,  // The positions and masses of the points, if this is a leaf.
+    pub(crate) center_of_mass: (Float, Float, Float), /* The center of mass for this node and it's children all
+                                                       * together. (x, y, z). */
+    center_of_mass_velocity: (Float, Float, Float), // Mass-weighted mean velocity of this node.
+    pub(crate) total_mass: Float,                   // Total mass of all entities under this node.
+    pub(crate) r_max: Float,                        // Maximum radius that is a child of this node.
+    pub(crate) v_max: Float,                        // Maximum speed that is a child of this node.
+    pub(crate) softening_max: Float, // Maximum softening length that is a child of this node.
+    pub(crate) number_of_points: usize, // Number of entities under this node.
+    pub(crate) quadrupole: [Float; 6], // Traceless quadrupole moment about the center of mass.
+    octupole: [Float; 10],           // Traceless octupole moment about the center of mass.
+    #[serde(with = "crate::utilities::float_array")]
+    min: [Float; D], // The smallest value along each axis of any entity under this node.
+    #[serde(with = "crate::utilities::float_array")]
+    max: [Float; D], // The largest value along each axis of any entity under this node.
+}
+
+/// Returns the length of the vector with the given components.
+fn norm(components: impl Iterator<Item = Float>) -> Float {
+    Float::sqrt(components.fold(0., |sum, component| sum + component * component))
 }
 
-impl<T: AsEntity + Clone> Node<T> {
-    pub(crate) fn new() -> Node<T> {
+impl<T: AsEntity<D>, const D: usize> Node<T, D> {
+    pub(crate) fn new() -> Node<T, D> {
         Node {
             split_dimension: None,
             split_value: 0.0,
             left: None,
             right: None,
             points: None,
+            ids: Vec::new(),
+            arrays: LeafArrays::default(),
             center_of_mass: (0.0, 0.0, 0.0),
+            center_of_mass_velocity: (0.0, 0.0, 0.0),
             total_mass: 0.0,
             r_max: 0.0,
-            x_min: 0.0,
-            x_max: 0.0,
-            y_min: 0.0,
-            y_max: 0.0,
-            z_min: 0.0,
-            z_max: 0.0,
+            v_max: 0.0,
+            softening_max: 0.0,
+            number_of_points: 0,
+            quadrupole: [0.0; 6],
+            octupole: [0.0; 10],
+            min: [0.0; D],
+            max: [0.0; D],
         }
     }
     /// Looks into its own children's maximum and minimum values, setting its own
     /// values accordingly.
     pub(crate) fn set_max_mins(&mut self) {
-        let xmin = f64::min(
-            self.left.as_ref().unwrap().x_min,
-            self.right.as_ref().unwrap().x_min,
-        );
-        let xmax = f64::max(
-            self.left.as_ref().unwrap().x_max,
-            self.right.as_ref().unwrap().x_max,
-        );
-        let ymin = f64::min(
-            self.left.as_ref().unwrap().y_min,
-            self.right.as_ref().unwrap().y_min,
-        );
-        let ymax = f64::max(
-            self.left.as_ref().unwrap().y_max,
-            self.right.as_ref().unwrap().y_max,
-        );
-        let zmin = f64::min(
-            self.left.as_ref().unwrap().z_min,
-            self.right.as_ref().unwrap().z_min,
-        );
-        let zmax = f64::max(
-            self.left.as_ref().unwrap().z_max,
-            self.right.as_ref().unwrap().z_max,
-        );
+        let left = self.left.as_ref().unwrap();
+        let right = self.right.as_ref().unwrap();
+        let mut min = [0.0; D];
+        let mut max = [0.0; D];
+        for axis in 0..D {
+            min[axis] = Float::min(left.min[axis], right.min[axis]);
+            max[axis] = Float::max(left.max[axis], right.max[axis]);
+        }
         let left_r_max = self.left.as_ref().expect("unexpected null node #7").r_max;
         let right_r_max = self.right.as_ref().expect("unexpected null node #8").r_max;
-        self.r_max = f64::max(left_r_max, right_r_max);
-        self.x_min = xmin;
-        self.x_max = xmax;
-        self.y_min = ymin;
-        self.y_max = ymax;
-        self.z_min = zmin;
-        self.z_max = zmax;
+        self.r_max = Float::max(left_r_max, right_r_max);
+        let left_v_max = self.left.as_ref().expect("unexpected null node #7").v_max;
+        let right_v_max = self.right.as_ref().expect("unexpected null node #8").v_max;
+        self.v_max = Float::max(left_v_max, right_v_max);
+        let left_softening_max = self
+            .left
+            .as_ref()
+            .expect("unexpected null node #7")
+            .softening_max;
+        let right_softening_max = self
+            .right
+            .as_ref()
+            .expect("unexpected null node #8")
+            .softening_max;
+        self.softening_max = Float::max(left_softening_max, right_softening_max);
+        self.min = min;
+        self.max = max;
     }
     // Used when treating a node as the sum of its parts in gravity calculations.
     /// Converts a node into an entity with the x, y, z, and mass being derived from the center of
     /// mass and the total mass of the entities it contains.
     pub(crate) fn as_entity(&self) -> Entity {
         // Construct a "super radius" of the largest dimension / 2 + a radius.
-        let (range_x, range_y, range_z) = (
-            self.x_max - self.x_min,
-            self.y_max - self.y_min,
-            self.z_max - self.z_min,
-        );
-        let max_dimension_range = f64::max(range_x, f64::max(range_y, range_z));
-        let super_radius = max_dimension_range / 2f64 + self.r_max;
+        let super_radius = self.max_distance() / 2. + self.r_max;
         // Center of mass is NaN a lot
         Entity {
             x: self.center_of_mass.0,
             y: self.center_of_mass.1,
             z: self.center_of_mass.2,
-            vx: 0.0,
-            vy: 0.0,
-            vz: 0.0,
+            vx: self.center_of_mass_velocity.0,
+            vy: self.center_of_mass_velocity.1,
+            vz: self.center_of_mass_velocity.2,
             mass: self.total_mass,
             radius: super_radius,
+            softening_length: self.softening_max,
         }
     }
 
-    pub(crate) fn max_distance(&self) -> f64 {
-        let x_distance = self.x_max - self.x_min;
-        let y_distance = self.y_max - self.y_min;
-        let z_distance = self.z_max - self.z_min;
-        f64::max(x_distance, f64::max(y_distance, z_distance))
+    /// Returns the acceleration which this node's quadrupole and octupole moments, up to `order`,
+    /// add to that of its monopole on a point at `position`.
+    pub(crate) fn multipole_acceleration(
+        &self,
+        position: (Float, Float, Float),
+        order: MultipoleOrder,
+        gravitational_constant: Float,
+    ) -> (Float, Float, Float) {
+        let r = [
+            position.0 - self.center_of_mass.0,
+            position.1 - self.center_of_mass.1,
+            position.2 - self.center_of_mass.2,
+        ];
+        multipole::acceleration(
+            order,
+            &self.quadrupole,
+            &self.octupole,
+            r,
+            gravitational_constant,
+        )
     }
 
-    /// Traverses tree and returns first child found with points.
-    pub(crate) fn traverse_tree_helper(&self) -> Vec<T> {
-        let mut to_return: Vec<T> = Vec::new();
+    pub(crate) fn max_distance(&self) -> Float {
+        (0..D)
+            .map(|axis| self.max[axis] - self.min[axis])
+            .fold(0., Float::max)
+    }
+
+    /// Returns the distance from the center of mass to the farthest corner of this node's bounds,
+    /// which is the radius of a sphere about the center of mass containing every entity in it.
+    pub(crate) fn radius_about_center_of_mass(&self) -> Float {
+        let center = self.center_of_mass_array();
+        norm(
+            (0..D).map(|axis| {
+                Float::max(center[axis] - self.min[axis], self.max[axis] - center[axis])
+            }),
+        )
+    }
+
+    /// Returns the distance between the center of mass and the center of this node's bounds.
+    pub(crate) fn center_offset(&self) -> Float {
+        let center = self.center_of_mass_array();
+        norm((0..D).map(|axis| center[axis] - (self.min[axis] + self.max[axis]) / 2.))
+    }
+
+    /// Returns the center of mass of this node as an array, so that its first `D` coordinates can
+    /// be taken by index.
+    fn center_of_mass_array(&self) -> [Float; 3] {
+        let (x, y, z) = self.center_of_mass;
+        [x, y, z]
+    }
+
+    /// Returns whether this node may be approximated as a whole, as decided by the tree's
+    /// [OpeningCriterion](crate::OpeningCriterion), for a body which is `distance` from its center
+    /// of mass and `distance_to_bounds` from its bounds. Both the walk for a single entity and the
+    /// walk for a group of entities decide which nodes to open with this.
+    pub(crate) fn accepted(
+        &self,
+        distance: Float,
+        distance_to_bounds: Float,
+        params: ForceParameters,
+    ) -> bool {
+        params.opening_criterion.accepts(&CandidateNode {
+            distance,
+            size: self.max_distance(),
+            center_offset: self.center_offset(),
+            distance_to_bounds,
+            mass: self.total_mass,
+            theta: params.theta,
+            gravitational_constant: params.gravitational_constant,
+            previous_acceleration: params.previous_acceleration,
+        })
+    }
+
+    /// Returns the distance between the nearest points of the bounds of this node and `other`,
+    /// which is zero if they overlap.
+    pub(crate) fn distance_between_bounds(&self, other: &Node<T, D>) -> Float {
+        norm((0..D).map(|axis| {
+            Float::max(
+                0.,
+                Float::max(
+                    self.min[axis] - other.max[axis],
+                    other.min[axis] - self.max[axis],
+                ),
+            )
+        }))
+    }
+
+    /// Returns the distance from `position` to the nearest point of this node's bounds, which is
+    /// zero if `position` is inside them.
+    pub(crate) fn distance_to_bounds(&self, position: (Float, Float, Float)) -> Float {
+        let position = [position.0, position.1, position.2];
+        norm((0..D).map(|axis| {
+            Float::max(
+                0.,
+                Float::max(
+                    self.min[axis] - position[axis],
+                    position[axis] - self.max[axis],
+                ),
+            )
+        }))
+    }
+
+    /// Replaces every entity in the tree, in the same order that
+    /// [traverse_tree_helper](Node::traverse_tree_helper) returns them, with the next entity from
+    /// `entities`. The aggregate values of the nodes are not updated, so the replacements should
+    /// not have moved or changed mass.
+    pub(crate) fn replace_points(&mut self, entities: &mut impl Iterator<Item = T>) {
+        if let Some(node) = &mut self.left {
+            node.replace_points(entities);
+        }
+        if let Some(node) = &mut self.right {
+            node.replace_points(entities);
+        } else {
+            for point in self.points.as_mut().expect("unexpected null node #10") {
+                *point = entities
+                    .next()
+                    .expect("too few entities to replace the tree's");
+            }
+        }
+    }
+
+    /// Appends a reference to every entity in the tree to `points`, in the same order that
+    /// [traverse_tree_helper](Node::traverse_tree_helper) returns them.
+    pub(crate) fn collect_points<'a>(&'a self, points: &mut Vec<&'a T>) {
         if let Some(node) = &self.left {
-            to_return.append(&mut node.traverse_tree_helper());
+            node.collect_points(points);
         }
         if let Some(node) = &self.right {
-            to_return.append(&mut node.traverse_tree_helper());
+            node.collect_points(points);
+        } else if let Some(own_points) = &self.points {
+            points.extend(own_points.iter());
+        }
+    }
+
+    /// Moves every entity out of the tree and appends it to `points` and its id to `ids`, in the
+    /// same order that [traverse_tree_helper](Node::traverse_tree_helper) returns them.
+    pub(crate) fn into_points(self, points: &mut Vec<T>, ids: &mut Vec<EntityId>) {
+        if let Some(node) = self.left {
+            node.into_points(points, ids);
+        }
+        if let Some(node) = self.right {
+            node.into_points(points, ids);
+        } else if let Some(own_points) = self.points {
+            points.extend(own_points);
+            ids.extend(self.ids);
+        }
+    }
+
+    /// Adds `point` to a leaf of this subtree, choosing at every level the child whose bounds
+    /// grow the least. The aggregate values of every node along the way are updated, and any
+    /// node which has become unbalanced is rebuilt. The way taken to the leaf is appended to
+    /// `path`, and the number of steps along it to the highest node whose structure changed is
+    /// returned, as the paths to the entities below that node have to be recorded again.
+    pub(crate) fn insert(
+        &mut self,
+        point: T,
+        id: EntityId,
+        path: &mut Vec<bool>,
+        max_entities: i32,
+        kind: TreeKind,
+    ) -> usize
+    where
+        T: Send + Sync,
+    {
+        if self.right.is_none() {
+            let points = self.points.as_mut().expect("unexpected null node #12");
+            points.push(point);
+            self.ids.push(id);
+            if points.len() as i32 > max_entities {
+                self.rebuild(max_entities, kind);
+            } else {
+                self.set_aggregates_from_points();
+            }
+            return 0;
+        }
+        let entity = point.as_entity();
+        let left = self.left.as_mut().expect("unexpected null node #7");
+        let right = self.right.as_mut().expect("unexpected null node #8");
+        let (left_growth, right_growth) = (left.growth(&entity), right.growth(&entity));
+        let changed = if left_growth < right_growth
+            || (left_growth == right_growth && left.number_of_points <= right.number_of_points)
+        {
+            path.push(false);
+            left.insert(point, id, path, max_entities, kind)
         } else {
-            to_return.append(
-                &mut (self
-                    .points
-                    .as_ref()
-                    .expect("unexpected null node #10")
-                    .clone()),
+            path.push(true);
+            right.insert(point, id, path, max_entities, kind)
+        };
+        self.set_aggregates_from_children();
+        if self.rebalance(max_entities, kind) {
+            0
+        } else {
+            changed + 1
+        }
+    }
+
+    /// Removes the entity with the given id from the leaf at the end of `path`, updating the
+    /// aggregate values of every node along the way. A node left with an empty child is replaced
+    /// by its other child, and any node which has become unbalanced is rebuilt. Returns the
+    /// removed entity, along with the number of steps along `path` to the highest node whose
+    /// structure changed.
+    pub(crate) fn remove(
+        &mut self,
+        id: EntityId,
+        path: &[bool],
+        max_entities: i32,
+        kind: TreeKind,
+    ) -> Option<(T, usize)>
+    where
+        T: Send + Sync,
+    {
+        let (removed, changed) = match path.split_first() {
+            None => {
+                let index = self.ids.iter().position(|other_id| *other_id == id)?;
+                self.ids.remove(index);
+                let point = self.points.as_mut()?.remove(index);
+                (point, 0)
+            }
+            Some((true, rest)) => {
+                let (point, changed) = self.right.as_mut()?.remove(id, rest, max_entities, kind)?;
+                (point, changed + 1)
+            }
+            Some((false, rest)) => {
+                let (point, changed) = self.left.as_mut()?.remove(id, rest, max_entities, kind)?;
+                (point, changed + 1)
+            }
+        };
+        if self.set_aggregates_after_removal(max_entities, kind) {
+            Some((removed, 0))
+        } else {
+            Some((removed, changed))
+        }
+    }
+
+    /// Removes every entity for which the next value of `keep` is `false`, taking the entities in
+    /// traversal order, and updates the aggregate values of the subtree as
+    /// [remove](Node::remove) does.
+    pub(crate) fn retain(
+        &mut self,
+        keep: &mut impl Iterator<Item = bool>,
+        max_entities: i32,
+        kind: TreeKind,
+    ) where
+        T: Send + Sync,
+    {
+        if let (Some(left), Some(right)) = (&mut self.left, &mut self.right) {
+            left.retain(keep, max_entities, kind);
+            right.retain(keep, max_entities, kind);
+        } else {
+            let points = self.points.as_mut().expect("unexpected null node #12");
+            let mask = points
+                .iter()
+                .map(|_| {
+                    keep.next()
+                        .expect("too few values to retain the tree's entities")
+                })
+                .collect::<Vec<_>>();
+            let mut flags = mask.iter();
+            points.retain(|_| *flags.next().unwrap_or(&true));
+            let mut flags = mask.iter();
+            self.ids.retain(|_| *flags.next().unwrap_or(&true));
+        }
+        self.set_aggregates_after_removal(max_entities, kind);
+    }
+
+    /// Updates the aggregate values of this node after entities have been removed from it. An
+    /// internal node with an empty child is replaced by its other child. Returns whether the
+    /// structure of this node changed.
+    fn set_aggregates_after_removal(&mut self, max_entities: i32, kind: TreeKind) -> bool
+    where
+        T: Send + Sync,
+    {
+        if self.right.is_none() {
+            self.number_of_points = self.ids.len();
+            if self.number_of_points > 0 {
+                self.set_aggregates_from_points();
+            }
+            return false;
+        }
+        let left_empty = self
+            .left
+            .as_ref()
+            .expect("unexpected null node #7")
+            .number_of_points
+            == 0;
+        let right_empty = self
+            .right
+            .as_ref()
+            .expect("unexpected null node #8")
+            .number_of_points
+            == 0;
+        if left_empty {
+            *self = *self.right.take().expect("unexpected null node #8");
+            true
+        } else if right_empty {
+            *self = *self.left.take().expect("unexpected null node #7");
+            true
+        } else {
+            self.set_aggregates_from_children();
+            self.rebalance(max_entities, kind)
+        }
+    }
+
+    /// Returns how much larger the bounds of this node would become if `entity` were added to it,
+    /// measured as the increase in the sum of its extents in each dimension.
+    fn growth(&self, entity: &Entity) -> Float {
+        let grow = |min: Float, max: Float, value: Float| {
+            Float::max(max, value) - Float::min(min, value) - (max - min)
+        };
+        entity
+            .position::<D>()
+            .iter()
+            .enumerate()
+            .map(|(axis, value)| grow(self.min[axis], self.max[axis], *value))
+            .sum()
+    }
+
+    /// Rebuilds this node from scratch if one of its children holds more than twice as many
+    /// entities as the other, as in a scapegoat tree. This keeps the tree balanced without
+    /// rebuilding it after every insertion or removal. Only k-d trees are rebalanced, as the other
+    /// kinds of tree split space rather than entities and are not balanced to begin with. Returns
+    /// whether this node was rebuilt.
+    fn rebalance(&mut self, max_entities: i32, kind: TreeKind) -> bool
+    where
+        T: Send + Sync,
+    {
+        if kind != TreeKind::KdTree {
+            return false;
+        }
+        let (left, right) = match (&self.left, &self.right) {
+            (Some(left), Some(right)) => (left.number_of_points, right.number_of_points),
+            _ => return false,
+        };
+        let unbalanced =
+            usize::max(left, right) > 2 * usize::min(left, right) + max_entities as usize;
+        if unbalanced {
+            self.rebuild(max_entities, kind);
+        }
+        unbalanced
+    }
+
+    /// Rebuilds this node and all of its children from the entities they contain. This may change
+    /// the order in which the entities are traversed.
+    pub(crate) fn rebuild(&mut self, max_entities: i32, kind: TreeKind)
+    where
+        T: Send + Sync,
+    {
+        let mut points = Vec::with_capacity(self.number_of_points + 1);
+        let mut ids = Vec::with_capacity(self.number_of_points + 1);
+        std::mem::replace(self, Node::new()).into_points(&mut points, &mut ids);
+        *self = kind.build(points, ids, max_entities);
+    }
+
+    /// Appends the id of every entity in the tree to `ids`, in the same order that
+    /// [traverse_tree_helper](Node::traverse_tree_helper) returns the entities.
+    pub(crate) fn collect_ids(&self, ids: &mut Vec<EntityId>) {
+        if let Some(node) = &self.left {
+            node.collect_ids(ids);
+        }
+        if let Some(node) = &self.right {
+            node.collect_ids(ids);
+        } else {
+            ids.extend(self.ids.iter());
+        }
+    }
+
+    /// Returns the node at the end of `path`, which is followed from this node by taking the right
+    /// child at every `true` and the left child at every `false`.
+    pub(crate) fn descendant(&self, path: &[bool]) -> Option<&Node<T, D>> {
+        match path.split_first() {
+            None => Some(self),
+            Some((true, rest)) => self.right.as_ref()?.descendant(rest),
+            Some((false, rest)) => self.left.as_ref()?.descendant(rest),
+        }
+    }
+
+    /// Records in `paths` the path to the leaf holding each entity in this subtree, given the
+    /// `path` to this node. See [descendant](Node::descendant) for how paths are followed.
+    pub(crate) fn record_paths(
+        &self,
+        path: &mut Vec<bool>,
+        paths: &mut HashMap<EntityId, Vec<bool>>,
+    ) {
+        match (&self.left, &self.right) {
+            (Some(left), Some(right)) => {
+                path.push(false);
+                left.record_paths(path, paths);
+                path.pop();
+                path.push(true);
+                right.record_paths(path, paths);
+                path.pop();
+            }
+            _ => {
+                for id in &self.ids {
+                    paths.insert(*id, path.clone());
+                }
+            }
+        }
+    }
+
+    /// Applies `update` to the entity with the given id in the leaf at the end of `path`, and
+    /// recalculates the aggregate values of every node along the way. Returns `false` if the leaf
+    /// holds no such entity.
+    pub(crate) fn update<F: FnOnce(&mut T)>(
+        &mut self,
+        id: EntityId,
+        path: &[bool],
+        update: F,
+    ) -> bool {
+        let updated = match path.split_first() {
+            None => {
+                let index = match self.ids.iter().position(|other_id| *other_id == id) {
+                    Some(index) => index,
+                    None => return false,
+                };
+                match &mut self.points {
+                    Some(points) => update(&mut points[index]),
+                    None => return false,
+                }
+                self.set_aggregates_from_points();
+                return true;
+            }
+            Some((true, rest)) => match &mut self.right {
+                Some(node) => node.update(id, rest, update),
+                None => false,
+            },
+            Some((false, rest)) => match &mut self.left {
+                Some(node) => node.update(id, rest, update),
+                None => false,
+            },
+        };
+        if updated {
+            self.set_aggregates_from_children();
+        }
+        updated
+    }
+
+    /// Appends a mutable reference to every entity in the tree to `points`, in the same order that
+    /// [traverse_tree_helper](Node::traverse_tree_helper) returns them. If the entities are moved,
+    /// [refit](Node::refit) must be called afterwards.
+    pub(crate) fn collect_points_mut<'a>(&'a mut self, points: &mut Vec<&'a mut T>) {
+        if let Some(node) = &mut self.left {
+            node.collect_points_mut(points);
+        }
+        if let Some(node) = &mut self.right {
+            node.collect_points_mut(points);
+        } else if let Some(own_points) = &mut self.points {
+            points.extend(own_points.iter_mut());
+        }
+    }
+
+    /// Recalculates the aggregate values of this node and all of its children from the entities
+    /// they contain, keeping the structure of the tree and the order of its entities.
+    pub(crate) fn refit(&mut self) {
+        match (&mut self.left, &mut self.right) {
+            (Some(left), Some(right)) => {
+                left.refit();
+                right.refit();
+                self.set_aggregates_from_children();
+            }
+            _ => self.set_aggregates_from_points(),
+        }
+    }
+
+    /// Recalculates the aggregate values of this subtree like [refit](Node::refit), but rebuilds
+    /// any node whose [overlap](Node::overlap) has grown beyond `max_overlap`. Only the parts of
+    /// the tree which have become poor fits for their entities are rebuilt. Returns whether any
+    /// node was rebuilt.
+    pub(crate) fn refit_or_rebuild(
+        &mut self,
+        max_overlap: Float,
+        max_entities: i32,
+        kind: TreeKind,
+    ) -> bool
+    where
+        T: Send + Sync,
+    {
+        match (&mut self.left, &mut self.right) {
+            (Some(left), Some(right)) => {
+                let left_rebuilt = left.refit_or_rebuild(max_overlap, max_entities, kind);
+                let right_rebuilt = right.refit_or_rebuild(max_overlap, max_entities, kind);
+                self.set_aggregates_from_children();
+                if self.overlap() > max_overlap {
+                    self.rebuild(max_entities, kind);
+                    return true;
+                }
+                left_rebuilt || right_rebuilt
+            }
+            _ => {
+                self.set_aggregates_from_points();
+                false
+            }
+        }
+    }
+
+    /// Returns how far the bounds of this node's children overlap along the dimension it was split
+    /// on, as a fraction of this node's extent in that dimension. A freshly built node has no
+    /// overlap. As entities move across the split, the children's bounds grow into each other and
+    /// the tree walk has to open more nodes.
+    pub(crate) fn overlap(&self) -> Float {
+        let (left, right) = match (&self.left, &self.right) {
+            (Some(left), Some(right)) => (left, right),
+            _ => return 0.,
+        };
+        // The children of a node which is not a k-d tree node may be in either order.
+        let overlap = |left_min: Float, left_max: Float, right_min: Float, right_max: Float| {
+            Float::min(left_max, right_max) - Float::max(left_min, right_min)
+        };
+        let (overlap, extent) = match self.split_dimension {
+            Some(axis) => (
+                overlap(
+                    left.min[axis],
+                    left.max[axis],
+                    right.min[axis],
+                    right.max[axis],
+                ),
+                self.max[axis] - self.min[axis],
+            ),
+            None => return 0.,
+        };
+        if overlap <= 0. || extent <= 0. {
+            0.
+        } else {
+            overlap / extent
+        }
+    }
+
+    /// Returns a tree with the same structure and aggregate values as this one, with every entity
+    /// replaced by the next one from `points`, in traversal order. If the replacements have moved,
+    /// the new tree must be [refit](Node::refit).
+    pub(crate) fn with_points(&self, points: &mut impl Iterator<Item = T>) -> Node<T, D> {
+        Node {
+            left: self
+                .left
+                .as_ref()
+                .map(|node| Box::new(node.with_points(points))),
+            right: self
+                .right
+                .as_ref()
+                .map(|node| Box::new(node.with_points(points))),
+            points: self.points.as_ref().map(|own_points| {
+                own_points
+                    .iter()
+                    .map(|_| {
+                        points
+                            .next()
+                            .expect("too few entities to replace the tree's")
+                    })
+                    .collect()
+            }),
+            ids: self.ids.clone(),
+            arrays: self.arrays.clone(),
+            ..*self
+        }
+    }
+
+    /// Sets the aggregate values of a leaf from the entities it contains.
+    fn set_aggregates_from_points(&mut self) {
+        let pts = self.points.as_ref().expect("unexpected null node #11");
+        let entities = pts.iter().map(|x| x.as_entity()).collect::<Vec<Entity>>();
+        self.set_aggregates_from_entities(&entities);
+    }
+
+    /// Sets the aggregate values of a leaf from `entities`, which are its points as entities.
+    fn set_aggregates_from_entities(&mut self, entities: &[Entity]) {
+        // we calculate the center of mass and total mass for each axis and store it as a three-tuple.
+        // This admittedly terse `fold` used to be a for loop. I refactored it for the sake of immutability.
+        // I'm still unsure if this was optimal.
+        let (x_total, y_total, z_total, max_radius, total_mass, max_softening) =
+            // making this iterator parallel negatively impacts performance, at least for
+            // bench_05 and bench_10
+            entities.iter().fold((0.0, 0.0, 0.0, 0.0, 0.0, 0.0), |acc, pt| {
+                (
+                    acc.0 + (pt.x * pt.mass),
+                    acc.1 + (pt.y * pt.mass),
+                    acc.2 + (pt.z * pt.mass),
+                    if acc.3 > pt.radius { acc.3 } else { pt.radius },
+                    acc.4 + pt.mass,
+                    if acc.5 > pt.softening_length { acc.5 } else { pt.softening_length },
+                )
+            });
+
+        let (vx_total, vy_total, vz_total) = entities.iter().fold((0.0, 0.0, 0.0), |acc, pt| {
+            (
+                acc.0 + (pt.vx * pt.mass),
+                acc.1 + (pt.vy * pt.mass),
+                acc.2 + (pt.vz * pt.mass),
+            )
+        });
+
+        let max_speed = entities
+            .iter()
+            .map(|pt| Float::sqrt(pt.vx * pt.vx + pt.vy * pt.vy + pt.vz * pt.vz))
+            .fold(0.0, Float::max);
+
+        let (min, max) = bounds(entities, |entity| entity);
+        self.center_of_mass = (
+            x_total / total_mass,
+            y_total / total_mass,
+            z_total / total_mass,
+        );
+        self.center_of_mass_velocity = (
+            vx_total / total_mass,
+            vy_total / total_mass,
+            vz_total / total_mass,
+        );
+        let (mut quadrupole, mut octupole) = ([0.0; 6], [0.0; 10]);
+        for pt in entities.iter() {
+            let d = [
+                pt.x - self.center_of_mass.0,
+                pt.y - self.center_of_mass.1,
+                pt.z - self.center_of_mass.2,
+            ];
+            let (q, o) = (
+                multipole::point_quadrupole(pt.mass, d),
+                multipole::point_octupole(pt.mass, d),
             );
+            quadrupole
+                .iter_mut()
+                .zip(q.iter())
+                .for_each(|(a, b)| *a += b);
+            octupole.iter_mut().zip(o.iter()).for_each(|(a, b)| *a += b);
         }
-        to_return
+        self.quadrupole = quadrupole;
+        self.octupole = octupole;
+        self.total_mass = total_mass;
+        self.number_of_points = entities.len();
+        self.r_max = max_radius;
+        self.v_max = max_speed;
+        self.softening_max = max_softening;
+        self.min = min;
+        self.max = max;
+        self.arrays = LeafArrays::new(entities);
     }
 
-    /// Takes in a mutable slice of entities and creates a recursive 3d tree structure.
-    pub(crate) fn new_root_node(pts: &[T], max_entities: i32) -> Node<T> {
+    /// Sets the aggregate values of an internal node from those of its children.
+    fn set_aggregates_from_children(&mut self) {
+        let left = self.left.as_ref().expect("unexpected null node #7");
+        let right = self.right.as_ref().expect("unexpected null node #8");
+        // The center of mass is a recursive definition. This finds the average COM for
+        // each node.
+        let left_mass = left.total_mass;
+        let right_mass = right.total_mass;
+        let (left_x, left_y, left_z) = left.center_of_mass;
+        let (right_x, right_y, right_z) = right.center_of_mass;
+        let total_mass = left_mass + right_mass;
+        assert!(total_mass != 0., "invalid mass of 0");
+
+        let (center_x, center_y, center_z) = (
+            ((left_mass * left_x) + (right_mass * right_x)) / total_mass,
+            ((left_mass * left_y) + (right_mass * right_y)) / total_mass,
+            ((left_mass * left_z) + (right_mass * right_z)) / total_mass,
+        );
+        let (left_vx, left_vy, left_vz) = left.center_of_mass_velocity;
+        let (right_vx, right_vy, right_vz) = right.center_of_mass_velocity;
+        self.center_of_mass_velocity = (
+            ((left_mass * left_vx) + (right_mass * right_vx)) / total_mass,
+            ((left_mass * left_vy) + (right_mass * right_vy)) / total_mass,
+            ((left_mass * left_vz) + (right_mass * right_vz)) / total_mass,
+        );
+        // The children's moments are shifted from their own centers of mass to this node's.
+        let (mut quadrupole, mut octupole) = ([0.0; 6], [0.0; 10]);
+        for child in &[left, right] {
+            let d = [
+                child.center_of_mass.0 - center_x,
+                child.center_of_mass.1 - center_y,
+                child.center_of_mass.2 - center_z,
+            ];
+            let q = multipole::shift_quadrupole(&child.quadrupole, child.total_mass, d);
+            let o =
+                multipole::shift_octupole(&child.octupole, &child.quadrupole, child.total_mass, d);
+            quadrupole
+                .iter_mut()
+                .zip(q.iter())
+                .for_each(|(a, b)| *a += b);
+            octupole.iter_mut().zip(o.iter()).for_each(|(a, b)| *a += b);
+        }
+        self.quadrupole = quadrupole;
+        self.octupole = octupole;
+        self.number_of_points = left.number_of_points + right.number_of_points;
+        self.center_of_mass = (center_x, center_y, center_z);
+        self.set_max_mins();
+        self.total_mass = total_mass;
+    }
+
+    /// Creates a leaf containing `pts`, which have the ids `ids`.
+    pub(crate) fn leaf(pts: Vec<T>, ids: Vec<EntityId>) -> Node<T, D> {
+        let mut node = Node::new();
+        node.points = Some(pts);
+        node.ids = ids;
+        node.set_aggregates_from_points();
+        node
+    }
+
+    /// Creates a leaf containing every entity in `entries`, which are the entities with their
+    /// positions, taking them out of it. The positions are reused rather than converted again.
+    pub(crate) fn leaf_from_entries(entries: &mut [(Entity, Option<(T, EntityId)>)]) -> Node<T, D> {
+        let mut entities = Vec::with_capacity(entries.len());
+        let (pts, ids) = entries
+            .iter_mut()
+            .map(|(entity, entry)| {
+                entities.push(std::mem::take(entity));
+                entry.take().expect("every entity is taken once")
+            })
+            .unzip();
+        let mut node = Node::new();
+        node.points = Some(pts);
+        node.ids = ids;
+        node.set_aggregates_from_entities(&entities);
+        node
+    }
+
+    /// Creates an internal node with the given children, which must not overlap. The node is
+    /// split along the dimension in which the gap between its children is widest, halfway across
+    /// the gap.
+    pub(crate) fn internal(left: Node<T, D>, right: Node<T, D>) -> Node<T, D> {
+        let gap = |left_min: Float, left_max: Float, right_min: Float, right_max: Float| {
+            if right_min - left_max >= left_min - right_max {
+                (right_min - left_max, (left_max + right_min) / 2.)
+            } else {
+                (left_min - right_max, (right_max + left_min) / 2.)
+            }
+        };
+        let (split_dimension, (_, split_value)) = (0..D)
+            .map(|axis| {
+                (
+                    axis,
+                    gap(
+                        left.min[axis],
+                        left.max[axis],
+                        right.min[axis],
+                        right.max[axis],
+                    ),
+                )
+            })
+            .fold(
+                None,
+                |widest: Option<(usize, (Float, Float))>, candidate| match widest {
+                    Some(widest) if (widest.1).0 >= (candidate.1).0 => Some(widest),
+                    _ => Some(candidate),
+                },
+            )
+            .expect("there is at least one dimension");
+        let mut node = Node::new();
+        node.split_dimension = Some(split_dimension);
+        node.split_value = split_value;
+        node.left = Some(Box::new(left));
+        node.right = Some(Box::new(right));
+        node.set_aggregates_from_children();
+        node
+    }
+
+    /// Takes in a vector of entities and creates a recursive k-d tree structure. Every internal
+    /// node splits its entities at the median of the dimension in which they are most spread out,
+    /// so the entities are reordered, and each id in `ids` stays with its entity. The two halves
+    /// of large nodes are built in parallel.
+    pub(crate) fn new_root_node(pts: Vec<T>, ids: Vec<EntityId>, max_entities: i32) -> Node<T, D>
+    where
+        T: Send + Sync,
+    {
         // Start and end are probably 0 and pts.len(), respectively.
         let length_of_points = pts.len() as i32;
-        let mut entities = pts.iter().map(|x| x.as_entity()).collect::<Vec<Entity>>();
-        let (xdistance, ydistance, zdistance) = xyz_distances(entities.as_slice());
         // If our current collection is small enough to become a leaf (it has less than
         // max_entities entities)
         if length_of_points <= max_entities {
             // then we convert it into a leaf node.
+            return Node::leaf(pts, ids);
+        }
+        // Every entity is converted once, up front, and the entities and their ids are then
+        // partitioned in place along with their positions.
+        let mut entries = pts
+            .into_par_iter()
+            .zip(ids)
+            .map(|(x, id)| (x.as_entity(), Some((x, id))))
+            .collect::<Vec<_>>();
+        Node::new_kd_node(&mut entries, max_entities)
+    }
 
-            // we calculate the center of mass and total mass for each axis and store it as a three-tuple.
-            // This admittedly terse `fold` used to be a for loop. I refactored it for the sake of immutability.
-            // I'm still unsure if this was optimal.
-            let (x_total, y_total, z_total, max_radius, total_mass) =
-                // making this iterator parallel negatively impacts performance, at least for
-                // bench_05 and bench_10
-                pts.iter().fold((0.0, 0.0, 0.0, 0.0, 0.0), |acc, pt| {
-                    let pt = pt.as_entity();
-                    (
-                        acc.0 + (pt.x * pt.mass),
-                        acc.1 + (pt.y * pt.mass),
-                        acc.2 + (pt.z * pt.mass),
-                        if acc.3 > pt.radius { acc.3 } else { pt.radius },
-                        acc.4 + pt.mass,
-                    )
-                });
-
-            let (x_max, x_min, y_max, y_min, z_max, z_min) = max_min_xyz(&entities);
-            Node {
-                center_of_mass: (
-                    x_total / total_mass,
-                    y_total / total_mass,
-                    z_total / total_mass,
-                ),
-                total_mass,
-                r_max: max_radius,
-                points: Some(pts.to_vec()),
-                left: None,
-                right: None,
-                split_dimension: None,
-                split_value: 0.0,
-                x_max: *x_max,
-                x_min: *x_min,
-                y_max: *y_max,
-                y_min: *y_min,
-                z_max: *z_max,
-                z_min: *z_min,
-            }
+    /// Builds a k-d tree over `entries`, which are the entities with their positions, taking the
+    /// entities out of it.
+    fn new_kd_node(entries: &mut [(Entity, Option<(T, EntityId)>)], max_entities: i32) -> Node<T, D>
+    where
+        T: Send + Sync,
+    {
+        if entries.len() as i32 <= max_entities {
+            return Node::leaf_from_entries(entries);
+        }
         // So the objective here is to find the median value for whatever axis has the greatest disparity in distance.
-        // It is more efficient to pick three random values and pick the median of those as the pivot point, so that is
-        // done if the vector has enough points. Otherwise, it picks the first element. FindMiddle just returns the middle
-        // value of the three f64's given to it. Hopefully there is a more idomatic way to do this.
+        let (min, max) = bounds::<_, D>(entries, |(entity, _)| entity);
+        let extent = |axis: usize| max[axis] - min[axis];
+        let mut root_node = Node::new();
+        // If no one axis is more spread out than all of the others, split on the first.
+        let split_dimension = (0..D)
+            .find(|axis| (0..D).all(|other| other == *axis || extent(*axis) > extent(other)))
+            .unwrap_or(0);
+        let (split_value, split_index) =
+            find_median(split_dimension, entries, |(entity, _)| entity);
+        root_node.split_dimension = Some(split_dimension);
+        root_node.split_value = split_value;
+        let len = entries.len();
+        let (below_split, above_split) = entries.split_at_mut(split_index);
+
+        // Now we construct the left and right children based on this split into lower and upper halves.
+        let (left, right) = join(
+            len,
+            || Node::new_kd_node(below_split, max_entities),
+            || Node::new_kd_node(above_split, max_entities),
+        );
+        root_node.left = Some(Box::new(left));
+        root_node.right = Some(Box::new(right));
+        root_node.set_aggregates_from_children();
+        root_node
+    }
+}
+
+impl<T: AsEntity<D> + Clone, const D: usize> Node<T, D> {
+    /// Traverses tree and returns first child found with points.
+    pub(crate) fn traverse_tree_helper(&self) -> Vec<T> {
+        let mut to_return: Vec<T> = Vec::new();
+        if let Some(node) = &self.left {
+            to_return.append(&mut node.traverse_tree_helper());
+        }
+        if let Some(node) = &self.right {
+            to_return.append(&mut node.traverse_tree_helper());
         } else {
-            let mut root_node = Node::new();
-            let split_index;
-            let (split_dimension, split_value) = if zdistance > ydistance && zdistance > xdistance {
-                // "If the z distance is the greatest"
-                // split on Z
-                let (split_value, tmp) = find_median(Dimension::Z, &mut entities);
-                split_index = tmp;
-                (Dimension::Z, split_value)
-            } else if ydistance > xdistance && ydistance > zdistance {
-                // "If the y distance is the greatest"
-                // split on Y
-                let (split_value, tmp) = find_median(Dimension::Y, &mut entities);
-                split_index = tmp;
-                (Dimension::Y, split_value)
-            } else {
-                // "If the x distance is the greatest"
-                // split on X
-                let (split_value, tmp) = find_median(Dimension::X, &mut entities);
-                split_index = tmp;
-                (Dimension::X, split_value)
-            };
-            root_node.split_dimension = Some(split_dimension);
-            root_node.split_value = *split_value;
-            let (below_split, above_split) = pts.split_at(split_index);
-
-            // Now we construct the left and right children based on this split into lower and upper halves.
-            let left = Node::new_root_node(below_split, max_entities);
-            let right = Node::new_root_node(above_split, max_entities);
-            // The center of mass is a recursive definition. This finds the average COM for
-            // each node.
-            let left_mass = left.total_mass;
-            let right_mass = right.total_mass;
-            let (left_x, left_y, left_z) = left.center_of_mass;
-            let (right_x, right_y, right_z) = right.center_of_mass;
-            let total_mass = left_mass + right_mass;
-            assert!(total_mass != 0., "invalid mass of 0");
-
-            let (center_x, center_y, center_z) = (
-                ((left_mass * left_x) + (right_mass * right_x)) / total_mass,
-                ((left_mass * left_y) + (right_mass * right_y)) / total_mass,
-                ((left_mass * left_z) + (right_mass * right_z)) / total_mass,
+            to_return.append(
+                &mut (self
+                    .points
+                    .as_ref()
+                    .expect("unexpected null node #10")
+                    .clone()),
             );
-            root_node.left = Some(Box::new(left));
-            root_node.right = Some(Box::new(right));
-            root_node.center_of_mass = (center_x, center_y, center_z);
-            root_node.set_max_mins();
-            root_node.total_mass = total_mass;
-            root_node
         }
+        to_return
     }
 }
 
@@ -252,13 +970,13 @@ impl<T: AsEntity + Clone> Node<T> {
 fn test() {
     use crate::{collisions::soft_body, Responsive, SimulationResult};
     impl Responsive for Entity {
-        fn respond(&self, simulation_result: SimulationResult<Self>, time_step: f64) -> Self {
+        fn respond(&self, simulation_result: SimulationResult<Self>, time_step: Float) -> Self {
             let mut vx = self.vx;
             let mut vy = self.vy;
             let mut vz = self.vz;
             let (mut ax, mut ay, mut az) = simulation_result.gravitational_acceleration;
-            for other in simulation_result.collisions {
-                let (collision_ax, collision_ay, collision_az) = soft_body(self, other, 50f64);
+            for other in simulation_result.colliding_entities() {
+                let (collision_ax, collision_ay, collision_az) = soft_body(self, other, 50.);
                 ax += collision_ax;
                 ay += collision_ay;
                 az += collision_az;
@@ -276,29 +994,31 @@ fn test() {
                 z: self.z + (vz * time_step),
                 radius: self.radius,
                 mass: self.mass,
+                softening_length: self.softening_length,
             }
         }
     }
     let mut test_vec: Vec<Entity> = Vec::new();
     for i in 0..10 {
         test_vec.push(Entity {
-            x: i as f64,
-            y: (10 - i) as f64,
-            z: i as f64,
-            vx: i as f64,
-            vy: i as f64,
-            vz: i as f64,
-            mass: i as f64,
-            radius: i as f64,
+            x: i as Float,
+            y: (10 - i) as Float,
+            z: i as Float,
+            vx: i as Float,
+            vy: i as Float,
+            vz: i as Float,
+            mass: i as Float,
+            radius: i as Float,
+            softening_length: 0.,
         });
     }
 
     let check_vec = test_vec.clone();
-    let tree = crate::GravTree::new(&test_vec, 0.2, 3, 0.2);
+    let tree = crate::GravTree::new(&test_vec, 0.2, 3, 0.2, crate::CalculateCollisions::Yes);
     let root_node = tree.root.clone();
 
-    let mut nodes: Vec<Node<Entity>> = Vec::new();
-    let mut traversal_stack: Vec<Option<Box<Node<Entity>>>> = Vec::new();
+    let mut nodes: Vec<Node<Entity, 3>> = Vec::new();
+    let mut traversal_stack: Vec<Option<Box<Node<Entity, 3>>>> = Vec::new();
     let mut rover = Some(Box::new(root_node));
     while !traversal_stack.is_empty() || rover.is_some() {
         if rover.is_some() {
@@ -329,3 +1049,35 @@ fn test() {
     let total_mass = check_vec.iter().fold(0., |acc, x| acc + x.mass);
     assert_eq!(total_mass, tree.root.left.unwrap().total_mass);
 }
+
+/// Tests that the k-d tree partitions its entities, so that every entity in a node's left subtree
+/// is at or below the node's split value and every entity in its right subtree is at or above it.
+#[test]
+fn test_kd_partition() {
+    let test_vec = (0..1000)
+        .map(|i| Entity {
+            x: ((i * 7919) % 1000) as Float,
+            y: ((i * 104_729) % 997) as Float,
+            z: ((i * 1_299_709) % 991) as Float,
+            mass: 1.,
+            radius: 0.1,
+            ..Entity::default()
+        })
+        .collect::<Vec<_>>();
+    let tree = crate::GravTree::new(&test_vec, 0.2, 3, 0.2, crate::CalculateCollisions::No);
+
+    fn check(node: &Node<Entity, 3>) {
+        if let (Some(left), Some(right)) = (&node.left, &node.right) {
+            let axis = node.split_dimension.expect("internal nodes have a split");
+            for entity in left.traverse_tree_helper() {
+                assert!(entity.position::<3>()[axis] <= node.split_value);
+            }
+            for entity in right.traverse_tree_helper() {
+                assert!(entity.position::<3>()[axis] >= node.split_value);
+            }
+            check(left);
+            check(right);
+        }
+    }
+    check(tree.root.left.as_ref().expect("the tree has a root"));
+}
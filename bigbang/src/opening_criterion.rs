@@ -0,0 +1,101 @@
+use crate::Float;
+
+/// Decides whether a node of the tree is far enough from an entity to be approximated by its
+/// multipole expansion, rather than opened and walked into. This is the multipole acceptance
+/// criterion of the Barnes-Hut walk, and is chosen per tree with
+/// [set_opening_criterion](crate::GravTree::set_opening_criterion).
+///
+/// [OpeningAngle], [SalmonWarren] and [RelativeForce] are provided, and other criteria can be
+/// written against the description of the node in [CandidateNode]. The fast multipole
+/// [Solver](crate::Solver) does not use this, as it decides which pairs of nodes interact instead.
+pub trait OpeningCriterion: Send + Sync {
+    /// Returns `true` if `node` may be approximated as a whole, or `false` if it must be opened.
+    fn accepts(&self, node: &CandidateNode) -> bool;
+
+    /// Returns whether this criterion uses
+    /// [previous_acceleration](CandidateNode::previous_acceleration). If it does, the tree keeps
+    /// the accelerations from the last time step even when no built-in integrator needs them.
+    fn uses_previous_acceleration(&self) -> bool {
+        false
+    }
+}
+
+/// What an [OpeningCriterion] is told about a node of the tree, and the entity it would act on.
+#[derive(Clone, Copy, Debug)]
+pub struct CandidateNode {
+    /// The distance from the entity to the node's center of mass.
+    pub distance: Float,
+    /// The length of the longest side of the node's bounding box.
+    pub size: Float,
+    /// The distance between the node's center of mass and the center of its bounding box.
+    pub center_offset: Float,
+    /// The distance from the entity to the nearest point of the node's bounding box, which is zero
+    /// if the entity is inside it.
+    pub distance_to_bounds: Float,
+    /// The total mass of the node.
+    pub mass: Float,
+    /// The tree's `theta`.
+    pub theta: Float,
+    /// The tree's gravitational constant.
+    pub gravitational_constant: Float,
+    /// The magnitude of the acceleration on the entity at the end of the last time step, if it is
+    /// known.
+    pub previous_acceleration: Option<Float>,
+}
+
+/// The classic Barnes-Hut opening angle: a node is approximated when its size `s` and distance `d`
+/// satisfy `s / d < theta`. A smaller `theta` opens more nodes and is more accurate, and a `theta`
+/// of zero never approximates. This is the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OpeningAngle;
+
+impl OpeningCriterion for OpeningAngle {
+    fn accepts(&self, node: &CandidateNode) -> bool {
+        node.size < node.theta * node.distance
+    }
+}
+
+/// The criterion of Salmon & Warren (1994), which guards against nodes whose center of mass sits
+/// far from their geometric center. Such a node's mass can lie much closer to the entity than its
+/// center of mass does, so the opening angle underestimates its error. A node is approximated
+/// when `d > s / theta + δ`, where `δ` is the offset between its center of mass and geometric
+/// center.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SalmonWarren;
+
+impl OpeningCriterion for SalmonWarren {
+    fn accepts(&self, node: &CandidateNode) -> bool {
+        node.theta * (node.distance - node.center_offset) > node.size
+    }
+}
+
+/// The relative force criterion used by GADGET-2. A node is approximated when the size of the
+/// leading error term of its expansion, `G M s² / d⁴`, is less than `alpha` times the magnitude
+/// of the acceleration on the entity at the last time step. This spends accuracy where it
+/// matters: entities with large accelerations tolerate larger absolute errors. GADGET-2 uses an
+/// `alpha` of about 0.005.
+///
+/// Nodes which contain the entity, or which are within a tenth of their size of it, are always
+/// opened. Where the previous acceleration is not known yet, such as on the first time step, the
+/// [OpeningAngle] is used instead.
+#[derive(Clone, Copy, Debug)]
+pub struct RelativeForce(pub Float);
+
+impl OpeningCriterion for RelativeForce {
+    fn accepts(&self, node: &CandidateNode) -> bool {
+        let previous_acceleration = match node.previous_acceleration {
+            Some(previous_acceleration) => previous_acceleration,
+            None => return OpeningAngle.accepts(node),
+        };
+        if node.distance_to_bounds <= 0.1 * node.size {
+            return false;
+        }
+        let distance_squared = node.distance * node.distance;
+        node.gravitational_constant * node.mass * node.size * node.size
+            < self.0 * previous_acceleration * distance_squared * distance_squared
+    }
+
+    fn uses_previous_acceleration(&self) -> bool {
+        true
+    }
+}
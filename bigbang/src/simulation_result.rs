@@ -1,6 +1,77 @@
+use crate::Float;
 pub struct SimulationResult<'a, T> {
-    /// The result of the simulation's collision check
-    /// just a vector of references to potential collisions
-    pub collisions: Vec<&'a T>,
-    pub gravitational_acceleration: (f64, f64, f64),
+    /// The result of the simulation's collision check: every entity which this one collided with,
+    /// and how. This is empty unless the tree calculates collisions.
+    pub collisions: Vec<Contact<'a, T>>,
+    pub gravitational_acceleration: (Float, Float, Float),
+    /// The rate of change of the gravitational acceleration. This is only calculated when the tree
+    /// uses an [Integrator](crate::Integrator) which needs it, and is `None` otherwise.
+    pub jerk: Option<(Float, Float, Float)>,
+}
+
+impl<'a, T> SimulationResult<'a, T> {
+    /// Returns an iterator over the entities which this one collided with, without the rest of
+    /// each [Contact].
+    pub fn colliding_entities(&self) -> impl Iterator<Item = &'a T> + '_ {
+        self.collisions.iter().map(|contact| contact.other)
+    }
+}
+
+/// A collision between the entity which a [SimulationResult] is for and another entity, as it is
+/// at the moment the two first touch.
+pub struct Contact<'a, T> {
+    /// The entity which was collided with.
+    pub other: &'a T,
+    /// How far into the time step the two entities first touch, which is zero if they already
+    /// overlap at the start of it. This is always zero with
+    /// [Discrete](crate::CollisionDetection::Discrete) collision detection.
+    pub time: Float,
+    /// The unit vector pointing from the center of the other entity towards the center of this
+    /// one at `time`, which is the direction in which this entity is pushed. It is zero if the
+    /// two centers coincide.
+    pub normal: (Float, Float, Float),
+    /// How far the two entities overlap along the normal at `time`, i.e. the sum of their radii
+    /// less the distance between their centers. Entities which only touch partway through the
+    /// time step do not overlap yet, so this is zero for them.
+    pub penetration_depth: Float,
+    /// The point halfway through the overlap of the two entities along the normal at `time`.
+    pub point: (Float, Float, Float),
+    /// The velocity of this entity relative to the other along the normal. This is negative
+    /// while the two are moving towards each other and positive once they are moving apart.
+    pub normal_velocity: Float,
+}
+
+/// Everything about a [Contact] other than the entity which it is with, as seen from one of the
+/// two entities.
+#[derive(Clone, Copy)]
+pub(crate) struct ContactGeometry {
+    pub(crate) time: Float,
+    pub(crate) normal: (Float, Float, Float),
+    pub(crate) penetration_depth: Float,
+    pub(crate) point: (Float, Float, Float),
+    pub(crate) normal_velocity: Float,
+}
+
+impl ContactGeometry {
+    /// Returns the contact with `other`.
+    pub(crate) fn with<T>(self, other: &T) -> Contact<'_, T> {
+        Contact {
+            other,
+            time: self.time,
+            normal: self.normal,
+            penetration_depth: self.penetration_depth,
+            point: self.point,
+            normal_velocity: self.normal_velocity,
+        }
+    }
+
+    /// Returns the same contact as seen from the other entity. Only the normal changes, as the
+    /// velocity along it is the same from either side.
+    pub(crate) fn reversed(self) -> ContactGeometry {
+        let (nx, ny, nz) = self.normal;
+        ContactGeometry {
+            normal: (-nx, -ny, -nz),
+            ..self
+        }
+    }
 }
@@ -0,0 +1,114 @@
+use crate::Float;
+use serde::{Deserialize, Serialize};
+
+/// Gravitational softening modifies the force law at short range so that close encounters do not
+/// produce arbitrarily large accelerations. Without it, two entities which pass very near to each
+/// other are flung apart, and the time step has to be made tiny to resolve the encounter.
+///
+/// The length given to each kernel is the softening length `ε` used for every pair of entities.
+/// An [Entity](crate::Entity) may also carry its own `softening_length`. When it does, the length
+/// used for a pair is the largest of the two entities' lengths and the tree-wide length, so setting
+/// the tree-wide length to zero gives purely per-entity softening. Per-entity lengths are ignored
+/// when softening is [None](Softening::None).
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Softening {
+    /// No softening. The force law is used as-is at every distance.
+    None,
+    /// Plummer softening, `a = G * m * r / (r² + ε²)^(3/2)`. Simple and smooth, but the force
+    /// differs from the unsoftened force at every distance.
+    Plummer(Float),
+    /// The cubic-spline kernel of Monaghan & Lattanzio (1985), as used in GADGET. The force is
+    /// exactly Newtonian beyond the kernel's support of `h = 2.8ε` and smoothly goes to zero
+    /// inside it. `ε` is the Plummer-equivalent softening length, so that the potential at zero
+    /// separation matches that of [Plummer](Softening::Plummer) softening with the same `ε`.
+    Spline(Float),
+}
+
+/// The ratio of the spline kernel's support to its Plummer-equivalent softening length.
+const SPLINE_SUPPORT_RATIO: Float = 2.8;
+
+impl Softening {
+    /// Returns the softening length to use for a pair of entities whose own softening lengths are
+    /// `a` and `b`, or `None` if no softening should be applied.
+    fn length(&self, a: Float, b: Float) -> Option<Float> {
+        match *self {
+            Softening::None => None,
+            Softening::Plummer(epsilon) | Softening::Spline(epsilon) => {
+                Some(Float::max(epsilon, Float::max(a, b)))
+            }
+        }
+    }
+
+    /// Returns the softening length which applies to an entity whose own softening length is
+    /// `own`, or `None` if no softening should be applied.
+    pub(crate) fn entity_length(&self, own: Float) -> Option<Float> {
+        self.length(own, 0.)
+    }
+
+    /// Returns the factor which, multiplied by `G * m` and the (unnormalized) distance vector,
+    /// yields the softened Newtonian acceleration. This replaces the unsoftened `1 / r³`. `a` and
+    /// `b` are the softening lengths carried by the two entities involved. Returns `None` if no
+    /// softening applies to this pair.
+    pub(crate) fn inverse_cube(&self, r: Float, a: Float, b: Float) -> Option<Float> {
+        let epsilon = match self.length(a, b) {
+            Some(epsilon) if epsilon > 0. => epsilon,
+            _ => return None,
+        };
+        match *self {
+            Softening::None => None,
+            Softening::Plummer(_) => {
+                let r_squared = r * r + epsilon * epsilon;
+                Some(1. / (r_squared * Float::sqrt(r_squared)))
+            }
+            Softening::Spline(_) => {
+                let h = SPLINE_SUPPORT_RATIO * epsilon;
+                if r >= h {
+                    return None;
+                }
+                let u = r / h;
+                let h_cubed = h * h * h;
+                // These are the coefficients of the derivative of the spline potential, as given in
+                // Springel, Yoshida & White (2001), divided through by r.
+                Some(if u < 0.5 {
+                    (32. / 3. + u * u * (32. * u - 38.4)) / h_cubed
+                } else {
+                    (64. / 3. - 48. * u + 38.4 * u * u
+                        - 32. / 3. * u * u * u
+                        - 1. / 15. / (u * u * u))
+                        / h_cubed
+                })
+            }
+        }
+    }
+
+    /// Returns the softened replacement for `1 / r³`, as [inverse_cube](Softening::inverse_cube)
+    /// does, along with its derivative with respect to `r`. The derivative is needed to calculate
+    /// the jerk. Returns `None` if no softening applies to this pair.
+    pub(crate) fn inverse_cube_and_derivative(
+        &self,
+        r: Float,
+        a: Float,
+        b: Float,
+    ) -> Option<(Float, Float)> {
+        let inverse_cube = self.inverse_cube(r, a, b)?;
+        let epsilon = self.length(a, b)?;
+        let derivative = match *self {
+            Softening::None => return None,
+            Softening::Plummer(_) => {
+                let r_squared = r * r + epsilon * epsilon;
+                -3. * r / (r_squared * r_squared * Float::sqrt(r_squared))
+            }
+            Softening::Spline(_) => {
+                let h = SPLINE_SUPPORT_RATIO * epsilon;
+                let u = r / h;
+                let h_fourth = h * h * h * h;
+                if u < 0.5 {
+                    (96. * u * u - 76.8 * u) / h_fourth
+                } else {
+                    (-48. + 76.8 * u - 32. * u * u + 0.2 / (u * u * u * u)) / h_fourth
+                }
+            }
+        };
+        Some((inverse_cube, derivative))
+    }
+}
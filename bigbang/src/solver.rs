@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+
+/// Selects the algorithm used to calculate the gravitational acceleration on every entity.
+///
+/// Both solvers use the same tree and the same `theta`, so the results of one can be compared
+/// against the other with [accelerations](crate::GravTree::accelerations) and
+/// [acceleration_errors](crate::GravTree::acceleration_errors).
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Solver {
+    /// Walk the tree once for every entity, approximating distant nodes by their multipole
+    /// expansions. This costs O(n log n) per time step. This is the default.
+    BarnesHut,
+    /// Barnes-Hut, but walking the tree once for every leaf rather than once for every entity.
+    /// Each leaf's walk only approximates the nodes which the
+    /// [OpeningCriterion](crate::OpeningCriterion) accepts from the nearest point of the leaf's
+    /// bounds, and the list of nodes it reaches is shared by every entity in the leaf. This is at
+    /// least as accurate as Barnes-Hut, and usually faster.
+    ///
+    /// The criterion is asked about the group with the smallest distances and previous
+    /// acceleration of any entity in it. The built-in criteria never accept a node which is
+    /// closer, so a node accepted for the group is accepted for every entity in it. As with the
+    /// fast multipole method, Barnes-Hut is still used where block time steps or the Hermite
+    /// integrator only need the forces on a few entities.
+    GroupedBarnesHut,
+    /// The fast multipole method. The tree is walked against itself, so that whole nodes interact
+    /// with whole nodes: the field of a distant node is expanded about the center of mass of the
+    /// node it acts on, and these local expansions are then passed down the tree to the entities.
+    /// This costs O(n) per time step, which pays off for very large simulations.
+    ///
+    /// Nodes are expanded to their quadrupole moments and the local expansions to second order,
+    /// whatever the [MultipoleOrder](crate::MultipoleOrder). Two nodes interact as a whole when the
+    /// sum of their sizes is less than `theta` times the distance between them, and only where
+    /// the force between them is unsoftened. The fast multipole method is only used for the
+    /// [Newtonian](crate::ForceLaw::Newtonian) force law without
+    /// [Plummer](crate::Softening::Plummer) softening, and not where the jerk is needed or where
+    /// block time steps only need the forces on a few entities. Barnes-Hut is used in those cases.
+    FastMultipole,
+}
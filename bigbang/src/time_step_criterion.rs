@@ -0,0 +1,93 @@
+use crate::entity::Entity;
+use crate::integrator::Integrator;
+use crate::softening::Softening;
+use crate::Float;
+use serde::{Deserialize, Serialize};
+
+/// The deepest level of the block time step hierarchy. An entity on level `k` uses a time step
+/// of `time_step / 2^k`, so a single time step takes at most `2^MAX_LEVEL` substeps.
+pub(crate) const MAX_LEVEL: u32 = 20;
+
+/// Selects how [GravTree](crate::GravTree) chooses a time step for each entity.
+///
+/// With any criterion other than [None](TimeStepCriterion::None), every entity gets its own time
+/// step of `time_step / 2^k`, where `time_step` is the tree's time step and `k` is the smallest
+/// level that satisfies the criterion. The steps are arranged in a block hierarchy, so entities
+/// on the same level are always advanced together, and forces are only calculated for the
+/// entities whose steps end on a given substep. A close pair of entities can then take many small
+/// steps without slowing down the rest of the simulation.
+///
+/// Block time steps use kick-drift-kick leapfrog, so the tree's integrator must be
+/// [Leapfrog](crate::Integrator::Leapfrog) or [VelocityVerlet](crate::Integrator::VelocityVerlet).
+/// Setting a criterion with any other integrator, or another integrator while a criterion is set,
+/// panics.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TimeStepCriterion {
+    /// Every entity uses the tree's time step. This is the default.
+    None,
+    /// The acceleration-based criterion used by GADGET, `dt = sqrt(2ηε / |a|)`, where `η` is the
+    /// given accuracy parameter and `ε` is the entity's softening length. Unsoftened entities use
+    /// their radius in place of the softening length. Values of `η` around 0.025 are typical.
+    /// Entities with neither a softening length nor a radius use the tree's time step.
+    Acceleration(Float),
+    /// The simplified form of Aarseth's criterion, `dt = η|a| / |j|`, where `η` is the given
+    /// accuracy parameter and `j` is the jerk. Values of `η` around 0.01 are typical.
+    Aarseth(Float),
+}
+
+impl TimeStepCriterion {
+    /// Whether block time steps with this criterion can be run with `integrator`. Without a
+    /// criterion, every integrator can be used.
+    pub(crate) fn supports(&self, integrator: Integrator) -> bool {
+        match self {
+            TimeStepCriterion::None => true,
+            _ => matches!(
+                integrator,
+                Integrator::Leapfrog | Integrator::VelocityVerlet
+            ),
+        }
+    }
+
+    /// Whether this criterion needs the jerk to be calculated.
+    pub(crate) fn needs_jerk(&self) -> bool {
+        matches!(self, TimeStepCriterion::Aarseth(_))
+    }
+
+    /// Returns the level of the block hierarchy that `entity` should be on, given its current
+    /// acceleration and jerk and the longest time step available.
+    pub(crate) fn level(
+        &self,
+        entity: &Entity,
+        acceleration: (Float, Float, Float),
+        jerk: Option<(Float, Float, Float)>,
+        softening: Softening,
+        max_time_step: Float,
+    ) -> u32 {
+        let magnitude = |(x, y, z): (Float, Float, Float)| Float::sqrt(x * x + y * y + z * z);
+        let time_step = match *self {
+            TimeStepCriterion::None => return 0,
+            TimeStepCriterion::Acceleration(eta) => {
+                let length = softening
+                    .entity_length(entity.softening_length)
+                    .unwrap_or(entity.radius);
+                Float::sqrt(2. * eta * length / magnitude(acceleration))
+            }
+            TimeStepCriterion::Aarseth(eta) => {
+                let jerk = jerk.expect("the Aarseth criterion needs the jerk");
+                eta * magnitude(acceleration) / magnitude(jerk)
+            }
+        };
+        // An entity feeling no force (or no change in force) can take the longest step. So does
+        // one the criterion can say nothing about, such as a point mass with no softening length,
+        // whose step would otherwise be zero.
+        if time_step.is_nan() || time_step <= 0. || time_step >= max_time_step {
+            return 0;
+        }
+        let level = Float::log2(max_time_step / time_step).ceil();
+        if level > MAX_LEVEL as Float {
+            MAX_LEVEL
+        } else {
+            level as u32
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use crate::as_entity::AsEntity;
+use crate::entity::{Entity, EntityId};
+use crate::utilities::{self, bounds};
+use crate::Float;
+use crate::Node;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Selects how the tree is built from its entities.
+///
+/// Every kind of tree is stored as a binary tree, so the tree walk, refitting, and inserting and
+/// removing entities work the same way for all of them. They differ in where the nodes are split,
+/// which changes how quickly the tree is built and how well its nodes fit the entities.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TreeKind {
+    /// Split every node at the median of the dimension in which its entities are most spread out.
+    /// This gives a balanced tree whose nodes fit the entities tightly. This is the default.
+    KdTree,
+    /// Split every cell into eight equal octants around its center. Each octant split is stored
+    /// as three binary splits, along x, y and z in turn, and empty octants are left out. The cells
+    /// are cubes regardless of how the entities are distributed, as in the original Barnes-Hut
+    /// algorithm. In two dimensions the cells are squares split into quadrants, and in one they
+    /// are intervals split in half.
+    Octree,
+    /// Sort the entities along the Morton (Z-order) space-filling curve, and build the nodes over
+    /// contiguous runs of the sorted entities. This gives the same cells as the
+    /// [Octree](TreeKind::Octree), but the build is a parallel sort followed by a single pass, and
+    /// entities which are close in space are close in memory.
+    Morton,
+    /// The same as [Morton](TreeKind::Morton), but along the Hilbert curve, whose consecutive
+    /// cells always share a face. This keeps neighbouring entities together in memory a little
+    /// better than the Morton curve.
+    Hilbert,
+}
+
+/// The number of bits of each coordinate used in a space-filling curve key.
+const BITS: u32 = 21;
+
+/// An entity with its id, which is taken out once it has been placed in a leaf.
+type Entry<T> = Option<(T, EntityId)>;
+
+impl TreeKind {
+    /// Builds a tree of this kind containing `pts`, which have the ids `ids`. The entities are
+    /// reordered, and each id stays with its entity.
+    pub(crate) fn build<T: AsEntity<D> + Send + Sync, const D: usize>(
+        &self,
+        pts: Vec<T>,
+        ids: Vec<EntityId>,
+        max_entities: i32,
+    ) -> Node<T, D> {
+        if pts.len() as i32 <= max_entities || *self == TreeKind::KdTree {
+            return Node::new_root_node(pts, ids, max_entities);
+        }
+        let mut entities = pts
+            .into_par_iter()
+            .zip(ids)
+            .map(|(x, id)| (x.as_entity(), Some((x, id))))
+            .collect::<Vec<_>>();
+        let (origin, size) = bounding_cube(&entities);
+        match self {
+            TreeKind::KdTree => unreachable!("k-d trees are built by `new_root_node`"),
+            TreeKind::Octree => octree_node(&mut entities, origin, [size; D], 0, max_entities),
+            TreeKind::Morton | TreeKind::Hilbert => {
+                let key = |entity: &Entity| {
+                    let quantized = quantize(entity.position(), origin, size);
+                    if *self == TreeKind::Morton {
+                        morton_key(quantized)
+                    } else {
+                        hilbert_key(quantized)
+                    }
+                };
+                let mut entries = entities
+                    .into_par_iter()
+                    .map(|(entity, entry)| (key(&entity), (entity, entry)))
+                    .collect::<Vec<_>>();
+                entries.par_sort_unstable_by_key(|(key, _)| *key);
+                let (keys, mut entries): (Vec<_>, Vec<_>) = entries.into_iter().unzip();
+                linear_node(&keys, &mut entries, max_entities)
+            }
+        }
+    }
+}
+
+/// Returns the lowest corner and the side of the smallest cube, or square or interval in fewer
+/// dimensions, containing every entity in `entities`.
+fn bounding_cube<T: Sync, const D: usize>(entities: &[(Entity, Entry<T>)]) -> ([Float; D], Float) {
+    let (min, max) = bounds(entities, |(entity, _)| entity);
+    let size = (0..D)
+        .map(|axis| max[axis] - min[axis])
+        .fold(0., Float::max);
+    (min, size)
+}
+
+/// Builds the two halves of a node, in parallel if there are enough entities.
+fn join<T: AsEntity<D> + Send + Sync, const D: usize>(
+    len: usize,
+    left: impl FnOnce() -> Node<T, D> + Send,
+    right: impl FnOnce() -> Node<T, D> + Send,
+) -> Node<T, D> {
+    let (left, right) = utilities::join(len, left, right);
+    Node::internal(left, right)
+}
+
+/// Builds an octree over `entries`, which are the entities with their ids, inside the cell
+/// whose lowest corner is `origin` and whose extents are `size`. The cell is split along
+/// `dimension` next.
+fn octree_node<T: AsEntity<D> + Send + Sync, const D: usize>(
+    entries: &mut [(Entity, Entry<T>)],
+    mut origin: [Float; D],
+    mut size: [Float; D],
+    mut dimension: usize,
+    max_entities: i32,
+) -> Node<T, D> {
+    if entries.len() as i32 <= max_entities {
+        return Node::leaf_from_entries(entries);
+    }
+    // Entities at exactly the same position can never be split apart, so give up on a cell once it
+    // has been halved this many times in a row without separating any entities.
+    let mut stalled = 0;
+    loop {
+        if stalled > D * 128 {
+            return Node::leaf_from_entries(entries);
+        }
+        let half = size[dimension] / 2.;
+        let split_value = origin[dimension] + half;
+        let split_index = partition(entries, |(entity, _)| {
+            entity.position::<D>()[dimension] < split_value
+        });
+        size[dimension] = half;
+        let next = (dimension + 1) % D;
+        if split_index == 0 {
+            // Every entity is in the upper half, so the cell shrinks without adding a node.
+            origin[dimension] = split_value;
+        } else if split_index < entries.len() {
+            let len = entries.len();
+            let (lower, upper) = entries.split_at_mut(split_index);
+            let mut upper_origin = origin;
+            upper_origin[dimension] = split_value;
+            return join(
+                len,
+                move || octree_node(lower, origin, size, next, max_entities),
+                move || octree_node(upper, upper_origin, size, next, max_entities),
+            );
+        }
+        stalled += 1;
+        dimension = next;
+    }
+}
+
+/// Moves every element of `entries` for which `lower` is true to the front, and returns how many
+/// there are.
+fn partition<E>(entries: &mut [E], lower: impl Fn(&E) -> bool) -> usize {
+    let mut split_index = 0;
+    for i in 0..entries.len() {
+        if lower(&entries[i]) {
+            entries.swap(i, split_index);
+            split_index += 1;
+        }
+    }
+    split_index
+}
+
+/// Builds a tree over `entries`, which are the entities with their ids, whose space-filling curve
+/// keys are `keys`, in sorted order. Each
+/// node is split where the highest bit in which its keys differ changes, so that its children are
+/// contiguous runs of the sorted entities.
+fn linear_node<T: AsEntity<D> + Send + Sync, const D: usize>(
+    keys: &[u64],
+    entries: &mut [(Entity, Entry<T>)],
+    max_entities: i32,
+) -> Node<T, D> {
+    if entries.len() as i32 <= max_entities {
+        return Node::leaf_from_entries(entries);
+    }
+    let (first, last) = (keys[0], keys[keys.len() - 1]);
+    let split_index = if first == last {
+        // Entities with the same key are split evenly.
+        keys.len() / 2
+    } else {
+        let bit = 63 - (first ^ last).leading_zeros();
+        keys.partition_point(|key| key & (1 << bit) == 0)
+    };
+    let len = entries.len();
+    let (lower_keys, upper_keys) = keys.split_at(split_index);
+    let (lower, upper) = entries.split_at_mut(split_index);
+    join(
+        len,
+        move || linear_node(lower_keys, lower, max_entities),
+        move || linear_node(upper_keys, upper, max_entities),
+    )
+}
+
+/// Returns the coordinates of `position` on a grid of `2^BITS` cells per side over the cube whose
+/// lowest corner is `origin` and whose side is `size`.
+fn quantize<const D: usize>(position: [Float; D], origin: [Float; D], size: Float) -> [u32; D] {
+    let cells = (1u32 << BITS) as Float;
+    let mut quantized = [0; D];
+    for (q, (x, o)) in quantized.iter_mut().zip(position.iter().zip(origin.iter())) {
+        let cell = if size > 0. {
+            (x - o) / size * cells
+        } else {
+            0.
+        };
+        *q = Float::min(Float::max(cell, 0.), cells - 1.) as u32;
+    }
+    quantized
+}
+
+/// Interleaves the bits of the coordinates, from the most significant down, taking x, y and z in
+/// turn.
+fn interleave<const D: usize>(coordinates: [u32; D]) -> u64 {
+    let mut key = 0;
+    for bit in (0..BITS).rev() {
+        for coordinate in coordinates.iter() {
+            key = (key << 1) | ((coordinate >> bit) & 1) as u64;
+        }
+    }
+    key
+}
+
+/// Returns the position of a grid cell along the Morton curve.
+fn morton_key<const D: usize>(coordinates: [u32; D]) -> u64 {
+    interleave(coordinates)
+}
+
+/// Returns the position of a grid cell along the Hilbert curve, using Skilling's transform of the
+/// coordinates (Skilling, 2004).
+fn hilbert_key<const D: usize>(mut x: [u32; D]) -> u64 {
+    let m = 1u32 << (BITS - 1);
+    // Inverse undo.
+    let mut q = m;
+    while q > 1 {
+        let p = q - 1;
+        for i in 0..D {
+            if x[i] & q != 0 {
+                x[0] ^= p;
+            } else {
+                let t = (x[0] ^ x[i]) & p;
+                x[0] ^= t;
+                x[i] ^= t;
+            }
+        }
+        q >>= 1;
+    }
+    // Gray encode.
+    for i in 1..D {
+        x[i] ^= x[i - 1];
+    }
+    let mut t = 0;
+    let mut q = m;
+    while q > 1 {
+        if x[D - 1] & q != 0 {
+            t ^= q - 1;
+        }
+        q >>= 1;
+    }
+    for coordinate in x.iter_mut() {
+        *coordinate ^= t;
+    }
+    interleave(x)
+}
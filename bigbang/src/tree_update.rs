@@ -0,0 +1,23 @@
+use crate::Float;
+use serde::{Deserialize, Serialize};
+
+/// Selects how [GravTree](crate::GravTree) updates its nodes after the entities have moved at the
+/// end of every time step.
+///
+/// Entities usually move very little in a single time step, so the nodes built for the last step
+/// still fit them well. With [Refit](TreeUpdate::Refit), the structure of the tree is kept and
+/// only the aggregate values of the nodes (center of mass, total mass, largest radius and bounds)
+/// are recalculated from the bottom up. This is much cheaper than building the tree again, but the
+/// tree walk becomes less efficient as entities wander across the splits between nodes, so any
+/// node which has become a poor fit for its entities is rebuilt.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TreeUpdate {
+    /// Build the whole tree again from scratch at every time step. This is the default.
+    Rebuild,
+    /// Keep the structure of the tree and recalculate the aggregate values of its nodes. A node
+    /// is rebuilt, along with everything below it, once the bounds of its two children overlap
+    /// along the dimension it was split on by more than the given fraction of its own extent in
+    /// that dimension. Freshly built nodes do not overlap at all. Values around 0.1 are typical,
+    /// and zero rebuilds every node which any entity has crossed.
+    Refit(Float),
+}
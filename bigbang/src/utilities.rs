@@ -1,79 +1,98 @@
-use super::Dimension;
-#[allow(unused_imports)]
 use crate::entity::Entity;
+use crate::Float;
+use rayon::prelude::*;
 use std::cmp::Ordering;
-/// Returns the absolute distance in every dimension (the range in every dimension)
-/// of an array slice of entities.
-pub(crate) fn xyz_distances(entities: &[Entity]) -> (f64, f64, f64) {
-    let (x_max, x_min, y_max, y_min, z_max, z_min) = max_min_xyz(entities);
-    let x_distance = x_max - x_min;
-    let y_distance = y_max - y_min;
-    let z_distance = z_max - z_min;
-    (x_distance.abs(), y_distance.abs(), z_distance.abs())
-}
+/// Slices of the tree with more entities than this are built in parallel.
+pub(crate) const PARALLEL_CUTOFF: usize = 4096;
 
-/// Given an array slice of entities, returns the maximum and minimum x, y, and z values as
-/// a septuple.
-pub(crate) fn max_min_xyz(entities: &[Entity]) -> (&f64, &f64, &f64, &f64, &f64, &f64) {
-    let (x_max, x_min) = max_min(Dimension::X, entities);
-    let (y_max, y_min) = max_min(Dimension::Y, entities);
-    let (z_max, z_min) = max_min(Dimension::Z, entities);
-    (x_max, x_min, y_max, y_min, z_max, z_min)
+/// Runs `left` and `right`, in parallel if `len`, the number of entities they work on between
+/// them, is above the [PARALLEL_CUTOFF].
+pub(crate) fn join<A: Send, B: Send>(
+    len: usize,
+    left: impl FnOnce() -> A + Send,
+    right: impl FnOnce() -> B + Send,
+) -> (A, B) {
+    if len > PARALLEL_CUTOFF {
+        rayon::join(left, right)
+    } else {
+        (left(), right())
+    }
 }
 
-/// Returns the maximum and minimum values in a slice of entities, given a dimension.
-pub(crate) fn max_min(dim: Dimension, entities: &[Entity]) -> (&f64, &f64) {
-    (
-        entities
-            .iter()
-            .max_by(|a, b| {
-                a.get_dim(&dim)
-                    .partial_cmp(b.get_dim(&dim))
-                    .unwrap_or(Ordering::Equal)
-            })
-            .unwrap_or_else(|| panic!("no max {} found", dim.as_string()))
-            .get_dim(&dim),
-        entities
-            .iter()
-            .min_by(|a, b| {
-                a.get_dim(&dim)
-                    .partial_cmp(b.get_dim(&dim))
-                    .unwrap_or(Ordering::Equal)
-            })
-            .unwrap_or_else(|| panic!("no min {} found", dim.as_string()))
-            .get_dim(&dim),
-    )
+/// Returns the minimum and maximum values along each of the first `D` axes of the entities in
+/// `entries`, where `entity` picks the entity out of each entry. Large slices are reduced in
+/// parallel.
+pub(crate) fn bounds<E: Sync, const D: usize>(
+    entries: &[E],
+    entity: impl Fn(&E) -> &Entity + Sync,
+) -> ([Float; D], [Float; D]) {
+    let empty = || ([Float::INFINITY; D], [Float::NEG_INFINITY; D]);
+    let include = |(mut min, mut max): ([Float; D], [Float; D]), entry: &E| {
+        for (i, x) in entity(entry).position::<D>().iter().enumerate() {
+            min[i] = Float::min(min[i], *x);
+            max[i] = Float::max(max[i], *x);
+        }
+        (min, max)
+    };
+    if entries.len() > PARALLEL_CUTOFF {
+        entries.par_iter().fold(empty, include).reduce(
+            empty,
+            |(mut min, mut max), (other_min, other_max)| {
+                for i in 0..D {
+                    min[i] = Float::min(min[i], other_min[i]);
+                    max[i] = Float::max(max[i], other_max[i]);
+                }
+                (min, max)
+            },
+        )
+    } else {
+        entries.iter().fold(empty(), include)
+    }
 }
 
-/// Finds the median value for a given dimension in a slice of entities.
-/// Making one that clones/uses immutability could be an interesting performance benchmark.
-pub(crate) fn find_median(dim: Dimension, pts: &mut [Entity]) -> (&f64, usize) {
-    find_median_helper(dim, pts, 0, pts.len(), pts.len() / 2usize)
+/// Finds the median value along `axis` in a slice of entries, where `entity` picks the entity out
+/// of each entry, and the index at which it is found. The slice is partitioned around that index,
+/// so every entry before it is at or below the median and every entry after it is at or above it.
+pub(crate) fn find_median<E>(
+    axis: usize,
+    entries: &mut [E],
+    entity: impl Fn(&E) -> &Entity,
+) -> (Float, usize) {
+    let coordinate = |entry: &E| {
+        let entity = entity(entry);
+        [entity.x, entity.y, entity.z][axis]
+    };
+    let mid = entries.len() / 2;
+    entries.select_nth_unstable_by(mid, |a, b| {
+        coordinate(a)
+            .partial_cmp(&coordinate(b))
+            .unwrap_or(Ordering::Equal)
+    });
+    (coordinate(&entries[mid]), mid)
 }
 
-fn find_median_helper(
-    dim: Dimension,
-    pts: &mut [Entity],
-    start: usize,
-    end: usize,
-    mid: usize,
-) -> (&f64, usize) {
-    let mut low = (start + 1);
-    let mut high = (end - 1); //exclusive end
-    while low <= high {
-        if pts[low].get_dim(&dim) < pts[start].get_dim(&dim) {
-            low += 1;
-        } else {
-            pts.swap(low, high);
-            high -= 1;
-        }
+/// Serializes arrays with one value per dimension, which serde only supports for fixed sizes, as
+/// sequences. Use with `#[serde(with = "crate::utilities::float_array")]`.
+pub(crate) mod float_array {
+    use crate::Float;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::convert::TryInto;
+
+    pub(crate) fn serialize<S: Serializer, const D: usize>(
+        array: &[Float; D],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        array[..].serialize(serializer)
     }
-    pts.swap(start, high);
-    if start == mid {
-        (pts[start].get_dim(&dim), start)
-    } else if high < mid {
-        find_median_helper(dim, pts, high + 1, end, mid)
-    } else {
-        find_median_helper(dim, pts, start, high, mid)
+
+    pub(crate) fn deserialize<'de, De: Deserializer<'de>, const D: usize>(
+        deserializer: De,
+    ) -> Result<[Float; D], De::Error> {
+        let values = Vec::<Float>::deserialize(deserializer)?;
+        let len = values.len();
+        values
+            .try_into()
+            .map_err(|_| De::Error::invalid_length(len, &"one value per dimension"))
     }
 }
@@ -0,0 +1,255 @@
+extern crate bigbang;
+use bigbang::collisions::{
+    elastic, friction, friction_with_spin, hertzian, restitution, soft_body, spring_dashpot,
+};
+use bigbang::{AsEntity, Float};
+
+#[derive(Clone, PartialEq, AsEntity)]
+struct Ball {
+    x: Float,
+    y: Float,
+    z: Float,
+    vx: Float,
+    vy: Float,
+    vz: Float,
+    radius: Float,
+    mass: Float,
+}
+
+impl Ball {
+    fn new(
+        position: (Float, Float, Float),
+        velocity: (Float, Float, Float),
+        radius: Float,
+        mass: Float,
+    ) -> Ball {
+        Ball {
+            x: position.0,
+            y: position.1,
+            z: position.2,
+            vx: velocity.0,
+            vy: velocity.1,
+            vz: velocity.2,
+            radius,
+            mass,
+        }
+    }
+
+    fn momentum(&self) -> (Float, Float, Float) {
+        (
+            self.vx * self.mass,
+            self.vy * self.mass,
+            self.vz * self.mass,
+        )
+    }
+}
+
+/// Two overlapping balls of different sizes and masses, which collide at an angle with a velocity
+/// along every axis.
+fn glancing_pair() -> (Ball, Ball) {
+    (
+        Ball::new((0., 0., 0.), (1., 0.5, -0.25), 1., 2.),
+        Ball::new((1.2, 0.6, 0.3), (-2., 0.25, 0.5), 0.5, 5.),
+    )
+}
+
+/// Returns `tolerance`, which is written for `f64`, loosened to allow for the coarser rounding of
+/// `f32` when the `f32` feature is enabled.
+fn rounding(tolerance: Float) -> Float {
+    Float::max(tolerance, 1e2 * Float::EPSILON)
+}
+
+fn assert_close(a: (Float, Float, Float), b: (Float, Float, Float)) {
+    let error = ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2) + (a.2 - b.2).powi(2)).sqrt();
+    assert!(error < rounding(1e-12), "{:?} is not {:?}", a, b);
+}
+
+/// Asserts that `a` and `b` receive opposite changes in momentum when each of them receives the
+/// given change in velocity, or the given acceleration.
+fn assert_conserves_momentum(
+    a: &Ball,
+    b: &Ball,
+    change: fn(&Ball, &Ball) -> (Float, Float, Float),
+) {
+    let (ax, ay, az) = change(a, b);
+    let (bx, by, bz) = change(b, a);
+    assert!(
+        (ax, ay, az) != (0., 0., 0.),
+        "the balls did not interact at all"
+    );
+    assert_close(
+        (
+            a.mass * ax + b.mass * bx,
+            a.mass * ay + b.mass * by,
+            a.mass * az + b.mass * bz,
+        ),
+        (0., 0., 0.),
+    );
+}
+
+/// Returns the balls after applying the change in velocity from `impulse` to both of them.
+fn after_impulse(
+    a: &Ball,
+    b: &Ball,
+    impulse: impl Fn(&Ball, &Ball) -> (Float, Float, Float),
+) -> (Ball, Ball) {
+    let apply = |ball: &Ball, (dx, dy, dz): (Float, Float, Float)| Ball {
+        vx: ball.vx + dx,
+        vy: ball.vy + dy,
+        vz: ball.vz + dz,
+        ..ball.clone()
+    };
+    (apply(a, impulse(a, b)), apply(b, impulse(b, a)))
+}
+
+fn kinetic_energy(ball: &Ball) -> Float {
+    0.5 * ball.mass * (ball.vx * ball.vx + ball.vy * ball.vy + ball.vz * ball.vz)
+}
+
+/// Test that entities which do not overlap do not push each other, rather than receiving their own
+/// velocity as an acceleration.
+#[test]
+fn soft_body_without_overlap_is_zero() {
+    let a = Ball::new((0., 0., 0.), (3., 4., 5.), 1., 1.);
+    let b = Ball::new((5., 0., 0.), (0., 0., 0.), 1., 1.);
+    assert_eq!(soft_body(&a, &b, 10.), (0., 0., 0.));
+    assert_eq!(spring_dashpot(&a, &b, 10., 1.), (0., 0., 0.));
+    assert_eq!(hertzian(&a, &b, 10., 1.), (0., 0., 0.));
+    assert_eq!(friction(&a, &b, 10., 0.5), (0., 0., 0.));
+}
+
+/// Test that every response gives the two entities opposite changes in momentum.
+#[test]
+fn responses_conserve_momentum() {
+    let (a, b) = glancing_pair();
+    assert_conserves_momentum(&a, &b, |a, b| soft_body(a, b, 10.));
+    assert_conserves_momentum(&a, &b, |a, b| restitution(a, b, 0.5));
+    assert_conserves_momentum(&a, &b, elastic);
+    assert_conserves_momentum(&a, &b, |a, b| spring_dashpot(a, b, 10., 3.));
+    assert_conserves_momentum(&a, &b, |a, b| hertzian(a, b, 10., 3.));
+    assert_conserves_momentum(&a, &b, |a, b| friction(a, b, 10., 0.5));
+}
+
+/// Test that a perfectly elastic impulse conserves kinetic energy, and a perfectly inelastic one
+/// leaves the entities moving together along the normal.
+#[test]
+fn restitution_sets_separating_speed() {
+    let (a, b) = glancing_pair();
+    let normal_velocity = |a: &Ball, b: &Ball| {
+        let (nx, ny, nz) = (a.x - b.x, a.y - b.y, a.z - b.z);
+        let length = (nx * nx + ny * ny + nz * nz).sqrt();
+        ((a.vx - b.vx) * nx + (a.vy - b.vy) * ny + (a.vz - b.vz) * nz) / length
+    };
+    let before = normal_velocity(&a, &b);
+    assert!(before < 0.);
+    for coefficient in [0., 0.5, 1.].iter() {
+        let (after_a, after_b) = after_impulse(&a, &b, |a, b| restitution(a, b, *coefficient));
+        let after = normal_velocity(&after_a, &after_b);
+        assert!(
+            (after + coefficient * before).abs() < rounding(1e-12),
+            "separated at {} instead of {}",
+            after,
+            -coefficient * before
+        );
+        if *coefficient == 1. {
+            let energy = |a: &Ball, b: &Ball| kinetic_energy(a) + kinetic_energy(b);
+            assert!((energy(&after_a, &after_b) - energy(&a, &b)).abs() < rounding(1e-12));
+        }
+        // Entities which are already moving apart are left alone.
+        if *coefficient > 0. {
+            assert_eq!(restitution(&after_a, &after_b, *coefficient), (0., 0., 0.));
+        }
+    }
+}
+
+/// Test that the elastic exchange swaps the velocities of equal masses in a head-on collision,
+/// and conserves kinetic energy for unequal ones.
+#[test]
+fn elastic_exchanges_velocities() {
+    let a = Ball::new((0., 0., 0.), (2., 0., 0.), 1., 3.);
+    let b = Ball::new((1.5, 0., 0.), (-1., 0., 0.), 1., 3.);
+    let (after_a, after_b) = after_impulse(&a, &b, elastic);
+    assert_close((after_a.vx, after_a.vy, after_a.vz), (-1., 0., 0.));
+    assert_close((after_b.vx, after_b.vy, after_b.vz), (2., 0., 0.));
+
+    let b = Ball { mass: 7., ..b };
+    let (after_a, after_b) = after_impulse(&a, &b, elastic);
+    let energy = kinetic_energy(&a) + kinetic_energy(&b);
+    assert!((kinetic_energy(&after_a) + kinetic_energy(&after_b) - energy).abs() < rounding(1e-12));
+    assert_close(
+        (
+            after_a.momentum().0 + after_b.momentum().0,
+            after_a.momentum().1 + after_b.momentum().1,
+            after_a.momentum().2 + after_b.momentum().2,
+        ),
+        (
+            a.momentum().0 + b.momentum().0,
+            a.momentum().1 + b.momentum().1,
+            a.momentum().2 + b.momentum().2,
+        ),
+    );
+}
+
+/// Test that damping pushes harder while the entities approach than while they separate, and never
+/// pulls them together.
+#[test]
+fn spring_dashpot_damps_the_approach() {
+    let approaching = Ball::new((0., 0., 0.), (1., 0., 0.), 1., 1.);
+    let separating = Ball::new((0., 0., 0.), (-1., 0., 0.), 1., 1.);
+    let other = Ball::new((1.9, 0., 0.), (0., 0., 0.), 1., 1.);
+    for contact in [spring_dashpot::<Ball, 3>, hertzian::<Ball, 3>].iter() {
+        let spring = contact(&approaching, &other, 10., 0.).0;
+        assert!(spring < 0.);
+        assert!(contact(&approaching, &other, 10., 1.).0 < spring);
+        assert!(contact(&separating, &other, 10., 1.).0 > spring);
+        assert_eq!(contact(&separating, &other, 10., 1000.), (0., 0., 0.));
+    }
+}
+
+/// Test that friction opposes sliding, that spinning against the direction of sliding can cancel
+/// it out, and that the total momentum and angular momentum of two spinning entities are
+/// conserved.
+#[test]
+fn friction_opposes_sliding() {
+    let a = Ball::new((0., 0., 0.), (0., 1., 0.), 1.25, 2.);
+    let b = Ball::new((1.5, 0., 0.), (0., 0., 0.), 0.75, 2.);
+    let (ax, ay, az) = friction(&a, &b, 4., 0.5);
+    assert_close((ax, ay, az), (0., -1., 0.));
+
+    // The surface of a at the contact point moves at v + ω × r, where r is one along x.
+    let rolling = (0., 0., -1.);
+    let (acceleration, angular_acceleration) =
+        friction_with_spin(&a, &b, rolling, (0., 0., 0.), 4., 0.5);
+    assert_eq!(acceleration, (0., 0., 0.));
+    assert_eq!(angular_acceleration, (0., 0., 0.));
+
+    let (spin_a, spin_b) = ((1., -2., 0.5), (0., 3., -1.));
+    let (a, b) = glancing_pair();
+    let (acceleration_a, angular_a) = friction_with_spin(&a, &b, spin_a, spin_b, 10., 0.5);
+    let (acceleration_b, angular_b) = friction_with_spin(&b, &a, spin_b, spin_a, 10., 0.5);
+    // The rate of change of angular momentum about the origin, r × F + I α, for each ball.
+    let torque =
+        |ball: &Ball, (ax, ay, az): (Float, Float, Float), (wx, wy, wz): (Float, Float, Float)| {
+            let (fx, fy, fz) = (ax * ball.mass, ay * ball.mass, az * ball.mass);
+            let inertia = 0.4 * ball.mass * ball.radius * ball.radius;
+            (
+                ball.y * fz - ball.z * fy + inertia * wx,
+                ball.z * fx - ball.x * fz + inertia * wy,
+                ball.x * fy - ball.y * fx + inertia * wz,
+            )
+        };
+    let (ta, tb) = (
+        torque(&a, acceleration_a, angular_a),
+        torque(&b, acceleration_b, angular_b),
+    );
+    assert!(angular_a != (0., 0., 0.));
+    assert_close(
+        (
+            a.mass * acceleration_a.0 + b.mass * acceleration_b.0,
+            a.mass * acceleration_a.1 + b.mass * acceleration_b.1,
+            a.mass * acceleration_a.2 + b.mass * acceleration_b.2,
+        ),
+        (0., 0., 0.),
+    );
+    assert_close((ta.0 + tb.0, ta.1 + tb.1, ta.2 + tb.2), (0., 0., 0.));
+}
@@ -6,23 +6,29 @@
 // The issue probably arises when the total number of entities is less than max_pts?
 
 extern crate bigbang;
-use bigbang::{collisions::soft_body, AsEntity, Entity, GravTree, Responsive, SimulationResult};
+use bigbang::{
+    collisions::soft_body, gravitational_constant, AsEntity, CalculateCollisions, CandidateNode,
+    CollisionDetection, EntityId, Float, ForceLaw, GravTree, LeafKernel, MultipoleOrder,
+    OpeningCriterion, Precision, RelativeForce, Responsive, SalmonWarren, SimulationResult, Solver,
+    TreeKind,
+};
+use std::collections::HashMap;
 
 #[derive(Clone, PartialEq, AsEntity)]
 struct MyEntity {
-    x: f64,
-    y: f64,
-    z: f64,
-    vx: f64,
-    vy: f64,
-    vz: f64,
-    radius: f64,
-    mass: f64,
+    x: Float,
+    y: Float,
+    z: Float,
+    vx: Float,
+    vy: Float,
+    vz: Float,
+    radius: Float,
+    mass: Float,
     collided_with: Vec<MyEntity>,
 }
 
 impl MyEntity {
-    fn new(x: f64, y: f64, z: f64, radius: f64, mass: f64) -> MyEntity {
+    fn new(x: Float, y: Float, z: Float, radius: Float, mass: Float) -> MyEntity {
         MyEntity {
             x,
             y,
@@ -38,15 +44,15 @@ impl MyEntity {
 }
 
 impl Responsive for MyEntity {
-    fn respond(&self, simulation_result: SimulationResult<Self>, time_step: f64) -> Self {
+    fn respond(&self, simulation_result: SimulationResult<Self>, time_step: Float) -> Self {
         let mut vx = self.vx;
         let mut vy = self.vy;
         let mut vz = self.vz;
         let mut collided_with = Vec::new();
         let (mut ax, mut ay, mut az) = simulation_result.gravitational_acceleration;
-        for other in simulation_result.collisions {
+        for other in simulation_result.colliding_entities() {
             collided_with.push(other.clone());
-            let (collision_ax, collision_ay, collision_az) = soft_body(self, other, 50f64);
+            let (collision_ax, collision_ay, collision_az) = soft_body(self, other, 50.);
             ax += collision_ax;
             ay += collision_ay;
             az += collision_az;
@@ -77,7 +83,13 @@ fn two_entities_collision() {
         MyEntity::new(0., 0., 1., 10., 5.),
     ];
 
-    let test_tree = GravTree::new(&vec_that_wants_to_be_a_kdtree, 0.2, 3, 0.2);
+    let test_tree = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.2,
+        3,
+        0.2,
+        CalculateCollisions::Yes,
+    );
     let after_time_step = test_tree.time_step().as_vec();
 
     // Each entity should have collided with exactly one other entity
@@ -93,7 +105,13 @@ fn two_entities_no_collision() {
         MyEntity::new(0., 0., 1., 10., 5.),
     ];
 
-    let test_tree = GravTree::new(&vec_that_wants_to_be_a_kdtree, 0.2, 3, 0.2);
+    let test_tree = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.2,
+        3,
+        0.2,
+        CalculateCollisions::Yes,
+    );
     let after_time_step = test_tree.time_step().as_vec();
 
     assert_eq!(after_time_step[0].collided_with.len(), 0);
@@ -108,20 +126,36 @@ fn two_entities_accel() {
         MyEntity::new(50., 0., 1., 10., 500.),
     ];
 
-    let test_tree = GravTree::new(&vec_that_wants_to_be_a_kdtree, 0.3, 3, 0.2);
-    let _after_time_step = test_tree.time_step().time_step().as_vec();
+    let mut test_tree = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.3,
+        3,
+        0.2,
+        CalculateCollisions::Yes,
+    );
+    test_tree.set_gravitational_constant(gravitational_constant::NBODY);
+    let entities = test_tree.as_vec();
+    let accelerations = test_tree.accelerations();
 
-    // 1.0 isn't right but it should at least not be 0, what the current test is suggesting
-    // Uncomment the following line when you're ready to fix this
-    // assert_eq!(after_time_step[0].vx, 1.);
+    // a = G * m_other * (r_other - r_self) / |r_other - r_self|³
+    let (dx, dy, dz) = (
+        entities[1].x - entities[0].x,
+        entities[1].y - entities[0].y,
+        entities[1].z - entities[0].z,
+    );
+    let d_cubed = Float::powi(Float::sqrt(dx * dx + dy * dy + dz * dz), 3);
+    let expected = (
+        entities[1].mass * dx / d_cubed,
+        entities[1].mass * dy / d_cubed,
+        entities[1].mass * dz / d_cubed,
+    );
+    assert!((accelerations[0].0 - expected.0).abs() < 1e-12);
+    assert!((accelerations[0].1 - expected.1).abs() < 1e-12);
+    assert!((accelerations[0].2 - expected.2).abs() < 1e-12);
 }
 
 /// Test that, given entities that are at the _exact same position_, the tree detects their collision.
-/// * NOTE:
-/// This is how things _should_ be, but isn't working quite yet. This is related to the fundamental
-/// structure of the tree and how it compares every particle to itself as part of the iteration.
-/// This is not ideal.
-/*
+/// The tree tells entities apart by their ids, so identical entities still collide.
 #[test]
 fn exact_overlap_collision() {
     let vec_that_wants_to_be_a_kdtree: Vec<MyEntity> = vec![
@@ -132,7 +166,13 @@ fn exact_overlap_collision() {
         MyEntity::new(0., 0., 1., 10., 5.),
     ];
 
-    let test_tree = GravTree::new(&vec_that_wants_to_be_a_kdtree, 0.2);
+    let test_tree = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.2,
+        3,
+        0.2,
+        CalculateCollisions::Yes,
+    );
     let after_time_step = test_tree.time_step().as_vec();
 
     // Each entity should have collided with exactly all four other entities
@@ -142,7 +182,6 @@ fn exact_overlap_collision() {
     assert_eq!(after_time_step[3].collided_with.len(), 4);
     assert_eq!(after_time_step[4].collided_with.len(), 4);
 }
-*/
 
 /// Test that, given five entities that are overlapping, the tree detects their collision.
 #[test]
@@ -155,7 +194,13 @@ fn five_entities_collision() {
         MyEntity::new(0., 1., 1., 10., 5.),
     ];
 
-    let test_tree = GravTree::new(&vec_that_wants_to_be_a_kdtree, 0.2, 3, 0.2);
+    let test_tree = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.2,
+        3,
+        0.2,
+        CalculateCollisions::Yes,
+    );
     let after_time_step = test_tree.time_step().as_vec();
 
     // Each entity should have collided with exactly all four other entities
@@ -166,8 +211,9 @@ fn five_entities_collision() {
     assert_eq!(after_time_step[4].collided_with.len(), 4);
 }
 /// Test that the gravitational acceleration of five particles is calculated correctly
-/// by verifying their velocity afterwards
+/// by verifying their velocity afterwards. The expected velocities were recorded in `f64`.
 #[test]
+#[cfg(not(feature = "f32"))]
 fn five_entities_accel() {
     let vec_that_wants_to_be_a_kdtree: Vec<MyEntity> = vec![
         MyEntity::new(0., 100., 0., 10., 50.),
@@ -177,22 +223,548 @@ fn five_entities_accel() {
         MyEntity::new(50., 100., 1., 10., 500.),
     ];
 
-    let test_tree = GravTree::new(&vec_that_wants_to_be_a_kdtree, 0.3, 3, 0.2);
-    let after_time_step = test_tree.time_step().time_step().as_vec();
+    let mut test_tree = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.3,
+        3,
+        0.2,
+        CalculateCollisions::Yes,
+    );
+    test_tree.set_force_law(ForceLaw::Legacy);
+    let test_tree = test_tree.time_step().time_step();
+    // The tree orders entities by position, so put them back in the order they were passed in.
+    let mut after_time_step = test_tree
+        .ids()
+        .into_iter()
+        .zip(test_tree.as_vec())
+        .collect::<Vec<_>>();
+    after_time_step.sort_by_key(|(id, _)| *id);
+    let after_time_step = after_time_step
+        .into_iter()
+        .map(|(_, entity)| entity)
+        .collect::<Vec<_>>();
 
-    assert_eq!(after_time_step[0].vx, 234.62426718543517);
-    assert_eq!(after_time_step[0].vy, -308.1666357163429);
-    assert_eq!(after_time_step[0].vz, 6.374453794316003);
+    assert_eq!(after_time_step[0].vx, 9.480900740707249);
+    assert_eq!(after_time_step[0].vy, -8.916497834378202);
+    assert_eq!(after_time_step[0].vz, 0.22629583024910993);
 
-    assert_eq!(after_time_step[1].vx, -728.0140671441542);
-    assert_eq!(after_time_step[1].vy, 961.0687440357227);
-    assert_eq!(after_time_step[1].vz, -22.318277419464977);
+    assert_eq!(after_time_step[1].vx, -6.627986936329425);
+    assert_eq!(after_time_step[1].vy, 23.602811217323506);
+    assert_eq!(after_time_step[1].vz, -0.0026193623208969623);
 
-    assert_eq!(after_time_step[2].vx, 68.99261434517841);
-    assert_eq!(after_time_step[2].vy, 202.1872542350379);
-    assert_eq!(after_time_step[2].vz, 0.28488247914098197);
+    assert_eq!(after_time_step[2].vx, -7.8361006909743125);
+    assert_eq!(after_time_step[2].vy, -13.033816937221616);
+    assert_eq!(after_time_step[2].vz, -0.0033071413254020564);
 
-    assert_eq!(after_time_step[3].vx, -232.853993009126);
-    assert_eq!(after_time_step[3].vy, 189.20383841899522);
-    assert_eq!(after_time_step[3].vz, -2.014092604958015);
+    assert_eq!(after_time_step[3].vx, 15.648614658606638);
+    assert_eq!(after_time_step[3].vy, 0.19718080930123996);
+    assert_eq!(after_time_step[3].vz, -0.004577257875615334);
+}
+
+/// Returns the x velocity which a tiny particle picks up in one time step from a single body of
+/// mass 100 which is `distance` away from it along the x axis.
+fn velocity_from_single_body(distance: Float, force_law: ForceLaw, g: Float) -> Float {
+    let vec_that_wants_to_be_a_kdtree: Vec<MyEntity> = vec![
+        MyEntity::new(0., 0., 0., 0.1, 1e-9),
+        MyEntity::new(distance, 0., 0., 0.1, 100.),
+    ];
+    let mut test_tree = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        1.,
+        3,
+        0.2,
+        CalculateCollisions::No,
+    );
+    test_tree.set_force_law(force_law);
+    test_tree.set_gravitational_constant(g);
+    let after_time_step = test_tree.time_step().as_vec();
+    after_time_step
+        .iter()
+        .find(|e| e.mass < 1.)
+        .expect("test particle was dropped")
+        .vx
+}
+
+/// Test that Newtonian gravity falls off with the square of the distance, while the legacy force
+/// law falls off linearly.
+#[test]
+fn inverse_square_force_law() {
+    let g = gravitational_constant::NBODY;
+    let near = velocity_from_single_body(10., ForceLaw::Newtonian, g);
+    let far = velocity_from_single_body(20., ForceLaw::Newtonian, g);
+    assert!(near > 0.);
+    assert!((near / far - 4.).abs() < 1e-12);
+
+    let near = velocity_from_single_body(10., ForceLaw::Legacy, g);
+    let far = velocity_from_single_body(20., ForceLaw::Legacy, g);
+    assert!((near / far - 2.).abs() < 1e-12);
+}
+
+/// Test that the Newtonian acceleration scales linearly with the gravitational constant and that
+/// the legacy force law ignores it.
+#[test]
+fn gravitational_constant_scaling() {
+    let nbody = velocity_from_single_body(10., ForceLaw::Newtonian, gravitational_constant::NBODY);
+    let si = velocity_from_single_body(10., ForceLaw::Newtonian, gravitational_constant::SI);
+    assert!((si / nbody - gravitational_constant::SI).abs() < 1e-20);
+
+    let nbody = velocity_from_single_body(10., ForceLaw::Legacy, gravitational_constant::NBODY);
+    let si = velocity_from_single_body(10., ForceLaw::Legacy, gravitational_constant::SI);
+    assert_eq!(nbody, si);
+}
+
+/// Returns `n` entities scattered randomly in a 100x100x100 box.
+fn random_entities(n: usize) -> Vec<MyEntity> {
+    (0..n)
+        .map(|_| {
+            MyEntity::new(
+                rand::random::<Float>() * 100.,
+                rand::random::<Float>() * 100.,
+                rand::random::<Float>() * 100.,
+                0.01,
+                rand::random::<Float>() * 10. + 1.,
+            )
+        })
+        .collect()
+}
+
+/// Test that, when theta is zero and no node is ever approximated, the tree walk reproduces the
+/// exact pairwise sum for every particle.
+#[test]
+fn tree_matches_direct_sum_without_approximation() {
+    let test_tree = GravTree::new(&random_entities(500), 0.2, 3, 0., CalculateCollisions::No);
+    for error in test_tree.acceleration_errors() {
+        assert!(error < 1e-10, "relative error was {}", error);
+    }
+}
+
+/// Test that approximating distant nodes keeps the tree walk close to the exact pairwise sum.
+#[test]
+fn tree_approximates_direct_sum() {
+    let test_tree = GravTree::new(&random_entities(2000), 0.2, 3, 0.5, CalculateCollisions::No);
+    let errors = test_tree.acceleration_errors();
+    let mean_error = errors.iter().sum::<Float>() / errors.len() as Float;
+    assert!(mean_error < 0.01, "mean relative error was {}", mean_error);
+}
+
+/// Returns the mean relative error of the tree walk over `entities`, for the given theta and
+/// multipole order.
+fn mean_error(entities: &[MyEntity], theta: Float, order: MultipoleOrder) -> Float {
+    let mut test_tree = GravTree::new(entities, 0.2, 3, theta, CalculateCollisions::No);
+    test_tree.set_multipole_order(order);
+    let errors = test_tree.acceleration_errors();
+    errors.iter().sum::<Float>() / errors.len() as Float
+}
+
+/// Test that every extra term of the multipole expansion makes the tree walk more accurate, so
+/// that a larger theta gives the same accuracy.
+#[test]
+fn multipole_expansion_improves_accuracy() {
+    let entities = random_entities(2000);
+    let monopole = mean_error(&entities, 0.5, MultipoleOrder::Monopole);
+    let quadrupole = mean_error(&entities, 0.5, MultipoleOrder::Quadrupole);
+    let octupole = mean_error(&entities, 0.5, MultipoleOrder::Octupole);
+    assert!(quadrupole < monopole / 2.);
+    assert!(octupole < quadrupole);
+    assert!(mean_error(&entities, 0.6, MultipoleOrder::Octupole) < monopole);
+}
+
+/// Test that the fast multipole method reproduces the exact pairwise sum when theta is zero, and
+/// that it is at least as accurate as the Barnes-Hut walk when nodes are approximated.
+#[test]
+fn fast_multipole_approximates_direct_sum() {
+    let mut test_tree = GravTree::new(&random_entities(500), 0.2, 3, 0., CalculateCollisions::No);
+    test_tree.set_solver(Solver::FastMultipole);
+    for error in test_tree.acceleration_errors() {
+        assert!(error < 1e-10, "relative error was {}", error);
+    }
+
+    let mut test_tree = GravTree::new(&random_entities(2000), 0.2, 3, 0.5, CalculateCollisions::No);
+    let errors = test_tree.acceleration_errors();
+    let barnes_hut = errors.iter().sum::<Float>() / errors.len() as Float;
+    test_tree.set_solver(Solver::FastMultipole);
+    let errors = test_tree.acceleration_errors();
+    let fast_multipole = errors.iter().sum::<Float>() / errors.len() as Float;
+    assert!(
+        fast_multipole < 0.005,
+        "mean relative error was {}",
+        fast_multipole
+    );
+    assert!(fast_multipole < barnes_hut);
+}
+
+fn mean(errors: Vec<Float>) -> Float {
+    errors.iter().sum::<Float>() / errors.len() as Float
+}
+
+/// Test that the grouped walk is at least as accurate as the walk for every entity, since it only
+/// approximates the nodes which every entity in a leaf would, with every opening criterion.
+#[test]
+fn grouped_barnes_hut_approximates_direct_sum() {
+    let entities = random_entities(2000);
+    let errors = |solver, criterion: Option<RelativeForce>| {
+        let mut test_tree = GravTree::new(&entities, 0.2, 16, 0.5, CalculateCollisions::No);
+        test_tree.set_solver(solver);
+        match criterion {
+            Some(criterion) => {
+                test_tree.set_opening_criterion(criterion);
+                mean(test_tree.time_step().acceleration_errors())
+            }
+            None => mean(test_tree.acceleration_errors()),
+        }
+    };
+    let (barnes_hut, grouped) = (
+        errors(Solver::BarnesHut, None),
+        errors(Solver::GroupedBarnesHut, None),
+    );
+    assert!(grouped < 0.01, "mean relative error was {}", grouped);
+    assert!(grouped < barnes_hut);
+    let (barnes_hut, grouped) = (
+        errors(Solver::BarnesHut, Some(RelativeForce(0.005))),
+        errors(Solver::GroupedBarnesHut, Some(RelativeForce(0.005))),
+    );
+    assert!(grouped < barnes_hut);
+}
+
+/// Test that every built-in opening criterion keeps the tree walk close to the exact pairwise sum,
+/// and that the relative force criterion becomes more accurate as `alpha` shrinks once it knows
+/// the accelerations from the last time step.
+#[test]
+fn opening_criteria_approximate_direct_sum() {
+    let entities = random_entities(2000);
+    let mut test_tree = GravTree::new(&entities, 0.2, 3, 0.5, CalculateCollisions::No);
+    let opening_angle = mean(test_tree.acceleration_errors());
+    test_tree.set_opening_criterion(SalmonWarren);
+    let salmon_warren = mean(test_tree.acceleration_errors());
+    assert!(
+        salmon_warren < 0.01,
+        "mean relative error was {}",
+        salmon_warren
+    );
+
+    let relative_force = |alpha| {
+        let mut test_tree = GravTree::new(&entities, 0.2, 3, 0.5, CalculateCollisions::No);
+        test_tree.set_opening_criterion(RelativeForce(alpha));
+        // Without the accelerations from the last time step, the opening angle is used.
+        assert_eq!(mean(test_tree.acceleration_errors()), opening_angle);
+        mean(test_tree.time_step().acceleration_errors())
+    };
+    let coarse = relative_force(0.01);
+    let fine = relative_force(0.001);
+    assert!(fine < 0.005, "mean relative error was {}", fine);
+    assert!(fine < coarse);
+}
+
+/// An opening criterion which never approximates a node.
+struct NeverApproximate;
+
+impl OpeningCriterion for NeverApproximate {
+    fn accepts(&self, _node: &CandidateNode) -> bool {
+        false
+    }
+}
+
+/// Test that a custom opening criterion replaces the built-in one.
+#[test]
+fn custom_opening_criterion() {
+    let mut test_tree = GravTree::new(&random_entities(500), 0.2, 3, 0.5, CalculateCollisions::No);
+    test_tree.set_opening_criterion(NeverApproximate);
+    for error in test_tree.acceleration_errors() {
+        assert!(error < 1e-10, "relative error was {}", error);
+    }
+}
+
+/// Test that every kind of tree reproduces the exact pairwise sum when theta is zero, and stays
+/// close to it when nodes are approximated.
+#[test]
+fn tree_kinds_approximate_direct_sum() {
+    let entities = random_entities(2000);
+    for kind in [TreeKind::Octree, TreeKind::Morton, TreeKind::Hilbert] {
+        let mut test_tree = GravTree::new(&entities, 0.2, 3, 0., CalculateCollisions::No);
+        test_tree.set_tree_kind(kind);
+        for error in test_tree.acceleration_errors() {
+            assert!(error < 1e-10, "{:?}: relative error was {}", kind, error);
+        }
+
+        let mut test_tree = GravTree::new(&entities, 0.2, 3, 0.5, CalculateCollisions::No);
+        test_tree.set_tree_kind(kind);
+        let error = mean(test_tree.acceleration_errors());
+        assert!(
+            error < 0.01,
+            "{:?}: mean relative error was {}",
+            kind,
+            error
+        );
+    }
+}
+
+/// Returns `tolerance`, which is written for `f64`, loosened to allow for the coarser rounding of
+/// `f32` when the `f32` feature is enabled.
+fn rounding(tolerance: Float) -> Float {
+    Float::max(tolerance, 1e2 * Float::EPSILON)
+}
+
+/// Test that the SIMD and scalar leaf kernels give bit-for-bit identical accelerations, for both
+/// force laws and with leaves large enough to fill the SIMD lanes, and that they still match the
+/// exact pairwise sum.
+#[test]
+fn leaf_kernels_are_identical() {
+    let mut entities = random_entities(1000);
+    // An entity at exactly the same position as another must not attract it.
+    entities.push(entities[0].clone());
+    for force_law in [ForceLaw::Newtonian, ForceLaw::Legacy] {
+        let mut test_tree = GravTree::new(&entities, 0.2, 13, 0.5, CalculateCollisions::No);
+        test_tree.set_force_law(force_law);
+        test_tree.set_leaf_kernel(LeafKernel::Validate);
+        let validated = test_tree.accelerations();
+        test_tree.set_leaf_kernel(LeafKernel::Simd);
+        let simd = test_tree.accelerations();
+        test_tree.set_leaf_kernel(LeafKernel::Scalar);
+        let scalar = test_tree.accelerations();
+        for ((simd, scalar), validated) in simd.iter().zip(scalar.iter()).zip(validated.iter()) {
+            for (a, b) in [(simd.0, scalar.0), (simd.1, scalar.1), (simd.2, scalar.2)] {
+                assert_eq!(a.to_bits(), b.to_bits());
+            }
+            assert_eq!(simd, validated);
+        }
+    }
+
+    let mut test_tree = GravTree::new(&entities, 0.2, 13, 0., CalculateCollisions::No);
+    test_tree.set_leaf_kernel(LeafKernel::Validate);
+    for error in test_tree.acceleration_errors() {
+        assert!(error < rounding(1e-10), "relative error was {}", error);
+    }
+}
+
+/// Checks that the mixed precision kernels are also identical to each other, and that summing in
+/// `f32` stays close to the exact pairwise sum.
+#[test]
+fn mixed_precision_approximates_direct_sum() {
+    let mut entities = random_entities(1000);
+    entities.push(entities[0].clone());
+    let mut test_tree = GravTree::new(&entities, 0.2, 13, 0., CalculateCollisions::No);
+    test_tree.set_precision(Precision::Mixed);
+    test_tree.set_leaf_kernel(LeafKernel::Simd);
+    let simd = test_tree.accelerations();
+    test_tree.set_leaf_kernel(LeafKernel::Scalar);
+    let scalar = test_tree.accelerations();
+    for (simd, scalar) in simd.iter().zip(scalar.iter()) {
+        for (a, b) in [(simd.0, scalar.0), (simd.1, scalar.1), (simd.2, scalar.2)] {
+            assert_eq!(a.to_bits(), b.to_bits());
+        }
+    }
+
+    test_tree.set_leaf_kernel(LeafKernel::Validate);
+    for error in test_tree.acceleration_errors() {
+        assert!(error < 1e-5, "relative error was {}", error);
+    }
+}
+
+/// Returns the ids of every pair of entities in `tree` which come within the sum of their radii of
+/// each other at some point in the next `time_step`, with the smaller id first, found by comparing
+/// every pair at their closest approach.
+fn colliding_pairs(tree: &GravTree<MyEntity>, time_step: Float) -> Vec<(EntityId, EntityId)> {
+    let entries = tree.ids().into_iter().zip(tree.iter()).collect::<Vec<_>>();
+    let mut pairs = Vec::new();
+    for (i, (id, a)) in entries.iter().enumerate() {
+        for (other_id, b) in entries[i + 1..].iter() {
+            let p = [a.x - b.x, a.y - b.y, a.z - b.z];
+            let v = [a.vx - b.vx, a.vy - b.vy, a.vz - b.vz];
+            let speed_squared = v.iter().map(|v| v * v).sum::<Float>();
+            let closest = if speed_squared == 0. {
+                0.
+            } else {
+                let t = -p.iter().zip(v.iter()).map(|(p, v)| p * v).sum::<Float>() / speed_squared;
+                t.max(0.).min(time_step)
+            };
+            let distance = p
+                .iter()
+                .zip(v.iter())
+                .map(|(p, v)| (p + v * closest).powi(2))
+                .sum::<Float>()
+                .sqrt();
+            if distance <= a.radius + b.radius {
+                pairs.push((Ord::min(*id, *other_id), Ord::max(*id, *other_id)));
+            }
+        }
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+/// Test that the broad phase reports every overlapping pair exactly once, including the pairs
+/// with a few large entities which the tree walk approximates the other entities around, and
+/// that every solver reports the same collisions in a time step however large theta is.
+#[test]
+fn broad_phase_finds_every_collision() {
+    let mut entities = random_entities(2000);
+    for entity in entities.iter_mut().take(1000) {
+        entity.radius = rand::random::<Float>() * 2.;
+    }
+    for entity in entities.iter_mut().take(5) {
+        entity.radius = 20.;
+    }
+    for solver in [
+        Solver::BarnesHut,
+        Solver::GroupedBarnesHut,
+        Solver::FastMultipole,
+    ] {
+        let mut test_tree = GravTree::new(&entities, 0.2, 8, 1.5, CalculateCollisions::Yes);
+        test_tree.set_solver(solver);
+        let expected = colliding_pairs(&test_tree, 0.);
+        assert!(expected.len() > 100);
+        assert_eq!(test_tree.collisions(), expected);
+        let mut counts = HashMap::new();
+        for (id, other_id) in expected {
+            *counts.entry(id).or_insert(0) += 1;
+            *counts.entry(other_id).or_insert(0) += 1;
+        }
+        let stepped = test_tree.time_step();
+        for (id, entity) in stepped.ids().into_iter().zip(stepped.iter()) {
+            assert_eq!(
+                entity.collided_with.len(),
+                counts.get(&id).copied().unwrap_or(0)
+            );
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, AsEntity)]
+struct Projectile {
+    x: Float,
+    y: Float,
+    z: Float,
+    vx: Float,
+    vy: Float,
+    vz: Float,
+    radius: Float,
+    mass: Float,
+    contacts: Vec<ContactRecord>,
+}
+
+/// The parts of a [Contact](bigbang::Contact) which do not borrow from the tree.
+#[derive(Clone, Debug, PartialEq)]
+struct ContactRecord {
+    time: Float,
+    normal: (Float, Float, Float),
+    penetration_depth: Float,
+    point: (Float, Float, Float),
+    normal_velocity: Float,
+}
+
+impl Projectile {
+    fn new(x: Float, vx: Float) -> Projectile {
+        Projectile {
+            x,
+            y: 0.,
+            z: 0.,
+            vx,
+            vy: 0.,
+            vz: 0.,
+            radius: 1.,
+            mass: 1e-9,
+            contacts: Vec::new(),
+        }
+    }
+}
+
+impl Responsive for Projectile {
+    fn respond(&self, simulation_result: SimulationResult<Self>, time_step: Float) -> Self {
+        assert!(simulation_result
+            .colliding_entities()
+            .zip(simulation_result.collisions.iter())
+            .all(|(other, contact)| std::ptr::eq(other, contact.other)));
+        Projectile {
+            x: self.x + self.vx * time_step,
+            y: self.y + self.vy * time_step,
+            z: self.z + self.vz * time_step,
+            contacts: simulation_result
+                .collisions
+                .iter()
+                .map(|contact| ContactRecord {
+                    time: contact.time,
+                    normal: contact.normal,
+                    penetration_depth: contact.penetration_depth,
+                    point: contact.point,
+                    normal_velocity: contact.normal_velocity,
+                })
+                .collect(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Test that the contact between two overlapping entities describes their overlap, from the
+/// point of view of each of them.
+#[test]
+fn contacts_describe_the_overlap() {
+    let projectiles = vec![Projectile::new(0., 1.), Projectile::new(1.5, -2.)];
+    let test_tree = GravTree::new(&projectiles, 0.2, 3, 0.2, CalculateCollisions::Yes);
+    for x in test_tree.time_step().iter() {
+        let direction = -x.vx.signum();
+        assert_eq!(
+            x.contacts,
+            vec![ContactRecord {
+                time: 0.,
+                normal: (direction, 0., 0.),
+                penetration_depth: 0.5,
+                point: (0.75, 0., 0.),
+                normal_velocity: -3.,
+            }]
+        );
+    }
+}
+
+/// Test that two fast entities which pass through each other within a single time step only
+/// collide with continuous collision detection, which reports the contact at the moment they
+/// first touch.
+#[test]
+fn continuous_collision_detection_catches_fast_entities() {
+    let projectiles = vec![Projectile::new(-10., 100.), Projectile::new(10., -100.)];
+    let mut test_tree = GravTree::new(&projectiles, 0.2, 3, 0.2, CalculateCollisions::Yes);
+    assert!(test_tree.collisions().is_empty());
+    let stepped = test_tree.time_step();
+    assert!(stepped.iter().all(|x| x.contacts.is_empty()));
+    assert!(stepped.iter().all(|x| x.x.abs() == 10.));
+
+    test_tree.set_collision_detection(CollisionDetection::Continuous);
+    assert_eq!(test_tree.collisions(), vec![(EntityId(0), EntityId(1))]);
+    for x in test_tree.time_step().iter() {
+        assert_eq!(x.contacts.len(), 1);
+        let contact = &x.contacts[0];
+        assert!(
+            (contact.time - 0.09).abs() < 1e-12,
+            "time was {}",
+            contact.time
+        );
+        assert_eq!(contact.normal, (-x.vx.signum(), 0., 0.));
+        assert!(contact.penetration_depth < 1e-12);
+        assert!(contact.point.0.abs() < 1e-12);
+        assert!((contact.normal_velocity + 200.).abs() < 1e-9);
+    }
+}
+
+/// Test that continuous collision detection reports every pair of randomly moving entities which
+/// come close enough to touch during the time step exactly once, however they are split between
+/// the nodes of the tree.
+#[test]
+fn continuous_broad_phase_finds_every_collision() {
+    let mut entities = random_entities(2000);
+    for entity in entities.iter_mut() {
+        entity.radius = rand::random::<Float>() * 0.5;
+        entity.vx = (rand::random::<Float>() - 0.5) * 20.;
+        entity.vy = (rand::random::<Float>() - 0.5) * 20.;
+        entity.vz = (rand::random::<Float>() - 0.5) * 20.;
+    }
+    for entity in entities.iter_mut().take(10) {
+        entity.vx *= 20.;
+    }
+    let mut test_tree = GravTree::new(&entities, 0.5, 8, 0.5, CalculateCollisions::Yes);
+    let discrete = test_tree.collisions();
+    assert_eq!(discrete, colliding_pairs(&test_tree, 0.));
+    test_tree.set_collision_detection(CollisionDetection::Continuous);
+    let continuous = test_tree.collisions();
+    assert_eq!(continuous, colliding_pairs(&test_tree, 0.5));
+    assert!(continuous.len() > discrete.len());
+    assert!(discrete.iter().all(|pair| continuous.contains(pair)));
 }
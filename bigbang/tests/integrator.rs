@@ -0,0 +1,326 @@
+extern crate bigbang;
+use bigbang::{
+    gravitational_constant, AsEntity, CalculateCollisions, Float, GravTree, Integrator, Responsive,
+    SimulationResult, TimeStepCriterion,
+};
+
+#[derive(Clone, PartialEq, AsEntity)]
+struct Body {
+    x: Float,
+    y: Float,
+    z: Float,
+    vx: Float,
+    vy: Float,
+    vz: Float,
+    radius: Float,
+    mass: Float,
+    jerk: Option<(Float, Float, Float)>,
+}
+
+impl Responsive for Body {
+    /// Gravity is handled by the integrator, so all that is left is to record the jerk.
+    fn respond(&self, simulation_result: SimulationResult<Self>, _time_step: Float) -> Self {
+        Body {
+            jerk: simulation_result.jerk,
+            ..self.clone()
+        }
+    }
+}
+
+/// Returns two equal unit masses a unit distance apart, moving on a circular orbit around their
+/// common center of mass.
+fn binary() -> Vec<Body> {
+    // v² / r = G * m / d², with r = 0.5 and d = 1.
+    let v = Float::sqrt(0.5);
+    let body = |x: Float, vy: Float| Body {
+        x,
+        y: 0.,
+        z: 0.,
+        vx: 0.,
+        vy,
+        vz: 0.,
+        radius: 0.01,
+        mass: 1.,
+        jerk: None,
+    };
+    vec![body(-0.5, -v), body(0.5, v)]
+}
+
+/// Returns the total kinetic and potential energy of the two bodies.
+fn energy(bodies: &[Body]) -> Float {
+    let kinetic = bodies.iter().fold(0., |acc, b| {
+        acc + 0.5 * b.mass * (b.vx * b.vx + b.vy * b.vy + b.vz * b.vz)
+    });
+    let (dx, dy, dz) = (
+        bodies[1].x - bodies[0].x,
+        bodies[1].y - bodies[0].y,
+        bodies[1].z - bodies[0].z,
+    );
+    let potential = -bodies[0].mass * bodies[1].mass / Float::sqrt(dx * dx + dy * dy + dz * dz);
+    kinetic + potential
+}
+
+/// Runs the binary for ten orbits with the given integrator and returns the final bodies.
+fn run_binary(integrator: Integrator) -> Vec<Body> {
+    let time_step = 0.01;
+    let period = 2. * (std::f64::consts::PI as Float) * 0.5 / Float::sqrt(0.5);
+    let mut tree = GravTree::new(&binary(), time_step, 3, 0.2, CalculateCollisions::No);
+    tree.set_gravitational_constant(gravitational_constant::NBODY);
+    tree.set_integrator(integrator);
+    for _ in 0..(10. * period / time_step) as usize {
+        tree = tree.time_step();
+    }
+    tree.as_vec()
+}
+
+/// Test that the leapfrog integrator keeps a circular orbit's energy and separation.
+#[test]
+fn leapfrog_conserves_energy() {
+    let initial = energy(&binary());
+    let bodies = run_binary(Integrator::Leapfrog);
+    assert!(((energy(&bodies) - initial) / initial).abs() < 1e-4);
+    let separation =
+        Float::sqrt((bodies[1].x - bodies[0].x).powi(2) + (bodies[1].y - bodies[0].y).powi(2));
+    assert!((separation - 1.).abs() < 1e-3);
+}
+
+/// Returns `tolerance`, which is written for `f64`, loosened to allow for the rounding of `f32`
+/// over the thousands of steps of `run_binary` when the `f32` feature is enabled.
+fn rounding(tolerance: Float) -> Float {
+    Float::max(tolerance, 1e3 * Float::EPSILON)
+}
+
+/// Test that velocity Verlet follows the same trajectory as leapfrog, up to rounding.
+#[test]
+fn velocity_verlet_matches_leapfrog() {
+    let leapfrog = run_binary(Integrator::Leapfrog);
+    let verlet = run_binary(Integrator::VelocityVerlet);
+    for (a, b) in leapfrog.iter().zip(verlet.iter()) {
+        assert!((a.x - b.x).abs() < rounding(1e-8));
+        assert!((a.y - b.y).abs() < rounding(1e-8));
+        assert!((a.vx - b.vx).abs() < rounding(1e-8));
+        assert!((a.vy - b.vy).abs() < rounding(1e-8));
+    }
+}
+
+/// Returns the distance between the first body and where it should be after the ten orbits run by
+/// `run_binary`.
+fn phase_error(bodies: &[Body]) -> Float {
+    let time_step = 0.01;
+    let period = 2. * (std::f64::consts::PI as Float) * 0.5 / Float::sqrt(0.5);
+    let t = (10. * period / time_step) as usize as Float * time_step;
+    let angle = t * 2. * (std::f64::consts::PI as Float) / period;
+    let (x, y) = (-0.5 * angle.cos(), -0.5 * angle.sin());
+    Float::sqrt((bodies[0].x - x).powi(2) + (bodies[0].y - y).powi(2))
+}
+
+/// Test that the fourth order integrators conserve energy and follow the orbit far more closely
+/// than leapfrog does.
+#[test]
+fn fourth_order_integrators_are_accurate() {
+    let initial = energy(&binary());
+    let leapfrog = phase_error(&run_binary(Integrator::Leapfrog));
+    for integrator in &[Integrator::Hermite, Integrator::RungeKutta4] {
+        let bodies = run_binary(*integrator);
+        let error = ((energy(&bodies) - initial) / initial).abs();
+        assert!(
+            error < rounding(1e-8),
+            "{:?} energy error was {}",
+            integrator,
+            error
+        );
+        assert!(phase_error(&bodies) < rounding(leapfrog / 100.));
+    }
+}
+
+/// Test that the jerk is passed to `respond` when using the Hermite integrator. On a circular
+/// orbit with angular velocity ω, the jerk is -ω² times the velocity.
+#[test]
+fn hermite_exposes_jerk() {
+    let mut tree = GravTree::new(&binary(), 0.001, 3, 0.2, CalculateCollisions::No);
+    tree.set_gravitational_constant(gravitational_constant::NBODY);
+    tree.set_integrator(Integrator::Hermite);
+    let omega_squared = 2.;
+    for body in tree.time_step().as_vec() {
+        let (jx, jy, jz) = body.jerk.expect("the jerk was not calculated");
+        assert!((jx + omega_squared * body.vx).abs() < 1e-5);
+        assert!((jy + omega_squared * body.vy).abs() < 1e-5);
+        assert!(jz.abs() < 1e-12);
+    }
+
+    // Other integrators do not calculate the jerk.
+    tree.set_integrator(Integrator::Leapfrog);
+    for body in tree.time_step().as_vec() {
+        assert!(body.jerk.is_none());
+    }
+}
+
+/// Test that `advance_to` ends exactly at the target time, shortening the last step, and follows
+/// the same trajectory as calling `time_step` repeatedly.
+#[test]
+fn advance_to_reaches_target_time() {
+    let mut tree = GravTree::new(&binary(), 0.01, 3, 0.2, CalculateCollisions::No);
+    tree.set_gravitational_constant(gravitational_constant::NBODY);
+    tree.set_integrator(Integrator::Leapfrog);
+    let advanced = tree.advance_to(0.505);
+    assert_eq!(advanced.time(), 0.505);
+
+    let mut stepped = tree;
+    for _ in 0..50 {
+        stepped = stepped.time_step();
+    }
+    let (advanced, stepped) = (advanced.as_vec(), stepped.as_vec());
+    for (a, b) in advanced.iter().zip(stepped.iter()) {
+        // The extra half step moves the bodies by about v * dt / 2.
+        let distance = Float::sqrt((a.x - b.x).powi(2) + (a.y - b.y).powi(2));
+        assert!((distance - Float::sqrt(0.5) * 0.005).abs() < 1e-4);
+    }
+}
+
+/// A body which counts the time steps it has been moved through.
+#[derive(Clone, PartialEq, AsEntity)]
+struct CountedBody {
+    x: Float,
+    y: Float,
+    z: Float,
+    vx: Float,
+    vy: Float,
+    vz: Float,
+    radius: Float,
+    mass: Float,
+    steps: usize,
+}
+
+impl Responsive for CountedBody {
+    fn respond(&self, _simulation_result: SimulationResult<Self>, _time_step: Float) -> Self {
+        CountedBody {
+            steps: self.steps + 1,
+            ..self.clone()
+        }
+    }
+}
+
+/// Test that `advance_to` does not take an extra, vanishingly small step when adding up the time
+/// steps rounds to just short of the target time, as adding up ten steps of 0.1 does.
+#[test]
+fn advance_to_absorbs_rounding() {
+    let bodies = binary()
+        .into_iter()
+        .map(|body| CountedBody {
+            x: body.x,
+            y: body.y,
+            z: body.z,
+            vx: body.vx,
+            vy: body.vy,
+            vz: body.vz,
+            radius: body.radius,
+            mass: body.mass,
+            steps: 0,
+        })
+        .collect::<Vec<_>>();
+    let tree = GravTree::new(&bodies, 0.1, 3, 0.2, CalculateCollisions::No);
+    let advanced = tree.advance_to(1.);
+    assert_eq!(advanced.time(), 1.);
+    for body in advanced.as_vec() {
+        assert_eq!(body.steps, 10);
+    }
+}
+
+/// Test that a time step criterion can only be combined with the integrators which support block
+/// time steps.
+#[test]
+#[should_panic]
+fn block_time_steps_need_leapfrog() {
+    let mut tree = GravTree::new(&binary(), 0.1, 3, 0.2, CalculateCollisions::No);
+    tree.set_integrator(Integrator::Leapfrog);
+    tree.set_time_step_criterion(TimeStepCriterion::Acceleration(0.002));
+    tree.set_integrator(Integrator::Hermite);
+}
+
+/// Returns a tight binary orbited at a distance by a third body.
+fn hierarchical_triple() -> Vec<Body> {
+    let mut bodies = binary();
+    for body in bodies.iter_mut() {
+        // Shrinking the binary by a factor of 100 speeds it up by a factor of 10.
+        body.x /= 100.;
+        body.vy *= 10.;
+        body.radius = 1e-3;
+    }
+    // The third body orbits the binary's center of mass at a distance of 10.
+    let v = Float::sqrt(2. / 10.);
+    bodies.push(Body {
+        x: 10.,
+        vy: v,
+        radius: 1e-3,
+        ..bodies[0].clone()
+    });
+    bodies
+}
+
+/// Returns the total kinetic and potential energy of any number of bodies.
+fn total_energy(bodies: &[Body]) -> Float {
+    let mut energy = 0.;
+    for (i, a) in bodies.iter().enumerate() {
+        energy += 0.5 * a.mass * (a.vx * a.vx + a.vy * a.vy + a.vz * a.vz);
+        for b in &bodies[i + 1..] {
+            let distance =
+                Float::sqrt((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2));
+            energy -= a.mass * b.mass / distance;
+        }
+    }
+    energy
+}
+
+/// Test that block time steps give the tight binary much shorter steps than the distant body,
+/// while conserving energy.
+#[test]
+fn block_time_steps() {
+    for criterion in &[
+        TimeStepCriterion::Acceleration(0.002),
+        TimeStepCriterion::Aarseth(0.02),
+    ] {
+        let bodies = hierarchical_triple();
+        let mut tree = GravTree::new(&bodies, 0.1, 3, 0.2, CalculateCollisions::No);
+        tree.set_gravitational_constant(gravitational_constant::NBODY);
+        tree.set_integrator(Integrator::Leapfrog);
+        tree.set_time_step_criterion(*criterion);
+        let tree = tree.advance_to(0.25);
+        assert_eq!(tree.time(), 0.25);
+
+        let after = tree.as_vec();
+        let time_steps = tree.time_steps();
+        let (binary_step, distant_step) =
+            after
+                .iter()
+                .zip(time_steps.iter())
+                .fold((0., 0.), |(binary, distant), (body, dt)| {
+                    if body.x > 1. {
+                        (binary, *dt)
+                    } else {
+                        (Float::max(binary, *dt), distant)
+                    }
+                });
+        assert!(binary_step * 100. < distant_step, "{:?}", criterion);
+        let initial = total_energy(&bodies);
+        let error = ((total_energy(&after) - initial) / initial).abs();
+        assert!(error < 1e-3, "{:?} energy error was {}", criterion, error);
+    }
+}
+
+/// Test that point masses, which have no length for the acceleration criterion to use, take the
+/// tree's time step rather than being put on ever finer levels.
+#[test]
+fn block_time_steps_with_point_masses() {
+    let mut bodies = hierarchical_triple();
+    for body in bodies.iter_mut() {
+        body.radius = 0.;
+    }
+    let mut tree = GravTree::new(&bodies, 0.1, 3, 0.2, CalculateCollisions::No);
+    tree.set_gravitational_constant(gravitational_constant::NBODY);
+    tree.set_integrator(Integrator::Leapfrog);
+    tree.set_time_step_criterion(TimeStepCriterion::Acceleration(0.002));
+    tree.time_step_mut();
+    assert!(tree.time_steps().iter().all(|dt| *dt == 0.1));
+    let tree = tree.advance_to(0.25);
+    assert_eq!(tree.time(), 0.25);
+}
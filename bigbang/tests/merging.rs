@@ -0,0 +1,239 @@
+extern crate bigbang;
+use bigbang::{
+    gravitational_constant, AsEntity, CalculateCollisions, CollisionDetection, Entity, EntityId,
+    Float, GravTree, Integrator, MergePolicy, Responsive, SimulationResult,
+};
+
+#[derive(Clone, PartialEq, AsEntity)]
+struct Planetesimal {
+    x: Float,
+    y: Float,
+    z: Float,
+    vx: Float,
+    vy: Float,
+    vz: Float,
+    radius: Float,
+    mass: Float,
+    /// The mass of iron in the planetesimal, which is carried over when it merges.
+    iron: Float,
+}
+
+impl Planetesimal {
+    fn new(
+        position: (Float, Float, Float),
+        velocity: (Float, Float, Float),
+        radius: Float,
+    ) -> Planetesimal {
+        Planetesimal {
+            x: position.0,
+            y: position.1,
+            z: position.2,
+            vx: velocity.0,
+            vy: velocity.1,
+            vz: velocity.2,
+            radius,
+            mass: radius.powi(3),
+            iron: radius.powi(3) / 3.,
+        }
+    }
+}
+
+impl Responsive for Planetesimal {
+    fn respond(&self, _simulation_result: SimulationResult<Self>, _time_step: Float) -> Self {
+        self.clone()
+    }
+}
+
+/// Builds the merged planetesimal from the entity chosen by the tree, adding up the iron.
+fn accrete(a: &Planetesimal, b: &Planetesimal, merged: &Entity) -> Planetesimal {
+    Planetesimal {
+        x: merged.x,
+        y: merged.y,
+        z: merged.z,
+        vx: merged.vx,
+        vy: merged.vy,
+        vz: merged.vz,
+        radius: merged.radius,
+        mass: merged.mass,
+        iron: a.iron + b.iron,
+    }
+}
+
+/// Merges colliding planetesimals only if they approach each other slower than `max_speed`, and
+/// otherwise leaves them to bounce.
+struct SlowCollisions {
+    max_speed: Float,
+}
+
+impl MergePolicy<Planetesimal> for SlowCollisions {
+    fn merges(&self, a: &Planetesimal, b: &Planetesimal) -> bool {
+        let speed = ((a.vx - b.vx).powi(2) + (a.vy - b.vy).powi(2) + (a.vz - b.vz).powi(2)).sqrt();
+        speed < self.max_speed
+    }
+
+    fn merge(&self, a: &Planetesimal, b: &Planetesimal, merged: &Entity) -> Planetesimal {
+        accrete(a, b, merged)
+    }
+}
+
+/// Returns the total mass, iron and momentum of the planetesimals in the tree.
+fn totals(tree: &GravTree<Planetesimal>) -> (Float, Float, (Float, Float, Float)) {
+    tree.iter()
+        .fold((0., 0., (0., 0., 0.)), |(mass, iron, p), x| {
+            (
+                mass + x.mass,
+                iron + x.iron,
+                (
+                    p.0 + x.mass * x.vx,
+                    p.1 + x.mass * x.vy,
+                    p.2 + x.mass * x.vz,
+                ),
+            )
+        })
+}
+
+/// Returns `tolerance`, which is written for `f64`, loosened to allow for the coarser rounding of
+/// `f32` when the `f32` feature is enabled.
+fn rounding(tolerance: Float) -> Float {
+    Float::max(tolerance, 1e2 * Float::EPSILON)
+}
+
+fn assert_close(a: Float, b: Float) {
+    assert!(
+        (a - b).abs() <= rounding(1e-9) * Float::max(a.abs(), b.abs()).max(1.),
+        "{} is not {}",
+        a,
+        b
+    );
+}
+
+/// Test that two colliding planetesimals merge into one at their center of mass, with their
+/// summed mass, volume and momentum, and their combined custom fields.
+#[test]
+fn colliding_entities_merge() {
+    let entities = vec![
+        Planetesimal::new((0., 0., 0.), (1., 0., 0.), 1.),
+        Planetesimal::new((1.5, 0., 0.), (-1., 2., 0.), 2.),
+        Planetesimal::new((20., 0., 0.), (0., 0., 0.), 1.),
+    ];
+    let mut tree = GravTree::new(&entities, 0.01, 3, 0.5, CalculateCollisions::No);
+    tree.set_gravitational_constant(0.);
+    tree.set_merge_policy(accrete);
+    let tree = tree.time_step();
+
+    assert_eq!(tree.get_number_of_entities(), 2);
+    assert!(tree.get(EntityId(0)).is_none());
+    assert!(tree.get(EntityId(1)).is_none());
+    assert!(tree.get(EntityId(2)).is_some());
+    let merged = tree
+        .get(EntityId(3))
+        .expect("the merged entity was not inserted");
+    assert_close(merged.mass, 9.);
+    assert_close(merged.iron, 3.);
+    assert_close(merged.radius, Float::cbrt(9.));
+    assert_close(merged.x, 1.5 * 8. / 9.);
+    assert_close(merged.vx, (1. - 8.) / 9.);
+    assert_close(merged.vy, 16. / 9.);
+}
+
+/// Test that a chain of planetesimals which each touch the next merges into a single one, and that
+/// nothing merges without a merge policy.
+#[test]
+fn chains_merge_into_one() {
+    let entities = (0..5)
+        .map(|i| Planetesimal::new((i as Float * 1.9, 0., 0.), (0., i as Float, 0.), 1.))
+        .collect::<Vec<_>>();
+    let mut tree = GravTree::new(&entities, 0.01, 3, 0.5, CalculateCollisions::No);
+    tree.set_gravitational_constant(0.);
+    assert_eq!(tree.time_step().get_number_of_entities(), 5);
+
+    let (mass, iron, momentum) = totals(&tree);
+    tree.set_merge_policy(accrete);
+    tree.time_step_mut();
+    assert_eq!(tree.ids(), vec![EntityId(5)]);
+    let (merged_mass, merged_iron, merged_momentum) = totals(&tree);
+    assert_close(merged_mass, mass);
+    assert_close(merged_iron, iron);
+    assert_close(merged_momentum.1, momentum.1);
+}
+
+/// Test that a merge policy can leave some colliding entities unmerged.
+#[test]
+fn policies_choose_which_entities_merge() {
+    let entities = vec![
+        Planetesimal::new((0., 0., 0.), (0.1, 0., 0.), 1.),
+        Planetesimal::new((1.5, 0., 0.), (0., 0., 0.), 1.),
+        Planetesimal::new((10., 0., 0.), (100., 0., 0.), 1.),
+        Planetesimal::new((11.5, 0., 0.), (0., 0., 0.), 1.),
+    ];
+    let mut tree = GravTree::new(&entities, 0.001, 3, 0.5, CalculateCollisions::No);
+    tree.set_gravitational_constant(0.);
+    tree.set_merge_policy(SlowCollisions { max_speed: 1. });
+    let tree = tree.time_step();
+    let mut ids = tree.ids();
+    ids.sort();
+    assert_eq!(ids, vec![EntityId(2), EntityId(3), EntityId(4)]);
+}
+
+/// Test that with continuous collision detection, planetesimals which are about to collide during
+/// the next time step are only merged once they actually overlap.
+#[test]
+fn continuous_detection_merges_overlapping_entities() {
+    let entities = vec![
+        Planetesimal::new((0., 0., 0.), (20., 0., 0.), 1.),
+        Planetesimal::new((5., 0., 0.), (0., 0., 0.), 1.),
+    ];
+    let mut tree = GravTree::new(&entities, 0.1, 3, 0.5, CalculateCollisions::No);
+    tree.set_gravitational_constant(0.);
+    tree.set_integrator(Integrator::Leapfrog);
+    tree.set_collision_detection(CollisionDetection::Continuous);
+    tree.set_merge_policy(accrete);
+
+    // The first planetesimal ends the step a unit short of touching the second, which it covers
+    // twice over in the next step.
+    tree.time_step_mut();
+    assert_eq!(tree.get_number_of_entities(), 2);
+    assert_eq!(tree.collisions(), vec![(EntityId(0), EntityId(1))]);
+
+    tree.time_step_mut();
+    assert_eq!(tree.ids(), vec![EntityId(2)]);
+}
+
+/// Test that a cloud of planetesimals which accretes under its own gravity with a built-in
+/// integrator conserves its mass and momentum as the number of planetesimals falls.
+#[test]
+fn accretion_conserves_momentum() {
+    let entities = (0..300)
+        .map(|_| {
+            let random = || rand::random::<Float>() - 0.5;
+            Planetesimal::new(
+                (random() * 40., random() * 40., random() * 40.),
+                (random(), random(), random()),
+                rand::random::<Float>() * 0.5 + 0.5,
+            )
+        })
+        .collect::<Vec<_>>();
+    let mut tree = GravTree::new(&entities, 0.1, 3, 0.5, CalculateCollisions::No);
+    tree.set_gravitational_constant(gravitational_constant::NBODY);
+    tree.set_integrator(Integrator::Leapfrog);
+    tree.set_merge_policy(accrete);
+    let (mass, iron, momentum) = totals(&tree);
+    for _ in 0..20 {
+        tree.time_step_mut();
+    }
+    assert!(tree.get_number_of_entities() < 300);
+    let (merged_mass, merged_iron, merged_momentum) = totals(&tree);
+    assert_close(merged_mass, mass);
+    assert_close(merged_iron, iron);
+    // Gravity conserves momentum up to the tree's approximation, so only the error from that is
+    // allowed, measured against the momentum of a single planetesimal.
+    for (p, merged_p) in [
+        (momentum.0, merged_momentum.0),
+        (momentum.1, merged_momentum.1),
+        (momentum.2, merged_momentum.2),
+    ]
+    .iter()
+    {
+        assert!((p - merged_p).abs() < 0.1, "{} is not {}", merged_p, p);
+    }
+}
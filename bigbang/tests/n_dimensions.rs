@@ -0,0 +1,143 @@
+extern crate bigbang;
+use bigbang::{
+    gravitational_constant, AsEntity, CalculateCollisions, Entity, Float, ForceLaw, GravTree,
+    Responsive, SimulationResult, TreeKind,
+};
+
+#[derive(Clone, PartialEq)]
+struct Body<const D: usize> {
+    position: [Float; D],
+    velocity: [Float; D],
+    mass: Float,
+}
+
+/// Returns the `D` values padded with zeros to three dimensions.
+fn padded<const D: usize>(values: [Float; D]) -> [Float; 3] {
+    let mut padded = [0.; 3];
+    padded[..D].copy_from_slice(&values);
+    padded
+}
+
+impl<const D: usize> AsEntity<D> for Body<D> {
+    fn as_entity(&self) -> Entity {
+        let ([x, y, z], [vx, vy, vz]) = (padded(self.position), padded(self.velocity));
+        Entity {
+            x,
+            y,
+            z,
+            vx,
+            vy,
+            vz,
+            radius: 0.01,
+            mass: self.mass,
+            softening_length: 0.,
+        }
+    }
+
+    fn set_entity(&mut self, entity: &Entity) {
+        self.position
+            .copy_from_slice(&[entity.x, entity.y, entity.z][..D]);
+        self.velocity
+            .copy_from_slice(&[entity.vx, entity.vy, entity.vz][..D]);
+    }
+}
+
+impl<const D: usize> Responsive for Body<D> {
+    fn respond(&self, simulation_result: SimulationResult<Self>, time_step: Float) -> Self {
+        let (ax, ay, az) = simulation_result.gravitational_acceleration;
+        let mut body = self.clone();
+        for (axis, a) in [ax, ay, az].iter().enumerate() {
+            if axis < D {
+                body.velocity[axis] += a * time_step;
+                body.position[axis] += body.velocity[axis] * time_step;
+            } else {
+                assert_eq!(*a, 0.);
+            }
+        }
+        body
+    }
+}
+
+fn random_bodies<const D: usize>(n: usize) -> Vec<Body<D>> {
+    (0..n)
+        .map(|_| {
+            let mut position = [0.; D];
+            for x in position.iter_mut() {
+                *x = rand::random::<Float>() * 100.;
+            }
+            Body {
+                position,
+                velocity: [0.; D],
+                mass: rand::random::<Float>() * 10. + 1.,
+            }
+        })
+        .collect()
+}
+
+/// Returns `tolerance`, which is written for `f64`, loosened to allow for the coarser rounding of
+/// `f32` when the `f32` feature is enabled.
+fn rounding(tolerance: Float) -> Float {
+    Float::max(tolerance, 1e2 * Float::EPSILON)
+}
+
+/// Test that in a sheet model, where the force does not depend on distance, every sheet is pulled
+/// by the difference between the mass on either side of it, and the tree gets this right even when
+/// it approximates nodes.
+#[test]
+fn sheet_model() {
+    let bodies = random_bodies::<1>(1000);
+    let mut tree = GravTree::new(&bodies, 0.1, 3, 0.5, CalculateCollisions::No);
+    tree.set_gravitational_constant(gravitational_constant::NBODY);
+    tree.set_force_law(ForceLaw::Sheet);
+    let all = tree.iter().cloned().collect::<Vec<_>>();
+    for (body, (a, _, _)) in tree.iter().zip(tree.accelerations()) {
+        let (left, right) = all.iter().fold((0., 0.), |(left, right), other| {
+            if other.position[0] < body.position[0] {
+                (left + other.mass, right)
+            } else if other.position[0] > body.position[0] {
+                (left, right + other.mass)
+            } else {
+                (left, right)
+            }
+        });
+        assert!(
+            (a - (right - left)).abs() < rounding(1e-12) * (left + right),
+            "acceleration was {}, expected {}",
+            a,
+            right - left
+        );
+    }
+    let stepped = tree.time_step();
+    assert_eq!(stepped.get_number_of_entities(), 1000);
+}
+
+/// Test that every kind of tree reproduces the exact pairwise sum in one, two and three
+/// dimensions when nothing is approximated, and stays close to it when nodes are. The bodies
+/// near the middle of a line feel almost no net force, so their relative error can be large
+/// however accurate the tree is, and the typical body's error is checked instead of the mean.
+#[test]
+fn every_dimension_matches_direct_sum() {
+    fn check<const D: usize>() {
+        let bodies = random_bodies::<D>(500);
+        for kind in [
+            TreeKind::KdTree,
+            TreeKind::Octree,
+            TreeKind::Morton,
+            TreeKind::Hilbert,
+        ] {
+            let mut tree = GravTree::new(&bodies, 0.1, 3, 0., CalculateCollisions::No);
+            tree.set_tree_kind(kind);
+            for error in tree.acceleration_errors() {
+                assert!(error < 1e-10, "relative error was {}", error);
+            }
+            tree.set_theta(0.2);
+            let mut errors = tree.acceleration_errors();
+            errors.sort_by(|a, b| a.partial_cmp(b).expect("errors are not NaN"));
+            let median = errors[errors.len() / 2];
+            assert!(median < 1e-2, "median relative error was {}", median);
+        }
+    }
+    check::<1>();
+    check::<2>();
+    check::<3>();
+}
@@ -0,0 +1,114 @@
+extern crate bigbang;
+use bigbang::{
+    gravitational_constant, AsEntity, CalculateCollisions, Float, GravTree, Responsive,
+    SimulationResult, Softening,
+};
+
+#[derive(Clone, PartialEq, AsEntity)]
+struct SoftEntity {
+    x: Float,
+    y: Float,
+    z: Float,
+    vx: Float,
+    vy: Float,
+    vz: Float,
+    radius: Float,
+    mass: Float,
+    softening_length: Float,
+}
+
+impl SoftEntity {
+    fn new(x: Float, mass: Float, softening_length: Float) -> SoftEntity {
+        SoftEntity {
+            x,
+            y: 0.,
+            z: 0.,
+            vx: 0.,
+            vy: 0.,
+            vz: 0.,
+            radius: 0.01,
+            mass,
+            softening_length,
+        }
+    }
+}
+
+impl Responsive for SoftEntity {
+    fn respond(&self, _simulation_result: SimulationResult<Self>, _time_step: Float) -> Self {
+        self.clone()
+    }
+}
+
+/// Returns the x acceleration on a tiny particle at the origin from a body of mass 100 placed
+/// `distance` away along the x axis.
+fn acceleration_at(
+    distance: Float,
+    softening: Softening,
+    softening_lengths: (Float, Float),
+) -> Float {
+    let entities = vec![
+        SoftEntity::new(0., 1e-9, softening_lengths.0),
+        SoftEntity::new(distance, 100., softening_lengths.1),
+    ];
+    let mut tree = GravTree::new(&entities, 0.1, 3, 0.2, CalculateCollisions::No);
+    tree.set_gravitational_constant(gravitational_constant::NBODY);
+    tree.set_softening(softening);
+    let index = tree
+        .as_vec()
+        .iter()
+        .position(|e| e.mass < 1.)
+        .expect("test particle was dropped");
+    tree.accelerations()[index].0
+}
+
+/// Test that Plummer softening follows `G * m * r / (r² + ε²)^(3/2)`.
+#[test]
+fn plummer_softening() {
+    let (r, epsilon): (Float, Float) = (2., 1.5);
+    let expected = 100. * r / (r * r + epsilon * epsilon).powf(1.5);
+    let actual = acceleration_at(r, Softening::Plummer(epsilon), (0., 0.));
+    assert!((actual - expected).abs() < 1e-12);
+}
+
+/// Returns `tolerance`, which is written for `f64`, loosened to allow for the coarser rounding of
+/// `f32` when the `f32` feature is enabled.
+fn rounding(tolerance: Float) -> Float {
+    Float::max(tolerance, 1e2 * Float::EPSILON)
+}
+
+/// Test that the spline kernel is exactly Newtonian beyond its support, weaker inside it, and
+/// continuous across its edge.
+#[test]
+fn spline_softening() {
+    let epsilon = 1.;
+    let h = 2.8 * epsilon;
+    let newtonian = |r: Float| 100. / (r * r);
+
+    let outside = acceleration_at(3., Softening::Spline(epsilon), (0., 0.));
+    assert!((outside - newtonian(3.)).abs() < rounding(1e-12) * newtonian(3.));
+
+    let inside = acceleration_at(1., Softening::Spline(epsilon), (0., 0.));
+    assert!(inside > 0. && inside < newtonian(1.));
+
+    let just_inside = acceleration_at(h * (1. - 1e-9), Softening::Spline(epsilon), (0., 0.));
+    assert!((just_inside / newtonian(h) - 1.).abs() < 1e-6);
+
+    // Deep inside the kernel the force vanishes linearly with distance.
+    let tiny = acceleration_at(1e-4, Softening::Spline(epsilon), (0., 0.));
+    let tinier = acceleration_at(5e-5, Softening::Spline(epsilon), (0., 0.));
+    assert!((tiny / tinier - 2.).abs() < 1e-3);
+}
+
+/// Test that the largest of the two entities' softening lengths and the tree-wide length is used.
+#[test]
+fn per_entity_softening() {
+    let tree_wide = acceleration_at(2., Softening::Plummer(1.5), (0., 0.));
+    let per_entity = acceleration_at(2., Softening::Plummer(0.), (0., 1.5));
+    assert_eq!(tree_wide, per_entity);
+    let larger = acceleration_at(2., Softening::Plummer(0.5), (1.5, 0.));
+    assert_eq!(tree_wide, larger);
+
+    // Per-entity lengths have no effect without a softening kernel.
+    let unsoftened = acceleration_at(2., Softening::None, (1.5, 1.5));
+    assert!((unsoftened - 25.).abs() < 1e-12);
+}
@@ -1,20 +1,23 @@
 extern crate bigbang;
-use bigbang::{AsEntity, Entity, GravTree, Responsive, SimulationResult};
+use bigbang::{
+    AsEntity, CalculateCollisions, Entity, EntityId, Float, GravTree, Integrator, Responsive,
+    SimulationResult, Solver, TimeStepCriterion, TreeKind, TreeUpdate,
+};
 
 #[derive(Clone, PartialEq)]
 struct MyEntity {
-    x: f64,
-    y: f64,
-    z: f64,
-    vx: f64,
-    vy: f64,
-    vz: f64,
-    radius: f64,
+    x: Float,
+    y: Float,
+    z: Float,
+    vx: Float,
+    vy: Float,
+    vz: Float,
+    radius: Float,
 }
 
 impl AsEntity for MyEntity {
     fn as_entity(&self) -> Entity {
-        return Entity {
+        Entity {
             x: self.x,
             y: self.y,
             z: self.z,
@@ -23,21 +26,31 @@ impl AsEntity for MyEntity {
             vz: self.vz,
             radius: self.radius,
             mass: if self.radius < 1. { 0.5 } else { 105. },
-        };
+            softening_length: 0.,
+        }
+    }
+
+    fn set_entity(&mut self, entity: &Entity) {
+        self.vx = entity.vx;
+        self.vy = entity.vy;
+        self.vz = entity.vz;
+        self.x = entity.x;
+        self.y = entity.y;
+        self.z = entity.z;
     }
 }
 
 impl Responsive for MyEntity {
-    fn respond(&self, simulation_result: SimulationResult<MyEntity>, time_step: f64) -> Self {
+    fn respond(&self, simulation_result: SimulationResult<MyEntity>, time_step: Float) -> Self {
         let (ax, ay, _az) = simulation_result.gravitational_acceleration;
         let (x, y, z) = (self.x, self.y, self.z);
         let (mut vx, mut vy, mut vz) = (self.vx, self.vy, self.vz);
         let self_mass = if self.radius < 1. { 0.5 } else { 105. };
         // calculate the collisions
-        for other in simulation_result.collisions.clone() {
+        for other in simulation_result.colliding_entities() {
             let other_mass = if other.radius < 1. { 0.5 } else { 105. };
             let mass_coefficient_v1 = (self_mass - other_mass) / (self_mass + other_mass);
-            let mass_coefficient_v2 = (2f64 * other_mass) / (self_mass + other_mass);
+            let mass_coefficient_v2 = (2. * other_mass) / (self_mass + other_mass);
             vx = (mass_coefficient_v1 * vx) + (mass_coefficient_v2 * other.vx);
             vy = (mass_coefficient_v1 * vy) + (mass_coefficient_v2 * other.vy);
             vz = (mass_coefficient_v1 * vz) + (mass_coefficient_v2 * other.vz);
@@ -59,13 +72,13 @@ impl Responsive for MyEntity {
 impl MyEntity {
     pub fn random_entity() -> MyEntity {
         MyEntity {
-            vx: 0f64,
-            vy: 0f64,
-            vz: 0f64,
-            x: rand::random::<f64>() * 50f64,
-            y: rand::random::<f64>() * 50f64,
-            z: rand::random::<f64>() * 50f64,
-            radius: rand::random::<f64>() / 10f64,
+            vx: 0.,
+            vy: 0.,
+            vz: 0.,
+            x: rand::random::<Float>() * 50.,
+            y: rand::random::<Float>() * 50.,
+            z: rand::random::<Float>() * 50.,
+            radius: rand::random::<Float>() / 10.,
         }
     }
 }
@@ -78,7 +91,7 @@ fn test_traversal() {
         vec.push(entity);
     }
     let vec_clone = vec.clone();
-    let tree = GravTree::new(&vec, 0.2, 3, 0.2);
+    let tree = GravTree::new(&vec, 0.2, 3, 0.2, CalculateCollisions::Yes);
     let traversed_vec = tree.as_vec();
     let mut all_found = true;
     for i in vec_clone {
@@ -98,7 +111,428 @@ fn test_time_step() {
         vec_that_wants_to_be_a_kdtree.push(entity);
     }
 
-    let test_tree = GravTree::new(&vec_that_wants_to_be_a_kdtree, 0.2, 3, 0.2);
+    let test_tree = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.2,
+        3,
+        0.2,
+        CalculateCollisions::Yes,
+    );
     let after_time_step = test_tree.time_step();
     assert_eq!(after_time_step.as_vec().len(), 1000);
 }
+
+#[test]
+fn test_time_step_mut() {
+    let mut vec_that_wants_to_be_a_kdtree: Vec<MyEntity> = Vec::new();
+    for _ in 0..1000 {
+        let entity = MyEntity::random_entity();
+        vec_that_wants_to_be_a_kdtree.push(entity);
+    }
+
+    let mut immutable_tree = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.2,
+        3,
+        0.2,
+        CalculateCollisions::Yes,
+    );
+    let mut mutable_tree = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.2,
+        3,
+        0.2,
+        CalculateCollisions::Yes,
+    );
+    for _ in 0..3 {
+        immutable_tree = immutable_tree.time_step();
+        mutable_tree.time_step_mut();
+    }
+    // Updating the tree in place should give exactly the same result as building a new one.
+    assert!(immutable_tree.as_vec() == mutable_tree.as_vec());
+    assert_eq!(immutable_tree.accelerations(), mutable_tree.accelerations());
+    assert_eq!(mutable_tree.time(), immutable_tree.time());
+}
+
+/// An entity which does not implement `Clone`.
+struct Unclonable(Entity);
+
+impl AsEntity for Unclonable {
+    fn as_entity(&self) -> Entity {
+        self.0.clone()
+    }
+
+    fn set_entity(&mut self, entity: &Entity) {
+        self.0.set_entity(entity);
+    }
+}
+
+impl Responsive for Unclonable {
+    fn respond(&self, _simulation_result: SimulationResult<Self>, _time_step: Float) -> Self {
+        Unclonable(self.0.clone())
+    }
+}
+
+#[test]
+fn test_without_clone() {
+    let entities = (0..100)
+        .map(|_| Unclonable(MyEntity::random_entity().as_entity()))
+        .collect::<Vec<_>>();
+    let mut test_tree = GravTree::from_vec(entities, 0.2, 3, 0.2, CalculateCollisions::Yes);
+    assert_eq!(test_tree.iter().count(), 100);
+    assert_eq!(test_tree.accelerations().len(), 100);
+
+    // Every integrator moves the entities in place when time stepping the tree in place.
+    for integrator in [
+        Integrator::None,
+        Integrator::Leapfrog,
+        Integrator::VelocityVerlet,
+        Integrator::Hermite,
+        Integrator::RungeKutta4,
+    ] {
+        test_tree.set_integrator(integrator);
+        test_tree.time_step_mut();
+    }
+    test_tree.set_integrator(Integrator::Leapfrog);
+    test_tree.set_time_step_criterion(TimeStepCriterion::Acceleration(0.025));
+    test_tree.time_step_mut();
+    assert_eq!(test_tree.iter().count(), 100);
+    assert!((test_tree.time() - 1.2).abs() < 1e-6);
+}
+
+/// An entity which only implements the required methods of `AsEntity`, as written before the
+/// built-in integrators existed.
+#[derive(Clone)]
+struct Immovable(Entity);
+
+impl AsEntity for Immovable {
+    fn as_entity(&self) -> Entity {
+        self.0.clone()
+    }
+}
+
+impl Responsive for Immovable {
+    fn respond(&self, simulation_result: SimulationResult<Self>, time_step: Float) -> Self {
+        let (ax, ay, az) = simulation_result.gravitational_acceleration;
+        let mut entity = self.0.clone();
+        entity.vx += ax * time_step;
+        entity.vy += ay * time_step;
+        entity.vz += az * time_step;
+        entity.x += entity.vx * time_step;
+        entity.y += entity.vy * time_step;
+        entity.z += entity.vz * time_step;
+        Immovable(entity)
+    }
+}
+
+#[test]
+fn test_without_set_entity() {
+    let entities = (0..100)
+        .map(|_| Immovable(MyEntity::random_entity().as_entity()))
+        .collect::<Vec<_>>();
+    let mut test_tree = GravTree::new(&entities, 0.2, 3, 0.2, CalculateCollisions::No);
+    test_tree = test_tree.time_step();
+    test_tree.time_step_mut();
+    assert_eq!(test_tree.iter().count(), 100);
+}
+
+#[test]
+#[should_panic(expected = "set_entity must be implemented")]
+fn test_integrator_without_set_entity() {
+    let entities = (0..10)
+        .map(|_| Immovable(MyEntity::random_entity().as_entity()))
+        .collect::<Vec<_>>();
+    let mut test_tree = GravTree::new(&entities, 0.2, 3, 0.2, CalculateCollisions::No);
+    test_tree.set_integrator(Integrator::Leapfrog);
+    test_tree.time_step_mut();
+}
+
+#[test]
+fn test_entity_ids() {
+    let vec_that_wants_to_be_a_kdtree = (0..100)
+        .map(|_| MyEntity::random_entity())
+        .collect::<Vec<_>>();
+    let mut test_tree = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.2,
+        3,
+        0.2,
+        CalculateCollisions::No,
+    );
+    let mut ids = test_tree.ids();
+    ids.sort();
+    assert_eq!(ids, (0..100).map(EntityId).collect::<Vec<_>>());
+    let ids = ids;
+    assert!(test_tree.get(ids[42]) == Some(&vec_that_wants_to_be_a_kdtree[42]));
+
+    // Ids are kept across time steps, although the entities may be reordered.
+    test_tree = test_tree.time_step();
+    test_tree.time_step_mut();
+    let mut after = test_tree.ids();
+    after.sort();
+    assert_eq!(after, ids);
+
+    let updated = test_tree.update(ids[42], |entity| entity.x = -100.);
+    assert!(updated);
+    assert_eq!(test_tree.get(ids[42]).map(|entity| entity.x), Some(-100.));
+
+    let removed = test_tree
+        .remove(ids[42])
+        .expect("entity 42 was in the tree");
+    assert_eq!(removed.x, -100.);
+    assert!(test_tree.get(ids[42]).is_none());
+    assert!(test_tree.remove(ids[42]).is_none());
+    assert!(!test_tree.update(ids[42], |entity| entity.x = 0.));
+    assert_eq!(test_tree.get_number_of_entities(), 99);
+    assert_eq!(test_tree.as_vec().len(), 99);
+    assert!(test_tree.get(ids[43]).is_some());
+    test_tree.time_step_mut();
+    assert_eq!(test_tree.ids().len(), 99);
+}
+
+/// Test that entities can be given their own ids, and that looking them up and updating them by
+/// id keeps the tree exact.
+#[test]
+fn test_new_with_ids() {
+    let vec_that_wants_to_be_a_kdtree = (0..100)
+        .map(|_| MyEntity::random_entity())
+        .collect::<Vec<_>>();
+    let ids = (0..100).map(|i| EntityId(1000 + 7 * i)).collect::<Vec<_>>();
+    let mut test_tree = GravTree::new_with_ids(
+        &vec_that_wants_to_be_a_kdtree,
+        &ids,
+        0.2,
+        3,
+        0.,
+        CalculateCollisions::No,
+    );
+    for (id, entity) in ids.iter().zip(vec_that_wants_to_be_a_kdtree.iter()) {
+        assert!(test_tree.get(*id) == Some(entity));
+    }
+    assert!(test_tree.get(EntityId(0)).is_none());
+    assert_eq!(
+        test_tree.insert(MyEntity::random_entity()),
+        EntityId(1000 + 7 * 99 + 1)
+    );
+
+    // Moving an entity updates the nodes along the way to its leaf, so the tree stays exact.
+    assert!(test_tree.update(ids[10], |entity| entity.x = 500.));
+    assert_eq!(test_tree.get(ids[10]).map(|entity| entity.x), Some(500.));
+    let errors = test_tree.acceleration_errors();
+    assert!(errors.iter().all(|error| *error < 1e-10));
+}
+
+/// Test that the same id cannot be given to two entities.
+#[test]
+#[should_panic]
+fn test_new_with_repeated_ids() {
+    let entities = (0..3)
+        .map(|_| MyEntity::random_entity())
+        .collect::<Vec<_>>();
+    GravTree::new_with_ids(
+        &entities,
+        &[EntityId(1), EntityId(2), EntityId(1)],
+        0.2,
+        3,
+        0.2,
+        CalculateCollisions::No,
+    );
+}
+
+#[test]
+fn test_insert_remove_retain() {
+    let vec_that_wants_to_be_a_kdtree = (0..200)
+        .map(|_| MyEntity::random_entity())
+        .collect::<Vec<_>>();
+    let mut test_tree = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.2,
+        3,
+        0.,
+        CalculateCollisions::No,
+    );
+    let mut inserted = Vec::new();
+    for _ in 0..100 {
+        inserted.push(test_tree.insert(MyEntity::random_entity()));
+    }
+    assert_eq!(inserted[0], EntityId(200));
+    assert_eq!(test_tree.get_number_of_entities(), 300);
+    assert_eq!(test_tree.as_vec().len(), 300);
+
+    for id in inserted.iter().step_by(2) {
+        assert!(test_tree.remove(*id).is_some());
+    }
+    for (id, entity) in test_tree.ids().into_iter().zip(test_tree.iter()) {
+        assert!(test_tree.get(id) == Some(entity));
+    }
+    test_tree.retain(|entity| entity.x < 25.);
+    let remaining = test_tree.as_vec();
+    assert_eq!(test_tree.get_number_of_entities(), remaining.len());
+    assert!(remaining.iter().all(|entity| entity.x < 25.));
+    for id in inserted.iter().skip(1).step_by(2) {
+        assert_eq!(test_tree.get(*id).is_some(), test_tree.ids().contains(id));
+    }
+
+    // With a theta of zero, the tree calculates exact accelerations, so the aggregate values of
+    // the nodes must have been kept up to date.
+    let errors = test_tree.acceleration_errors();
+    assert!(errors.iter().all(|error| *error < 1e-10));
+
+    test_tree.retain(|_| false);
+    assert_eq!(test_tree.get_number_of_entities(), 0);
+    let id = test_tree.insert(MyEntity::random_entity());
+    assert!(test_tree.get(id).is_some());
+}
+
+/// Test that every kind of tree keeps all of its entities through time steps, insertions and
+/// removals, and keeps the aggregate values of its nodes exact.
+#[test]
+fn test_tree_kinds() {
+    let vec_that_wants_to_be_a_kdtree = (0..500)
+        .map(|_| MyEntity::random_entity())
+        .collect::<Vec<_>>();
+    for kind in [TreeKind::Octree, TreeKind::Morton, TreeKind::Hilbert] {
+        let mut test_tree = GravTree::new(
+            &vec_that_wants_to_be_a_kdtree,
+            0.2,
+            3,
+            0.,
+            CalculateCollisions::Yes,
+        );
+        test_tree.set_tree_kind(kind);
+        let mut ids = test_tree.ids();
+        ids.sort();
+        assert_eq!(ids, (0..500).map(EntityId).collect::<Vec<_>>());
+
+        test_tree = test_tree.time_step();
+        test_tree.time_step_mut();
+        for _ in 0..50 {
+            test_tree.insert(MyEntity::random_entity());
+        }
+        for id in ids.iter().step_by(3) {
+            assert!(test_tree.remove(*id).is_some());
+        }
+        test_tree.time_step_mut();
+        assert_eq!(test_tree.ids().len(), 500 + 50 - 167);
+        for (id, entity) in test_tree.ids().into_iter().zip(test_tree.iter()) {
+            assert!(test_tree.get(id) == Some(entity));
+        }
+        let errors = test_tree.acceleration_errors();
+        assert!(errors.iter().all(|error| *error < 1e-10));
+    }
+}
+
+/// Test that refitting the tree instead of rebuilding it gives the same simulation, and keeps the
+/// aggregate values of the nodes exact as entities move across the splits between them.
+#[test]
+fn test_tree_update_refit() {
+    let vec_that_wants_to_be_a_kdtree = (0..500)
+        .map(|_| MyEntity {
+            vx: rand::random::<Float>() * 20. - 10.,
+            ..MyEntity::random_entity()
+        })
+        .collect::<Vec<_>>();
+    let mut rebuilt = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.2,
+        3,
+        0.,
+        CalculateCollisions::No,
+    );
+    let mut refit = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.2,
+        3,
+        0.,
+        CalculateCollisions::No,
+    );
+    refit.set_tree_update(TreeUpdate::Refit(0.1));
+    for _ in 0..5 {
+        rebuilt = rebuilt.time_step();
+        rebuilt.time_step_mut();
+        refit = refit.time_step();
+        refit.time_step_mut();
+    }
+
+    for id in rebuilt.ids() {
+        let (a, b) = (rebuilt.get(id).unwrap(), refit.get(id).unwrap());
+        assert!((a.x - b.x).abs() < 1e-9);
+        assert!((a.vx - b.vx).abs() < 1e-9);
+    }
+    let errors = refit.acceleration_errors();
+    assert!(errors.iter().all(|error| *error < 1e-10));
+}
+
+/// Test that time stepping with the fast multipole method gives the same simulation as the
+/// Barnes-Hut walk when neither approximates any nodes, collisions included.
+#[test]
+fn test_solver_fast_multipole() {
+    let vec_that_wants_to_be_a_kdtree = (0..300)
+        .map(|_| MyEntity {
+            radius: rand::random::<Float>() * 3.,
+            ..MyEntity::random_entity()
+        })
+        .collect::<Vec<_>>();
+    let mut barnes_hut = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.2,
+        3,
+        0.,
+        CalculateCollisions::Yes,
+    );
+    let mut fast_multipole = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.2,
+        3,
+        0.,
+        CalculateCollisions::Yes,
+    );
+    fast_multipole.set_solver(Solver::FastMultipole);
+    for _ in 0..3 {
+        barnes_hut = barnes_hut.time_step();
+        barnes_hut.time_step_mut();
+        fast_multipole = fast_multipole.time_step();
+        fast_multipole.time_step_mut();
+    }
+    for id in barnes_hut.ids() {
+        let (a, b) = (barnes_hut.get(id).unwrap(), fast_multipole.get(id).unwrap());
+        assert!((a.x - b.x).abs() < 1e-9);
+        assert!((a.vy - b.vy).abs() < 1e-9);
+    }
+}
+
+/// Test that the grouped walk gives exactly the same simulation as the walk for every entity when
+/// neither approximates any nodes, as both then sum every leaf in the same order.
+#[test]
+fn test_solver_grouped_barnes_hut() {
+    let vec_that_wants_to_be_a_kdtree = (0..300)
+        .map(|_| MyEntity {
+            radius: rand::random::<Float>() * 3.,
+            ..MyEntity::random_entity()
+        })
+        .collect::<Vec<_>>();
+    let mut barnes_hut = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.2,
+        8,
+        0.,
+        CalculateCollisions::Yes,
+    );
+    let mut grouped = GravTree::new(
+        &vec_that_wants_to_be_a_kdtree,
+        0.2,
+        8,
+        0.,
+        CalculateCollisions::Yes,
+    );
+    grouped.set_solver(Solver::GroupedBarnesHut);
+    for _ in 0..3 {
+        barnes_hut = barnes_hut.time_step();
+        barnes_hut.time_step_mut();
+        grouped = grouped.time_step();
+        grouped.time_step_mut();
+    }
+    for id in barnes_hut.ids() {
+        assert!(barnes_hut.get(id) == grouped.get(id));
+    }
+}
@@ -0,0 +1,179 @@
+extern crate bigbang;
+use bigbang::{
+    gravitational_constant, AsEntity, AsEntity2, CalculateCollisions, Entity, Float, ForceLaw,
+    GravTree, GravTree2, Responsive, SimulationResult,
+};
+
+#[derive(Clone, PartialEq, AsEntity2)]
+struct FlatEntity {
+    x: Float,
+    y: Float,
+    vx: Float,
+    vy: Float,
+    radius: Float,
+    mass: Float,
+    collisions: usize,
+}
+
+impl FlatEntity {
+    fn new(x: Float, y: Float, radius: Float, mass: Float) -> FlatEntity {
+        FlatEntity {
+            x,
+            y,
+            vx: 0.,
+            vy: 0.,
+            radius,
+            mass,
+            collisions: 0,
+        }
+    }
+}
+
+impl Responsive for FlatEntity {
+    fn respond(&self, simulation_result: SimulationResult<Self>, time_step: Float) -> Self {
+        let (ax, ay, az) = simulation_result.gravitational_acceleration;
+        assert_eq!(az, 0.);
+        let (vx, vy) = (self.vx + ax * time_step, self.vy + ay * time_step);
+        FlatEntity {
+            x: self.x + vx * time_step,
+            y: self.y + vy * time_step,
+            vx,
+            vy,
+            collisions: simulation_result.collisions.len(),
+            ..self.clone()
+        }
+    }
+}
+
+/// The same entities as a [FlatEntity], but in three dimensions with a `z` of zero.
+#[derive(Clone, PartialEq, AsEntity)]
+struct SpaceEntity {
+    x: Float,
+    y: Float,
+    z: Float,
+    vx: Float,
+    vy: Float,
+    vz: Float,
+    radius: Float,
+    mass: Float,
+}
+
+impl Responsive for SpaceEntity {
+    fn respond(&self, _simulation_result: SimulationResult<Self>, _time_step: Float) -> Self {
+        self.clone()
+    }
+}
+
+fn random_entities(n: usize) -> Vec<FlatEntity> {
+    (0..n)
+        .map(|_| {
+            FlatEntity::new(
+                rand::random::<Float>() * 100.,
+                rand::random::<Float>() * 100.,
+                0.01,
+                rand::random::<Float>() * 10. + 1.,
+            )
+        })
+        .collect()
+}
+
+/// Test that the 2-d tree reproduces the exact pairwise sum when nothing is approximated, and
+/// stays close to it when nodes are, for both force laws.
+#[test]
+fn tree2_approximates_direct_sum() {
+    let entities = random_entities(1000);
+    for force_law in [ForceLaw::Newtonian, ForceLaw::Logarithmic] {
+        let mut tree = GravTree2::new(&entities, 0.2, 3, 0., CalculateCollisions::No);
+        tree.set_force_law(force_law);
+        for error in tree.acceleration_errors() {
+            assert!(error < 1e-10, "relative error was {}", error);
+        }
+        tree.set_theta(0.2);
+        let errors = tree.acceleration_errors();
+        let mean = errors.iter().sum::<Float>() / errors.len() as Float;
+        assert!(mean < 1e-2, "mean relative error was {}", mean);
+    }
+}
+
+/// Returns `tolerance`, which is written for `f64`, loosened to allow for the coarser rounding of
+/// `f32` when the `f32` feature is enabled.
+fn rounding(tolerance: Float) -> Float {
+    Float::max(tolerance, 1e2 * Float::EPSILON)
+}
+
+/// Test that the Newtonian force law gives the same accelerations as a three dimensional tree of
+/// the same entities at `z = 0`.
+#[test]
+fn tree2_matches_flat_tree() {
+    let entities = random_entities(500);
+    let tree2 = GravTree2::new(&entities, 0.2, 3, 0., CalculateCollisions::No);
+    let flat = tree2
+        .iter()
+        .map(|entity| {
+            let entity = entity.as_entity();
+            SpaceEntity {
+                x: entity.x,
+                y: entity.y,
+                z: 0.,
+                vx: 0.,
+                vy: 0.,
+                vz: 0.,
+                radius: entity.radius,
+                mass: entity.mass,
+            }
+        })
+        .collect::<Vec<_>>();
+    let tree = GravTree::new(&flat, 0.2, 3, 0., CalculateCollisions::No);
+    let expected = tree
+        .iter()
+        .zip(tree.accelerations())
+        .map(|(entity, acceleration)| (entity.as_entity(), acceleration))
+        .collect::<Vec<(Entity, _)>>();
+    for (entity, (ax, ay, az)) in tree2.iter().zip(tree2.accelerations()) {
+        assert_eq!(az, 0.);
+        let (_, (ex, ey, ez)) = expected
+            .iter()
+            .find(|(other, _)| other.x == entity.x && other.y == entity.y)
+            .expect("entity was dropped");
+        assert_eq!(*ez, 0.);
+        let magnitude = Float::sqrt(ex * ex + ey * ey).max(1e-20);
+        assert!((ax - ex).abs() <= rounding(1e-10) * magnitude);
+        assert!((ay - ey).abs() <= rounding(1e-10) * magnitude);
+    }
+}
+
+/// Test that the logarithmic potential gives an acceleration of `G * m / r`.
+#[test]
+fn logarithmic_force_law() {
+    let entities = vec![
+        FlatEntity::new(0., 0., 0.01, 1e-9),
+        FlatEntity::new(4., 0., 0.01, 100.),
+    ];
+    let mut tree = GravTree2::new(&entities, 0.1, 3, 0.2, CalculateCollisions::No);
+    tree.set_gravitational_constant(gravitational_constant::NBODY);
+    tree.set_force_law(ForceLaw::Logarithmic);
+    let index = tree
+        .iter()
+        .position(|e| e.mass < 1.)
+        .expect("test particle was dropped");
+    let (ax, ay, _) = tree.accelerations()[index];
+    assert!((ax - 100. / 4.).abs() < 1e-12, "acceleration was {}", ax);
+    assert_eq!(ay, 0.);
+}
+
+/// Test that time stepping keeps every entity, moves them, and detects collisions in the plane.
+#[test]
+fn tree2_time_step() {
+    let mut entities = random_entities(200);
+    entities.push(FlatEntity::new(200., 200., 1., 1.));
+    entities.push(FlatEntity::new(201., 200., 1., 1.));
+    let mut tree = GravTree2::new(&entities, 0.1, 3, 0.2, CalculateCollisions::Yes);
+    tree.set_gravitational_constant(gravitational_constant::NBODY);
+    let stepped = tree.time_step();
+    assert_eq!(stepped.get_number_of_entities(), 202);
+    assert_eq!(stepped.as_vec().len(), 202);
+    assert_eq!(stepped.time(), 0.1);
+    let colliding = stepped.iter().filter(|e| e.collisions > 0).count();
+    assert_eq!(colliding, 2);
+    assert!(stepped.iter().all(|e| e.vx != 0. || e.vy != 0.));
+}
@@ -5,7 +5,21 @@ extern crate quote;
 extern crate syn;
 
 use crate::proc_macro::TokenStream;
-use syn::DeriveInput;
+use syn::{Data, DeriveInput, Fields};
+
+/// Returns true if the struct being derived has a named field called `field`.
+fn has_field(input: &DeriveInput, field: &str) -> bool {
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => fields
+                .named
+                .iter()
+                .any(|f| f.ident.iter().any(|ident| ident == field)),
+            _ => false,
+        },
+        _ => false,
+    }
+}
 
 #[proc_macro_derive(AsEntity)]
 pub fn derive_as_entity(input: TokenStream) -> TokenStream {
@@ -14,6 +28,13 @@ pub fn derive_as_entity(input: TokenStream) -> TokenStream {
     // type name
     let name = &input.ident;
 
+    // optional fields
+    let softening_length = if has_field(&input, "softening_length") {
+        quote! { self.softening_length }
+    } else {
+        quote! { 0. }
+    };
+
     // generics
     let generics = input.generics;
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
@@ -29,9 +50,65 @@ pub fn derive_as_entity(input: TokenStream) -> TokenStream {
                     y: self.y,
                     z: self.z,
                     radius: self.radius,
-                    mass: self.mass
+                    mass: self.mass,
+                    softening_length: #softening_length,
                 }
             }
+
+            fn set_entity(&mut self, entity: &bigbang::Entity) {
+                self.vx = entity.vx;
+                self.vy = entity.vy;
+                self.vz = entity.vz;
+                self.x = entity.x;
+                self.y = entity.y;
+                self.z = entity.z;
+            }
+        }
+    };
+
+    TokenStream::from(expanded)
+}
+
+#[proc_macro_derive(AsEntity2)]
+pub fn derive_as_entity2(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    // type name
+    let name = &input.ident;
+
+    // optional fields
+    let softening_length = if has_field(&input, "softening_length") {
+        quote! { self.softening_length }
+    } else {
+        quote! { 0. }
+    };
+
+    // generics
+    let generics = input.generics;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let expanded = quote! {
+        impl #impl_generics bigbang::AsEntity<2> for #name #ty_generics #where_clause {
+            fn as_entity(&self) -> bigbang::Entity {
+                bigbang::Entity {
+                    vx: self.vx,
+                    vy: self.vy,
+                    vz: 0.,
+                    x: self.x,
+                    y: self.y,
+                    z: 0.,
+                    radius: self.radius,
+                    mass: self.mass,
+                    softening_length: #softening_length,
+                }
+            }
+
+            fn set_entity(&mut self, entity: &bigbang::Entity) {
+                self.vx = entity.vx;
+                self.vy = entity.vy;
+                self.x = entity.x;
+                self.y = entity.y;
+            }
         }
     };
 
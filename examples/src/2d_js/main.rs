@@ -94,8 +94,16 @@ impl AsEntity for MyEntity {
             vz: 0.,
             radius: self.radius,
             mass: self.radius,
+            softening_length: 0.,
         }
     }
+
+    fn set_entity(&mut self, entity: &Entity) {
+        self.vx = entity.vx;
+        self.vy = entity.vy;
+        self.x = entity.x;
+        self.y = entity.y;
+    }
 }
 
 impl Responsive for MyEntity {
@@ -104,7 +112,7 @@ impl Responsive for MyEntity {
             // If there were some collisions, perform collision calculations instead of gravitational onees.
             let mut ax = 0.;
             let mut ay = 0.;
-            for other in &simulation_result.collisions {
+            for other in simulation_result.colliding_entities() {
                 let (collision_ax, collision_ay, _az) = soft_body(self, other, 200000f64);
                 ax += collision_ax;
                 ay += collision_ay;
@@ -185,13 +193,15 @@ fn main() {
     big_boi_2.radius = 1f64;
     big_boi_2.color = "green".to_string();
     starter_entities.push(big_boi_2);
-    let grav_tree = bigbang::GravTree::new(
+    let mut grav_tree = bigbang::GravTree::new(
         &mut starter_entities,
         TIME_STEP,
         MAX_ENTITIES,
         THETA,
         CalculateCollisions::No,
     );
+    // This example was tuned against the original force law.
+    grav_tree.set_force_law(bigbang::ForceLaw::Legacy);
 
     println!("initializing simulation...");
     {
@@ -24,8 +24,18 @@ impl AsEntity for Entity {
             vz: self.vz,
             radius: self.radius,
             mass: self.mass,
+            softening_length: 0.,
         }
     }
+
+    fn set_entity(&mut self, entity: &bigbang::Entity) {
+        self.vx = entity.vx;
+        self.vy = entity.vy;
+        self.vz = entity.vz;
+        self.x = entity.x;
+        self.y = entity.y;
+        self.z = entity.z;
+    }
 }
 
 impl Responsive for Entity {
@@ -35,7 +45,7 @@ impl Responsive for Entity {
         let (mut vx, mut vy, mut vz) = (self.vx, self.vy, self.vz);
         let self_mass = if self.radius < 1. { 0.5 } else { 105. };
         // calculate the collisions
-        for other in simulation_result.collisions.clone() {
+        for other in simulation_result.colliding_entities() {
             let other_mass = if other.radius < 1. { 0.5 } else { 105. };
             let mass_coefficient_v1 = (self_mass - other_mass) / (self_mass + other_mass);
             let mass_coefficient_v2 = (2f64 * other_mass) / (self_mass + other_mass);
@@ -80,6 +90,8 @@ fn main() {
         0.2,
         bigbang::CalculateCollisions::Yes,
     );
+    // This example was tuned against the original force law.
+    test_tree.set_force_law(bigbang::ForceLaw::Legacy);
 
     for i in 0..20 {
         println!("time step: {}", i);
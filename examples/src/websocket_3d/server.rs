@@ -54,7 +54,7 @@ impl Responsive for Entity {
             let mut ax = 0.;
             let mut ay = 0.;
             let mut az = 0.;
-            for other in &simulation_result.collisions {
+            for other in simulation_result.colliding_entities() {
                 let (collision_ax, collision_ay, collision_az) = soft_body(self, other, 200000f64);
                 ax += collision_ax;
                 ay += collision_ay;
@@ -267,6 +267,12 @@ async fn run(executor: TaskExecutor) {
         THETA,
         bigbang::CalculateCollisions::Yes,
     );
+    // This example was tuned against the original force law.
+    test_tree.set_force_law(bigbang::ForceLaw::Legacy);
+    // Soften close encounters so that passing particles are not flung out of the scene.
+    test_tree.set_softening(bigbang::Softening::Plummer(10.));
+    // The particles move fast enough to pass through each other between two time steps.
+    test_tree.set_collision_detection(bigbang::CollisionDetection::Continuous);
 
     loop {
         test_tree = test_tree.time_step();